@@ -0,0 +1,93 @@
+use atuin_ydoc_convert::{convert_to_json, convert_to_value, convert_to_writer, ReusableConverter};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+// A document shaped like a long BlockNote note: styled paragraphs, nested lists and tables
+fn generate_document(blocks: usize) -> String {
+    let mut xml = String::from("<blockgroup>");
+    for i in 0..blocks {
+        xml.push_str(&format!(
+            r#"<blockcontainer backgroundColor="default" id="block-{i}" textColor="default">"#
+        ));
+        match i % 4 {
+            0 => xml.push_str(&format!(
+                r#"<heading level="2" textAlignment="left">Section {i}</heading>"#
+            )),
+            1 => xml.push_str(
+                r#"<paragraph textAlignment="left">Some <bold>bold</bold> and <italic><code>styled</code></italic> text, with a <link href="https://atuin.sh">link</link>.</paragraph>"#,
+            ),
+            2 => {
+                xml.push_str(r#"<bulletListItem textAlignment="left">Parent item</bulletListItem><blockgroup>"#);
+                for j in 0..3 {
+                    xml.push_str(&format!(
+                        r#"<blockcontainer backgroundColor="default" id="block-{i}-{j}" textColor="default"><bulletListItem textAlignment="left">Child item {j}</bulletListItem></blockcontainer>"#
+                    ));
+                }
+                xml.push_str("</blockgroup>");
+            }
+            _ => {
+                xml.push_str(r#"<table textColor="default">"#);
+                for row in 0..3 {
+                    xml.push_str("<tableRow>");
+                    for col in 0..3 {
+                        xml.push_str(&format!(
+                            r#"<tableCell colspan="1" rowspan="1"><tableParagraph>Cell {row}, {col}</tableParagraph></tableCell>"#
+                        ));
+                    }
+                    xml.push_str("</tableRow>");
+                }
+                xml.push_str("</table>");
+            }
+        }
+        xml.push_str("</blockcontainer>");
+    }
+    xml.push_str("</blockgroup>");
+
+    xml
+}
+
+fn bench_convert(c: &mut Criterion) {
+    let xml = generate_document(10_000);
+
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Bytes(xml.len() as u64));
+    group.sample_size(20);
+
+    group.bench_function("to_value", |b| {
+        b.iter_batched(
+            || xml.clone(),
+            |xml| convert_to_value(xml).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    // what `convert_to_json` used to do, for comparison
+    group.bench_function("to_value_then_string", |b| {
+        b.iter_batched(
+            || xml.clone(),
+            |xml| serde_json::to_string(&convert_to_value(xml).unwrap()).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("to_json", |b| {
+        b.iter_batched(
+            || xml.clone(),
+            |xml| convert_to_json(xml).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("to_writer", |b| {
+        b.iter_batched(
+            || xml.clone(),
+            |xml| convert_to_writer(xml, std::io::sink()).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("reusable_to_json", |b| {
+        let mut converter = ReusableConverter::default();
+        b.iter(|| converter.to_json(&xml).unwrap().len())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_convert);
+criterion_main!(benches);
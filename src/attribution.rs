@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use serde_json::Value;
+use yrs::branch::{Branch, BranchID};
+use yrs::types::text::{Diff, YChange};
+use yrs::types::Attrs;
+use yrs::{
+    Any, Doc, Out, ReadTxn, Snapshot, Text, Transact, TransactionMut, Xml, XmlFragment,
+    XmlFragmentRef, XmlOut, XmlTextRef,
+};
+
+use crate::{
+    converter::{self, Error},
+    history, inspect,
+    options::ConvertOptions,
+    serialize::{self, Mark, XmlOptions},
+};
+
+/// The result of `convert_attributed`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribution {
+    /// The converted blocks, with each text run styled with the `author` that inserted it and
+    /// each block given the `author` that created it.
+    pub blocks: Value,
+    /// The number of characters of text each client inserted, by client id, written the same
+    /// way as in the `author` styles and fields of `blocks`.
+    pub characters: BTreeMap<String, usize>,
+}
+
+pub(crate) fn convert_attributed(
+    doc: &Doc,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<Attribution, Error> {
+    // Diffing text needs a write transaction, which splits items, so it's done in a copy of the
+    // document: attribute every text node up front and only then serialize the fragment, which
+    // needs a read transaction
+    let copy = history::doc_copy(doc, doc.offset_kind())?;
+    let mut texts = HashMap::new();
+    let mut authors = HashMap::new();
+    let mut characters = BTreeMap::new();
+    {
+        let mut txn = copy.transact_mut();
+        let fragment = inspect::find_fragment(&txn, fragment_name)?;
+        attribute_nodes(
+            &mut txn,
+            &fragment,
+            &mut texts,
+            &mut authors,
+            &mut characters,
+        );
+    }
+
+    let xml = {
+        let txn = copy.transact();
+        let fragment = inspect::find_fragment(&txn, fragment_name)?;
+        let mut marks = |text: &XmlTextRef| {
+            texts
+                .remove(&AsRef::<Branch>::as_ref(text).id())
+                .unwrap_or_default()
+        };
+
+        let mut xml = String::new();
+        serialize::write_fragment_marked(
+            fragment,
+            &txn,
+            &mut xml,
+            &XmlOptions::default(),
+            &mut marks,
+        )
+        .expect("writing to a String cannot fail");
+        xml
+    };
+
+    let mut blocks = converter::convert(&xml, options)?;
+    if let Value::Array(blocks) = &mut blocks {
+        mark_blocks(blocks, &authors);
+    }
+
+    Ok(Attribution { blocks, characters })
+}
+
+// Walks the fragment with an explicit stack, so that deeply nested documents can't overflow it
+fn attribute_nodes(
+    txn: &mut TransactionMut,
+    fragment: &XmlFragmentRef,
+    texts: &mut HashMap<BranchID, Vec<Diff<Mark>>>,
+    authors: &mut HashMap<String, String>,
+    characters: &mut BTreeMap<String, usize>,
+) {
+    // every item is missing from an empty snapshot, so the diff reports each one as added by
+    // the client that inserted it
+    let current = txn.snapshot();
+    let mut stack = fragment.children(txn).collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        match node {
+            XmlOut::Element(elem) => {
+                if elem.tag().as_ref() == "blockContainer" {
+                    if let (Some(id), BranchID::Nested(item)) = (
+                        elem.get_attribute(txn, "id"),
+                        AsRef::<Branch>::as_ref(&elem).id(),
+                    ) {
+                        authors.insert(id, item.client.to_string());
+                    }
+                }
+                stack.extend(elem.children(txn));
+            }
+            XmlOut::Fragment(fragment) => stack.extend(fragment.children(txn)),
+            XmlOut::Text(text) => {
+                let diffs = text.diff_range(
+                    txn,
+                    Some(&current),
+                    Some(&Snapshot::default()),
+                    YChange::identity,
+                );
+                let id = AsRef::<Branch>::as_ref(&text).id();
+                texts.insert(id, author_runs(diffs, characters));
+            }
+        }
+    }
+}
+
+// A run of text being joined up, kept in a String so that adding to it is cheap
+struct TextRun {
+    author: Option<String>,
+    attributes: Option<Box<Attrs>>,
+    text: String,
+}
+
+impl TextRun {
+    fn into_diff(self) -> Diff<Mark> {
+        Diff {
+            insert: Out::Any(Any::from(self.text)),
+            attributes: self.attributes,
+            ychange: self.author.map(author_mark),
+        }
+    }
+}
+
+// The diff has a run per item, so join up neighbouring runs by the same client with the same
+// formatting, and count the characters each client inserted
+fn author_runs(
+    diffs: Vec<Diff<YChange>>,
+    characters: &mut BTreeMap<String, usize>,
+) -> Vec<Diff<Mark>> {
+    let mut runs = Vec::new();
+    let mut pending: Option<TextRun> = None;
+    for diff in diffs {
+        let author = diff.ychange.map(|change| change.id.client.to_string());
+        let Out::Any(Any::String(s)) = diff.insert else {
+            runs.extend(pending.take().map(TextRun::into_diff));
+            runs.push(Diff {
+                insert: diff.insert,
+                attributes: diff.attributes,
+                ychange: author.map(author_mark),
+            });
+            continue;
+        };
+
+        if let Some(author) = &author {
+            *characters.entry(author.clone()).or_default() += s.chars().count();
+        }
+        match &mut pending {
+            Some(run) if run.author == author && run.attributes == diff.attributes => {
+                run.text.push_str(&s)
+            }
+            _ => {
+                runs.extend(pending.take().map(TextRun::into_diff));
+                pending = Some(TextRun {
+                    author,
+                    attributes: diff.attributes,
+                    text: s.to_string(),
+                });
+            }
+        }
+    }
+    runs.extend(pending.map(TextRun::into_diff));
+
+    runs
+}
+
+fn author_mark(author: String) -> Mark {
+    let attributes = HashMap::from([("stringValue".to_string(), Any::from(author))]);
+    ("author".into(), Any::Map(Arc::new(attributes)))
+}
+
+fn mark_blocks(blocks: &mut [Value], authors: &HashMap<String, String>) {
+    for block in blocks {
+        let author = block
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(|id| authors.get(id));
+        if let Some(author) = author {
+            block["author"] = author.clone().into();
+        }
+        if let Some(Value::Array(children)) = block.get_mut("children") {
+            mark_blocks(children, authors);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, Transact, Update};
+
+use crate::{
+    converter::Error, options::ConvertOptions, reusable::ReusableConverter, serialize,
+    serialize::XmlOptions,
+};
+
+/// A document to convert in a batch.
+#[derive(Debug, Clone)]
+pub enum BatchInput {
+    /// XML as produced by `get_fragment_xml`.
+    Xml(String),
+    /// A Yjs update (v1 encoding) containing the whole document.
+    Update(Vec<u8>),
+}
+
+impl From<String> for BatchInput {
+    fn from(xml: String) -> Self {
+        BatchInput::Xml(xml)
+    }
+}
+
+impl From<Vec<u8>> for BatchInput {
+    fn from(update: Vec<u8>) -> Self {
+        BatchInput::Update(update)
+    }
+}
+
+impl BatchInput {
+    fn len(&self) -> usize {
+        match self {
+            BatchInput::Xml(xml) => xml.len(),
+            BatchInput::Update(update) => update.len(),
+        }
+    }
+}
+
+/// Options for `convert_batch`.
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    threads: usize,
+    fragment_name: String,
+    convert: ConvertOptions,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            fragment_name: "document-store".to_string(),
+            convert: ConvertOptions::default(),
+        }
+    }
+}
+
+impl BatchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of worker threads. Defaults to the available parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// The name of the fragment to convert in `BatchInput::Update` documents. Defaults to
+    /// `"document-store"`, BlockNote's default.
+    pub fn fragment_name(mut self, fragment_name: impl Into<String>) -> Self {
+        self.fragment_name = fragment_name.into();
+        self
+    }
+
+    pub fn convert(mut self, convert: ConvertOptions) -> Self {
+        self.convert = convert;
+        self
+    }
+}
+
+/// Aggregate statistics for a batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub documents: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub input_bytes: usize,
+    /// The total length of the JSON produced for the documents that succeeded.
+    pub output_bytes: usize,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub struct BatchResult {
+    /// The JSON for each document, or why it failed, in the same order as the inputs.
+    pub results: Vec<Result<String, Error>>,
+    pub stats: BatchStats,
+}
+
+pub(crate) fn convert_batch<I>(inputs: I, options: &BatchOptions) -> BatchResult
+where
+    I: IntoIterator,
+    I::Item: Into<BatchInput>,
+{
+    let start = Instant::now();
+    let inputs = inputs.into_iter().map(Into::into);
+    let workers = match inputs.size_hint() {
+        (_, Some(upper)) => options.threads.min(upper),
+        (_, None) => options.threads,
+    }
+    .max(1);
+
+    // documents are handed out through a bounded channel, so only a few are held beyond the
+    // ones being converted, and each worker takes the next one as soon as it's free, so a few
+    // large documents don't hold up the rest of the batch
+    let (sender, receiver) = mpsc::sync_channel::<(usize, BatchInput)>(workers * 2);
+    let receiver = Mutex::new(receiver);
+    let (mut results, input_bytes) = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut converter = ReusableConverter::new(options.convert.clone());
+                    let mut results = Vec::new();
+                    loop {
+                        let next = receiver.lock().unwrap().recv();
+                        let Ok((i, input)) = next else {
+                            break;
+                        };
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            convert_input(&mut converter, &input, options)
+                        }));
+                        let result = result.unwrap_or_else(|payload| {
+                            // the converter may have been left half way through the document
+                            converter = ReusableConverter::new(options.convert.clone());
+                            Err(Error::Panicked(panic_message(payload)))
+                        });
+                        results.push((i, result));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut input_bytes = 0;
+        for (i, input) in inputs.enumerate() {
+            input_bytes += input.len();
+            sender
+                .send((i, input))
+                .expect("workers take documents until the channel is closed");
+        }
+        drop(sender);
+
+        let results = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("panics are caught for each document"))
+            .collect::<Vec<_>>();
+        (results, input_bytes)
+    });
+    results.sort_by_key(|(i, _)| *i);
+    let results = results
+        .into_iter()
+        .map(|(_, result)| result)
+        .collect::<Vec<_>>();
+
+    let succeeded = results.iter().filter(|result| result.is_ok()).count();
+    let stats = BatchStats {
+        documents: results.len(),
+        succeeded,
+        failed: results.len() - succeeded,
+        input_bytes,
+        output_bytes: results.iter().flatten().map(String::len).sum(),
+        elapsed: start.elapsed(),
+    };
+
+    BatchResult { results, stats }
+}
+
+fn convert_input(
+    converter: &mut ReusableConverter,
+    input: &BatchInput,
+    options: &BatchOptions,
+) -> Result<String, Error> {
+    match input {
+        BatchInput::Xml(xml) => converter.to_json(xml).map(str::to_string),
+        BatchInput::Update(update) => {
+            let update =
+                Update::decode_v1(update).map_err(|e| Error::InvalidUpdate(e.to_string()))?;
+            let doc = Doc::new();
+            doc.transact_mut()
+                .apply_update(update)
+                .map_err(|e| Error::InvalidUpdate(e.to_string()))?;
+
+            let xml = serialize::try_get_fragment_xml(
+                &doc,
+                &options.fragment_name,
+                &XmlOptions::default(),
+            )?;
+            converter.to_json(&xml).map(str::to_string)
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+use atuin_ydoc_convert::{
+    convert_document, convert_to_json_pretty_with, convert_to_json_with, convert_to_value_with,
+    get_fragment_xml_ref, set_fragment_blocks, try_get_fragment_xml, BlockNoteVersion,
+    ConvertOptions, DocumentOptions, Limits, XmlOptions,
+};
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, Transact, Update};
+
+const USAGE: &str = "\
+Usage: ydoc-convert [OPTIONS] [FILE]
+
+Converts a BlockNote document stored as XML, a Yjs update or BlockNote JSON to JSON or XML.
+Reads FILE, or stdin if FILE is missing or `-`.
+
+Options:
+  -f, --from <FORMAT>       Input format: xml, update or json [default: guessed from the input]
+  -t, --to <FORMAT>         Output format: json, pretty or xml [default: json]
+  -n, --fragment <NAME>     The Yjs XML fragment holding the document [default: document-store]
+  -m, --meta <NAME>         Output { meta, blocks }, with the named Y.Map or Y.Text under meta.
+                            Can be repeated. Needs a Yjs update and JSON output
+  -o, --output <FILE>       Write to FILE instead of stdout
+
+JSON output:
+      --omit-empty-content  Leave out `content` on blocks that can't have inline content
+      --omit-empty-children Leave out `children` on blocks with no nested blocks
+      --drop-default-props  Leave out props that have BlockNote's default value
+      --partial-blocks      Produce BlockNote's PartialBlock form
+      --blocknote <VERSION> BlockNote JSON format to produce: 0.22 or 0.23 [default: 0.22]
+      --max-depth <N>       Limits enforced while converting
+      --max-input-bytes <N>
+      --max-blocks <N>
+      --max-inline-items <N>
+      --max-table-cells <N>
+
+XML output:
+      --canonical           Sort attributes and formatting marks by name
+      --indent <N>          Indent block-level elements by N spaces per level
+
+  -h, --help                Print this help
+";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Input {
+    Xml,
+    Update,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Output {
+    Json,
+    Pretty,
+    Xml,
+}
+
+struct Args {
+    file: Option<String>,
+    output_file: Option<String>,
+    from: Option<Input>,
+    to: Output,
+    fragment_name: String,
+    meta: Vec<String>,
+    convert: ConvertOptions,
+    xml: XmlOptions,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("ydoc-convert: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ydoc-convert: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut parsed = Args {
+        file: None,
+        output_file: None,
+        from: None,
+        to: Output::Json,
+        fragment_name: "document-store".to_string(),
+        meta: Vec::new(),
+        convert: ConvertOptions::new(),
+        xml: XmlOptions::new(),
+    };
+    let mut limits = Limits::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        let number = |name: &str, value: String| {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("{} needs a number, not {:?}", name, value))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-f" | "--from" => {
+                parsed.from = Some(match value(&arg)?.as_str() {
+                    "xml" => Input::Xml,
+                    "update" => Input::Update,
+                    "json" => Input::Json,
+                    other => return Err(format!("unknown input format {:?}", other)),
+                })
+            }
+            "-t" | "--to" => {
+                parsed.to = match value(&arg)?.as_str() {
+                    "json" => Output::Json,
+                    "pretty" => Output::Pretty,
+                    "xml" => Output::Xml,
+                    other => return Err(format!("unknown output format {:?}", other)),
+                }
+            }
+            "-n" | "--fragment" => parsed.fragment_name = value(&arg)?,
+            "-m" | "--meta" => parsed.meta.push(value(&arg)?),
+            "-o" | "--output" => parsed.output_file = Some(value(&arg)?),
+            "--omit-empty-content" => parsed.convert = parsed.convert.omit_empty_content(true),
+            "--omit-empty-children" => parsed.convert = parsed.convert.omit_empty_children(true),
+            "--drop-default-props" => parsed.convert = parsed.convert.drop_default_props(true),
+            "--partial-blocks" => parsed.convert = parsed.convert.partial_blocks(true),
+            "--blocknote" => {
+                parsed.convert = parsed.convert.version(match value(&arg)?.as_str() {
+                    "0.22" => BlockNoteVersion::V0_22,
+                    "0.23" => BlockNoteVersion::V0_23,
+                    other => return Err(format!("unknown BlockNote version {:?}", other)),
+                })
+            }
+            "--max-depth" => limits = limits.max_depth(number(&arg, value(&arg)?)?),
+            "--max-input-bytes" => limits = limits.max_input_bytes(number(&arg, value(&arg)?)?),
+            "--max-blocks" => limits = limits.max_blocks(number(&arg, value(&arg)?)?),
+            "--max-inline-items" => limits = limits.max_inline_items(number(&arg, value(&arg)?)?),
+            "--max-table-cells" => limits = limits.max_table_cells(number(&arg, value(&arg)?)?),
+            "--canonical" => parsed.xml = parsed.xml.canonical(true),
+            "--indent" => parsed.xml = parsed.xml.indent(number(&arg, value(&arg)?)?),
+            "-" => parsed.file = None,
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            file => {
+                if parsed.file.is_some() {
+                    return Err("only one input file can be given".to_string());
+                }
+                parsed.file = Some(file.to_string());
+            }
+        }
+    }
+    parsed.convert = parsed.convert.limits(limits);
+
+    Ok(Some(parsed))
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let input = match &args.file {
+        Some(file) => fs::read(file).map_err(|e| format!("{}: {}", file, e))?,
+        None => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            input
+        }
+    };
+    let from = args.from.unwrap_or_else(|| guess_format(&input));
+
+    if !args.meta.is_empty() {
+        if from != Input::Update || args.to == Output::Xml {
+            return Err("--meta needs a Yjs update as input and JSON output".into());
+        }
+        return write_output(convert_document_with_meta(&input, args)?, args);
+    }
+
+    // Updates are serialized straight from the document. XML and JSON go through the
+    // converter, so XML output for them is rebuilt from the converted blocks.
+    let xml = match from {
+        Input::Update => {
+            let doc = Doc::new();
+            doc.transact_mut()
+                .apply_update(Update::decode_v1(&input)?)?;
+            try_get_fragment_xml(&doc, &args.fragment_name, &args.xml)?
+        }
+        Input::Xml if args.to != Output::Xml => String::from_utf8(input)?,
+        Input::Xml => {
+            let blocks = convert_to_value_with(String::from_utf8(input)?, &args.convert)?;
+            blocks_to_xml(&blocks, args)?
+        }
+        Input::Json => blocks_to_xml(&serde_json::from_slice(&input)?, args)?,
+    };
+
+    let output = match args.to {
+        Output::Json => convert_to_json_with(xml, &args.convert)?,
+        Output::Pretty => convert_to_json_pretty_with(xml, &args.convert)?,
+        Output::Xml => xml,
+    };
+    write_output(output, args)
+}
+
+fn write_output(mut output: String, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    output.push('\n');
+
+    match &args.output_file {
+        Some(file) => fs::write(file, output).map_err(|e| format!("{}: {}", file, e))?,
+        None => io::stdout().write_all(output.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+fn convert_document_with_meta(
+    update: &[u8],
+    args: &Args,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let doc = Doc::new();
+    doc.transact_mut()
+        .apply_update(Update::decode_v1(update)?)?;
+
+    let options = args.meta.iter().fold(
+        DocumentOptions::new()
+            .fragment_name(args.fragment_name.clone())
+            .convert(args.convert.clone()),
+        |options, name| options.meta(name.clone()),
+    );
+    let document = convert_document(&doc, &options)?;
+    Ok(match args.to {
+        Output::Pretty => serde_json::to_string_pretty(&document)?,
+        _ => serde_json::to_string(&document)?,
+    })
+}
+
+fn blocks_to_xml(
+    blocks: &serde_json::Value,
+    args: &Args,
+) -> Result<String, atuin_ydoc_convert::Error> {
+    let doc = Doc::new();
+    set_fragment_blocks(&doc, &args.fragment_name, blocks)?;
+    Ok(get_fragment_xml_ref(&doc, &args.fragment_name, &args.xml))
+}
+
+fn guess_format(input: &[u8]) -> Input {
+    match input.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'<') => Input::Xml,
+        Some(b'[') | Some(b'{') => Input::Json,
+        _ => Input::Update,
+    }
+}
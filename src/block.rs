@@ -1,7 +1,35 @@
-use roxmltree::Attributes;
+use serde::ser::{Serialize, SerializeMap, Serializer};
 use serde_json::{json, Value};
 
-use crate::content::Content;
+use crate::{content::Content, encoding, options::WithOptions};
+
+// Built-in blocks that have no inline content
+pub(crate) const CONTENTLESS_BLOCKS: &[&str] = &["image", "video", "audio", "file"];
+
+// BlockNote's default value for a prop of a built-in block, as it's written in the XML
+fn default_prop(type_name: &str, name: &str) -> Option<&'static str> {
+    match (type_name, name) {
+        (_, "textColor") | (_, "backgroundColor") => Some("default"),
+        (_, "textAlignment") => Some("left"),
+        ("heading", "level") => Some("1"),
+        ("heading", "isToggleable") | ("checkListItem", "checked") => Some("false"),
+        ("codeBlock", "language") => Some("text"),
+        ("image", "url" | "caption" | "name")
+        | ("video", "url" | "caption" | "name")
+        | ("audio", "url" | "caption" | "name")
+        | ("file", "url" | "caption" | "name") => Some(""),
+        ("image", "showPreview") | ("video", "showPreview") | ("audio", "showPreview") => {
+            Some("true")
+        }
+        _ => None,
+    }
+}
+
+// Props that aren't coerced to numbers or booleans are still strings, so compare as strings
+fn is_default_prop(type_name: &str, name: &str, value: &Value) -> bool {
+    default_prop(type_name, name)
+        .is_some_and(|default| encoding::into_string(value.clone()) == default)
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct Block {
@@ -23,11 +51,11 @@ impl Block {
         }
     }
 
-    pub fn apply_attributes(&mut self, attributes: Attributes) {
-        for attr in attributes {
-            match attr.name() {
+    pub fn apply_attributes(&mut self, attributes: Vec<(String, Value)>) {
+        for (name, value) in attributes {
+            match name.as_str() {
                 "id" => {
-                    self.id = attr.value().to_string();
+                    self.id = encoding::into_string(value);
                 }
                 name => {
                     // Handle type conversion for built-in block types
@@ -36,7 +64,12 @@ impl Block {
                         ("heading", "level")
                         | ("image", "previewWidth")
                         | ("video", "previewWidth") => {
-                            if let Ok(value) = attr.value().parse::<u64>() {
+                            let value = match value {
+                                Value::Number(n) => n.as_u64(),
+                                Value::String(s) => s.parse::<u64>().ok(),
+                                _ => None,
+                            };
+                            if let Some(value) = value {
                                 self.props.insert(name.to_string(), json!(value));
                             }
                         }
@@ -44,7 +77,12 @@ impl Block {
                         | ("image", "showPreview")
                         | ("audio", "showPreview")
                         | ("video", "showPreview") => {
-                            if let Ok(value) = attr.value().parse::<bool>() {
+                            let value = match value {
+                                Value::Bool(b) => Some(b),
+                                Value::String(s) => s.parse::<bool>().ok(),
+                                _ => None,
+                            };
+                            if let Some(value) = value {
                                 self.props.insert(name.to_string(), json!(value));
                             }
                         }
@@ -54,7 +92,7 @@ impl Block {
                             // do not insert
                         }
                         _ => {
-                            self.props.insert(name.to_string(), json!(attr.value()));
+                            self.props.insert(name.to_string(), value);
                         }
                     }
                 }
@@ -62,29 +100,84 @@ impl Block {
         }
     }
 
-    pub fn to_json(&self) -> Value {
-        let mut map = serde_json::Map::new();
-        map.insert("id".to_string(), json!(self.id));
-        let type_name = match self.type_name.as_str() {
+    fn json_type_name(&self) -> &str {
+        match self.type_name.as_str() {
             "bulletlistitem" => "bulletListItem",
             "checklistitem" => "checkListItem",
             "numberedlistitem" => "numberedListItem",
             _ => &self.type_name,
+        }
+    }
+}
+
+impl Serialize for WithOptions<'_, Block> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (block, options) = (self.value, self.options);
+        let type_name = block.json_type_name();
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("id", &block.id)?;
+        map.serialize_entry("type", type_name)?;
+
+        let props = Props {
+            props: &block.props,
+            type_name,
+            drop_defaults: options.drop_default_props || options.partial_blocks,
         };
-        map.insert("type".to_string(), json!(type_name));
-        map.insert("props".to_string(), Value::Object(self.props.clone()));
-        if let Some(content) = &self.content {
-            map.insert("content".to_string(), json!(content.to_json()));
-        } else {
-            map.insert("content".to_string(), json!([]));
+        if !(props.is_empty() && options.partial_blocks) {
+            map.serialize_entry("props", &props)?;
         }
-        if !self.children.is_empty() {
-            let children: Vec<Value> = self.children.iter().map(|child| child.to_json()).collect();
-            map.insert("children".to_string(), json!(children));
-        } else {
-            map.insert("children".to_string(), json!([]));
+
+        let content_is_empty = match &block.content {
+            Some(Content::Basic(content)) => content.is_empty(),
+            Some(Content::Table(_)) => false,
+            None => true,
+        };
+        let omit_content = content_is_empty
+            && (options.partial_blocks
+                || (options.omit_empty_content && CONTENTLESS_BLOCKS.contains(&type_name)));
+        if !omit_content {
+            match &block.content {
+                Some(content) => map.serialize_entry("content", &options.apply(content))?,
+                None => map.serialize_entry("content", &Vec::<Value>::new())?,
+            }
         }
 
-        Value::Object(map)
+        if !block.children.is_empty() || !(options.omit_empty_children || options.partial_blocks) {
+            map.serialize_entry("children", &options.apply(block.children.as_slice()))?;
+        }
+
+        map.end()
+    }
+}
+
+impl Serialize for WithOptions<'_, [Block]> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.value.iter().map(|block| self.options.apply(block)))
+    }
+}
+
+// A block's props, optionally without the ones that have their default value
+struct Props<'a> {
+    props: &'a serde_json::Map<String, Value>,
+    type_name: &'a str,
+    drop_defaults: bool,
+}
+
+impl Props<'_> {
+    fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.props.iter().filter(|(name, value)| {
+            !(self.drop_defaults && is_default_prop(self.type_name, name, value))
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+impl Serialize for Props<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
     }
 }
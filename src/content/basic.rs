@@ -1,7 +1,8 @@
-use roxmltree::Attributes;
-use serde_json::{json, Value};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::Value;
 
 use super::style::Style;
+use crate::options::WithOptions;
 
 #[derive(Clone, Debug)]
 pub(crate) struct BasicContent {
@@ -21,40 +22,67 @@ impl BasicContent {
         }
     }
 
-    pub fn apply_attributes(&mut self, attributes: Attributes) {
-        for attr in attributes {
-            self.props
-                .insert(attr.name().to_string(), json!(attr.value()));
+    pub fn apply_attributes(&mut self, attributes: Vec<(String, Value)>) {
+        for (name, value) in attributes {
+            self.props.insert(name, value);
         }
     }
+}
 
-    pub fn to_json(&self) -> Value {
-        let mut map = serde_json::Map::new();
-        map.insert("type".to_string(), json!(self.type_name));
-        let mut styles = serde_json::Map::new();
-        for style in self.styles.iter() {
-            match style {
-                Style::TextColor(color) => {
-                    styles.insert("textColor".to_string(), json!(color));
-                }
-                Style::BackgroundColor(color) => {
-                    styles.insert("backgroundColor".to_string(), json!(color));
-                }
-                _ => {
-                    styles.insert(style.name().to_string(), json!(true));
-                }
+impl Serialize for WithOptions<'_, BasicContent> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (content, options) = (self.value, self.options);
+
+        // props can override the type and styles, and the content can override props
+        let mut map = serializer.serialize_map(None)?;
+        if !content.props.contains_key("type") {
+            map.serialize_entry("type", &content.type_name)?;
+        }
+        let omit_styles = content.styles.is_empty() && options.partial_blocks;
+        if !omit_styles && !content.props.contains_key("styles") {
+            map.serialize_entry("styles", &Styles(&content.styles))?;
+        }
+        for (name, value) in content.props.iter() {
+            if !(name == "content" && content.content.is_some()) {
+                map.serialize_entry(name, value)?;
             }
         }
-        map.insert("styles".to_string(), Value::Object(styles));
-        for prop in self.props.iter() {
-            map.insert(prop.0.clone(), prop.1.clone());
+        if let Some(children) = &content.content {
+            map.serialize_entry("content", &options.apply(children.as_slice()))?;
         }
-        if let Some(content) = &self.content {
-            map.insert(
-                "content".to_string(),
-                json!(content.iter().map(|c| c.to_json()).collect::<Vec<Value>>()),
-            );
+
+        map.end()
+    }
+}
+
+impl Serialize for WithOptions<'_, [BasicContent]> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.value.iter().map(|content| self.options.apply(content)))
+    }
+}
+
+struct Styles<'a>(&'a [Style]);
+
+impl Serialize for Styles<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for (i, style) in self.0.iter().enumerate() {
+            // where a style is nested in itself, the innermost one applies
+            if self.0[i + 1..]
+                .iter()
+                .any(|inner| inner.name() == style.name())
+            {
+                continue;
+            }
+
+            match style {
+                Style::TextColor(value) | Style::BackgroundColor(value) | Style::Author(value) => {
+                    map.serialize_entry(style.name(), value)?
+                }
+                _ => map.serialize_entry(style.name(), &true)?,
+            }
         }
-        Value::Object(map)
+
+        map.end()
     }
 }
@@ -1,7 +1,9 @@
 use basic::BasicContent;
-use serde_json::{json, Value};
+use serde::{Serialize, Serializer};
 use table::TableContent;
 
+use crate::options::WithOptions;
+
 pub(crate) mod basic;
 pub(crate) mod style;
 pub(crate) mod table;
@@ -12,13 +14,11 @@ pub(crate) enum Content {
     Table(TableContent),
 }
 
-impl Content {
-    pub fn to_json(&self) -> Value {
-        match self {
-            Content::Basic(content) => {
-                json!(content.iter().map(|c| c.to_json()).collect::<Vec<Value>>())
-            }
-            Content::Table(content) => content.to_json(),
+impl Serialize for WithOptions<'_, Content> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Content::Basic(content) => self.options.apply(content.as_slice()).serialize(serializer),
+            Content::Table(content) => self.options.apply(content).serialize(serializer),
         }
     }
 }
@@ -7,6 +7,9 @@ pub(crate) enum Style {
     Code,
     TextColor(String),
     BackgroundColor(String),
+    Insertion,
+    Deletion,
+    Author(String),
 }
 
 impl Style {
@@ -19,6 +22,9 @@ impl Style {
             Style::Code => "code",
             Style::TextColor(_) => "textColor",
             Style::BackgroundColor(_) => "backgroundColor",
+            Style::Insertion => "insertion",
+            Style::Deletion => "deletion",
+            Style::Author(_) => "author",
         }
     }
 }
@@ -33,6 +39,8 @@ impl TryFrom<&str> for Style {
             "underline" => Ok(Style::Underline),
             "strike" => Ok(Style::Strike),
             "code" => Ok(Style::Code),
+            "insertion" => Ok(Style::Insertion),
+            "deletion" => Ok(Style::Deletion),
             _ => Err(format!("Cannot create Style from {}", s)),
         }
     }
@@ -1,6 +1,8 @@
+use serde::ser::{Serialize, SerializeMap, Serializer};
 use serde_json::{json, Value};
 
 use super::basic::BasicContent;
+use crate::options::{BlockNoteVersion, WithOptions};
 
 pub(crate) type TableRow = Vec<TableCell>;
 
@@ -10,6 +12,7 @@ pub(crate) struct TableCell {
     pub colspan: u32,
     pub rowspan: u32,
     pub colwidth: Option<u32>,
+    pub props: serde_json::Map<String, Value>,
 }
 
 impl TableCell {
@@ -19,10 +22,64 @@ impl TableCell {
             colspan: 1,
             rowspan: 1,
             colwidth: None,
+            props: serde_json::Map::new(),
         }
     }
 }
 
+impl Serialize for WithOptions<'_, TableCell> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (cell, options) = (self.value, self.options);
+        let content = options.apply(cell.content.as_slice());
+        if options.version == BlockNoteVersion::V0_22 {
+            return content.serialize(serializer);
+        }
+
+        let mut props = serde_json::Map::new();
+        for (name, default) in [
+            ("backgroundColor", "default"),
+            ("textColor", "default"),
+            ("textAlignment", "left"),
+        ] {
+            props.insert(name.to_string(), json!(default));
+        }
+        props.extend(cell.props.clone());
+        props.insert("colspan".to_string(), json!(cell.colspan));
+        props.insert("rowspan".to_string(), json!(cell.rowspan));
+        if options.drop_default_props || options.partial_blocks {
+            props.retain(|name, value| match name.as_str() {
+                "backgroundColor" | "textColor" => value != "default",
+                "textAlignment" => value != "left",
+                "colspan" | "rowspan" => value != 1,
+                _ => true,
+            });
+        }
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("type", "tableCell")?;
+        if !(props.is_empty() && options.partial_blocks) {
+            map.serialize_entry("props", &props)?;
+        }
+        map.serialize_entry("content", &content)?;
+        map.end()
+    }
+}
+
+impl Serialize for WithOptions<'_, [TableCell]> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(
+            "cells",
+            &self
+                .value
+                .iter()
+                .map(|cell| self.options.apply(cell))
+                .collect::<Vec<_>>(),
+        )?;
+        map.end()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct TableContent(Vec<TableRow>);
 
@@ -31,37 +88,33 @@ impl TableContent {
         TableContent(rows)
     }
 
-    pub fn to_json(&self) -> Value {
-        let column_widths = self
-            .0.first()
+    pub fn rows(&self) -> &[TableRow] {
+        let TableContent(rows) = self;
+        rows
+    }
+
+    fn column_widths(&self) -> Vec<Option<u32>> {
+        let TableContent(rows) = self;
+        rows.first()
             .map(|row| row.iter().map(|cell| cell.colwidth).collect::<Vec<_>>())
-            .unwrap_or_default();
+            .unwrap_or_default()
+    }
+}
+
+impl Serialize for WithOptions<'_, TableContent> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let rows = &self.value.0;
 
-        let mut map = serde_json::Map::new();
-        map.insert("type".to_string(), json!("tableContent"));
-        map.insert("columnWidths".to_string(), json!(column_widths));
-        map.insert(
-            "rows".to_string(),
-            json!(self
-                .0
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("type", "tableContent")?;
+        map.serialize_entry("columnWidths", &self.value.column_widths())?;
+        map.serialize_entry(
+            "rows",
+            &rows
                 .iter()
-                .map(|row| {
-                    let cells = row
-                        .iter()
-                        .map(|cell| {
-                            json!(cell
-                                .content
-                                .iter()
-                                .map(|content| content.to_json())
-                                .collect::<Vec<Value>>())
-                        })
-                        .collect::<Vec<_>>();
-                    let mut map = serde_json::Map::new();
-                    map.insert("cells".to_string(), json!(cells));
-                    Value::Object(map)
-                })
-                .collect::<Vec<_>>()),
-        );
-        Value::Object(map)
+                .map(|row| self.options.apply(row.as_slice()))
+                .collect::<Vec<_>>(),
+        )?;
+        map.end()
     }
 }
@@ -1,5 +1,7 @@
+use std::io;
+use std::sync::Arc;
+
 use roxmltree::{Document, Node, NodeType};
-use serde_json::Value;
 
 use crate::{
     block::Block,
@@ -9,12 +11,94 @@ use crate::{
         table::{TableCell, TableContent, TableRow},
         Content,
     },
+    diagnostic::{Diagnostic, Severity},
+    encoding,
+    inspect::SharedTypeKind,
+    limits::{self, LimitKind, Limits},
+    merge::Conflict,
+    options::ConvertOptions,
 };
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Error {
     ParseError(roxmltree::Error),
-    MalformedDocument(String, roxmltree::TextPos),
+    MalformedDocument {
+        kind: ErrorKind,
+        pos: roxmltree::TextPos,
+        /// The ids of the blocks enclosing the malformed node, from the root down.
+        block_path: Vec<String>,
+    },
+    InvalidDocument(Vec<Diagnostic>),
+    DepthLimitExceeded {
+        max_depth: usize,
+        pos: roxmltree::TextPos,
+    },
+    InputTooLarge {
+        max_bytes: usize,
+        len: usize,
+    },
+    LimitExceeded {
+        limit: LimitKind,
+        max: usize,
+        pos: roxmltree::TextPos,
+        /// The ids of the blocks enclosing the node that exceeded the limit, from the root down.
+        block_path: Vec<String>,
+    },
+    Io(Arc<io::Error>),
+    InvalidUtf8(std::str::Utf8Error),
+    InvalidUpdate(String),
+    InvalidBlocks(String),
+    FragmentNotFound {
+        name: String,
+    },
+    NotAFragment {
+        name: String,
+        kind: SharedTypeKind,
+    },
+    SnapshotUnavailable,
+    MergeConflict(Vec<Conflict>),
+    BlockNotFound {
+        id: String,
+    },
+    InvalidPosition(String),
+    InvalidEdit(String),
+    Panicked(String),
+}
+
+/// The ways in which a document can be malformed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A blockcontainer with no block inside it.
+    EmptyBlockContainer,
+    /// A style tag (e.g. `<bold>`) with no content.
+    EmptyStyleTag,
+    /// A style tag with more than one child node.
+    MultiChildStyleTag,
+    /// A style tag that isn't a known style.
+    UnknownStyle(String),
+    /// A table cell `colspan`, `rowspan` or `colwidth` attribute that isn't a number; holds the
+    /// attribute name.
+    InvalidSpan(String),
+    /// A table cell without a tableParagraph.
+    MissingTableParagraph,
+    /// A node other than an element or text (e.g. a comment) in inline content.
+    UnsupportedNode,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyBlockContainer => write!(f, "blockcontainer with no children"),
+            Self::EmptyStyleTag => write!(f, "style tag with no children"),
+            Self::MultiChildStyleTag => write!(f, "style tag with more than one child"),
+            Self::UnknownStyle(style) => write!(f, "Cannot create Style from {}", style),
+            Self::InvalidSpan(attribute) => write!(f, "Invalid {}", attribute),
+            Self::MissingTableParagraph => write!(f, "table cell with no table paragraph"),
+            Self::UnsupportedNode => write!(f, "Unsupported node type in content position"),
+        }
+    }
 }
 
 impl std::error::Error for Error {}
@@ -23,239 +107,507 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::ParseError(e) => write!(f, "Parse error: {}", e),
-            Self::MalformedDocument(message, pos) => {
+            Self::MalformedDocument {
+                kind,
+                pos,
+                block_path,
+            } => {
                 write!(
                     f,
                     "Malformed document: {} at line {} column {}",
-                    message, pos.row, pos.col
-                )
+                    kind, pos.row, pos.col
+                )?;
+                if let Some(block_id) = block_path.last() {
+                    write!(f, " in block {}", block_id)?;
+                }
+                Ok(())
+            }
+            Self::InvalidDocument(violations) => {
+                write!(f, "Invalid document: {} violation(s)", violations.len())?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            }
+            Self::DepthLimitExceeded { max_depth, pos } => write!(
+                f,
+                "Document is nested more than {} elements deep at line {} column {}",
+                max_depth, pos.row, pos.col
+            ),
+            Self::InputTooLarge { max_bytes, len } => write!(
+                f,
+                "Document is {} bytes, more than the maximum of {}",
+                len, max_bytes
+            ),
+            Self::LimitExceeded {
+                limit, max, pos, ..
+            } => write!(
+                f,
+                "Document has more than {} {} at line {} column {}",
+                max, limit, pos.row, pos.col
+            ),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::InvalidUtf8(e) => write!(f, "Invalid UTF-8: {}", e),
+            Self::InvalidUpdate(e) => write!(f, "Invalid Yjs update: {}", e),
+            Self::InvalidBlocks(e) => write!(f, "Invalid BlockNote JSON: {}", e),
+            Self::FragmentNotFound { name } => {
+                write!(f, "Document has no shared type named {}", name)
+            }
+            Self::NotAFragment { name, kind } => {
+                write!(f, "Shared type {} is a {}, not an XmlFragment", name, kind)
             }
+            Self::SnapshotUnavailable => write!(
+                f,
+                "Snapshots need a document with garbage collection disabled (skip_gc)"
+            ),
+            Self::MergeConflict(conflicts) => {
+                write!(f, "Merge has {} conflicting changes", conflicts.len())
+            }
+            Self::BlockNotFound { id } => write!(f, "Fragment has no block with id {}", id),
+            Self::InvalidPosition(e) => write!(f, "Invalid position: {}", e),
+            Self::InvalidEdit(e) => write!(f, "Invalid edit: {}", e),
+            Self::Panicked(e) => write!(f, "Conversion panicked: {}", e),
         }
     }
 }
 
-pub(crate) fn convert(xml: String) -> Result<serde_json::Value, Error> {
-    let doc = Document::parse(&xml).map_err(Error::ParseError)?;
-    let blockgroup = doc.root_element();
+pub(crate) fn convert(xml: &str, options: &ConvertOptions) -> Result<serde_json::Value, Error> {
+    let blocks = Converter::new(false, options.limits.clone()).convert(xml)?;
+
+    Ok(to_value(&blocks, options))
+}
+
+pub(crate) fn convert_to_writer<W: io::Write>(
+    xml: &str,
+    writer: W,
+    options: &ConvertOptions,
+    pretty: bool,
+) -> Result<(), Error> {
+    let blocks = Converter::new(false, options.limits.clone()).convert(xml)?;
+
+    write_json(&blocks, writer, options, pretty)
+}
 
-    let blocks = convert_blockgroup(blockgroup)?;
+pub(crate) fn convert_lenient(
+    xml: &str,
+    options: &ConvertOptions,
+) -> Result<(serde_json::Value, Vec<Diagnostic>), Error> {
+    let mut converter = Converter::new(true, options.limits.clone());
+    let blocks = converter.convert(xml)?;
 
-    Ok(Value::Array(
-        blocks.iter().map(|block| block.to_json()).collect(),
-    ))
+    Ok((to_value(&blocks, options), converter.diagnostics))
 }
 
-fn convert_blockgroup(blockgroup: Node) -> Result<Vec<Block>, Error> {
-    blockgroup
-        .children()
-        .filter(|child| child.is_element())
-        .map(|block_container| convert_block_container(block_container))
-        .collect()
+pub(crate) fn to_value(blocks: &[Block], options: &ConvertOptions) -> serde_json::Value {
+    serde_json::to_value(options.apply(blocks))
+        .expect("the document model always serializes to JSON")
 }
 
-fn convert_block_container(block_container: Node) -> Result<Block, Error> {
-    let mut block = Block::new();
-    block.apply_attributes(block_container.attributes());
-    let mut children = block_container
-        .children()
-        .filter(|child| child.is_element());
-    let Some(block_elem) = children.next() else {
-        return Err(Error::MalformedDocument(
-            "blockcontainer with no children".to_string(),
-            block_container
-                .document()
-                .text_pos_at(block_container.range().start),
-        ));
+pub(crate) fn write_json<W: io::Write>(
+    blocks: &[Block],
+    writer: W,
+    options: &ConvertOptions,
+    pretty: bool,
+) -> Result<(), Error> {
+    let document = options.apply(blocks);
+    let result = if pretty {
+        serde_json::to_writer_pretty(writer, &document)
+    } else {
+        serde_json::to_writer(writer, &document)
     };
+    // the model always serializes, so the only possible failure is writing
+    result.map_err(|e| Error::Io(Arc::new(e.into())))
+}
 
-    block.type_name = block_elem.tag_name().name().to_string();
-    block.apply_attributes(block_elem.attributes());
+pub(crate) struct Converter {
+    // In lenient mode, malformed nodes are skipped or repaired and reported in `diagnostics`
+    // rather than failing the whole conversion
+    lenient: bool,
+    diagnostics: Vec<Diagnostic>,
+    // ids of the blockcontainers enclosing the node currently being converted
+    block_path: Vec<String>,
+    limits: Limits,
+    // running totals checked against `limits`
+    blocks: usize,
+    inline_items: usize,
+    table_cells: usize,
+}
 
-    if block.type_name.as_str() == "table" {
-        return convert_table(block_elem, block);
+impl Converter {
+    pub fn new(lenient: bool, limits: Limits) -> Self {
+        Converter {
+            lenient,
+            diagnostics: Vec::new(),
+            block_path: Vec::new(),
+            limits,
+            blocks: 0,
+            inline_items: 0,
+            table_cells: 0,
+        }
     }
 
-    // Check if the block has content
-    let content = block_elem
-        .children()
-        .map(|child| convert_content(child, &mut vec![]))
-        .collect::<Result<Vec<_>, _>>()?;
-    block.content = Some(Content::Basic(content));
+    // Converts a whole document. The converter can be reused afterwards, keeping the capacity
+    // of its buffers.
+    pub fn convert(&mut self, xml: &str) -> Result<Vec<Block>, Error> {
+        self.diagnostics.clear();
+        self.block_path.clear();
+        self.blocks = 0;
+        self.inline_items = 0;
+        self.table_cells = 0;
 
-    // Check if the block has children
-    if let Some(blockgroup) = children.next() {
-        block.children = convert_blockgroup(blockgroup)?;
+        limits::check_input_bytes(xml, self.limits.max_input_bytes)?;
+        limits::check_depth(xml, self.limits.max_depth)?;
+        let doc = Document::parse(xml).map_err(Error::ParseError)?;
+        let blockgroup = doc.root_element();
+
+        self.convert_blockgroup(blockgroup, 1)
     }
 
-    Ok(block)
-}
+    fn malformed(&self, node: Node, kind: ErrorKind) -> Error {
+        Error::MalformedDocument {
+            kind,
+            pos: node.document().text_pos_at(node.range().start),
+            block_path: self.block_path.clone(),
+        }
+    }
+
+    // Counts one more of `limit` at `node`, failing if that goes over the maximum.
+    fn count(&mut self, node: Node, limit: LimitKind) -> Result<(), Error> {
+        let (count, max) = match limit {
+            LimitKind::Blocks => (&mut self.blocks, self.limits.max_blocks),
+            LimitKind::InlineItems => (&mut self.inline_items, self.limits.max_inline_items),
+            LimitKind::TableCells => (&mut self.table_cells, self.limits.max_table_cells),
+        };
+        *count += 1;
 
-fn convert_content(node: Node, styles: &mut Vec<Style>) -> Result<BasicContent, Error> {
-    match node.node_type() {
-        NodeType::Text => {
-            let mut content = BasicContent::new();
-            content.type_name = "text".to_string();
-            content.props.insert("text".to_string(), node.text().into());
-            content.styles = styles.clone();
-            Ok(content)
+        match max {
+            Some(max) if *count > max => Err(Error::LimitExceeded {
+                limit,
+                max,
+                pos: node.document().text_pos_at(node.range().start),
+                block_path: self.block_path.clone(),
+            }),
+            _ => Ok(()),
         }
-        NodeType::Element => match node.tag_name().name() {
-            "bold" | "italic" | "underline" | "strike" | "code" | "textColor"
-            | "backgroundColor" => {
-                // Style tags can have either one text child or one element child.
-                // In the case of an element child, the tag could be surrounded by whitespace.
-                // This seems to only happen when the XML is formatted with newlines,
-                // so we strip whitespace from text nodes.
-                let children = node
-                    .children()
-                    .filter(|child| {
-                        child.is_element()
-                            || (child.is_text()
-                                && !child
-                                    .text()
-                                    .expect("child.is_text() is true, but child.text() is None")
-                                    .trim()
-                                    .is_empty())
-                    })
-                    .collect::<Vec<_>>();
-                if children.is_empty() {
-                    Err(Error::MalformedDocument(
-                        "style tag with no children".to_string(),
-                        node.document().text_pos_at(node.range().start),
-                    ))
-                } else if children.len() > 1 {
-                    Err(Error::MalformedDocument(
-                        "style tag with more than one child".to_string(),
-                        node.document().text_pos_at(node.range().start),
-                    ))
-                } else {
-                    match node.tag_name().name() {
-                        "textColor" => {
-                            styles.push(Style::TextColor(
-                                node.attributes()
-                                    .find(|attr| attr.name() == "stringValue")
-                                    .map(|attr| attr.value().to_string())
-                                    .unwrap_or("default".to_string()),
-                            ));
-                        }
-                        "backgroundColor" => {
-                            styles.push(Style::BackgroundColor(
-                                node.attributes()
-                                    .find(|attr| attr.name() == "stringValue")
-                                    .map(|attr| attr.value().to_string())
-                                    .unwrap_or("default".to_string()),
-                            ));
-                        }
-                        style => {
-                            styles.push(style.try_into().map_err(|e| {
-                                Error::MalformedDocument(
-                                    e,
-                                    node.document().text_pos_at(node.range().start),
-                                )
-                            })?);
+    }
+
+    // Fails if `node`, at the given element depth, is nested deeper than the limit. The depth is
+    // checked before parsing too, but conversion recurses for each level as well, so it checks
+    // again rather than relying on the two agreeing.
+    fn check_depth(&self, node: Node, depth: usize) -> Result<(), Error> {
+        if depth > self.limits.max_depth {
+            return Err(Error::DepthLimitExceeded {
+                max_depth: self.limits.max_depth,
+                pos: node.document().text_pos_at(node.range().start),
+            });
+        }
+
+        Ok(())
+    }
+
+    // Returns the error in strict mode; in lenient mode, records it so conversion can continue.
+    fn recover(&mut self, error: Error, severity: Severity) -> Result<(), Error> {
+        if !self.lenient {
+            return Err(error);
+        }
+
+        let Error::MalformedDocument { kind, pos, .. } = error else {
+            return Err(error);
+        };
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message: kind.to_string(),
+            pos,
+            block_id: self.block_path.last().cloned(),
+        });
+
+        Ok(())
+    }
+
+    fn convert_blockgroup(&mut self, blockgroup: Node, depth: usize) -> Result<Vec<Block>, Error> {
+        self.check_depth(blockgroup, depth)?;
+        let mut blocks = Vec::new();
+        for block_container in blockgroup.children().filter(|child| child.is_element()) {
+            if let Some(block) = self.convert_block_container(block_container, depth + 1)? {
+                blocks.push(block);
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    fn convert_block_container(
+        &mut self,
+        block_container: Node,
+        depth: usize,
+    ) -> Result<Option<Block>, Error> {
+        self.check_depth(block_container, depth)?;
+        self.count(block_container, LimitKind::Blocks)?;
+
+        let mut block = Block::new();
+        block.apply_attributes(encoding::attributes(block_container));
+
+        self.block_path.push(block.id.clone());
+        let result = self.convert_block(block_container, block, depth);
+        self.block_path.pop();
+
+        result
+    }
+
+    fn convert_block(
+        &mut self,
+        block_container: Node,
+        mut block: Block,
+        depth: usize,
+    ) -> Result<Option<Block>, Error> {
+        let mut children = block_container
+            .children()
+            .filter(|child| child.is_element());
+        let Some(block_elem) = children.next() else {
+            self.recover(
+                self.malformed(block_container, ErrorKind::EmptyBlockContainer),
+                Severity::Error,
+            )?;
+            return Ok(None);
+        };
+
+        self.check_depth(block_elem, depth + 1)?;
+        block.type_name = encoding::tag_name(block_elem).into_owned();
+        block.apply_attributes(encoding::attributes(block_elem));
+        // inline items are counted per block
+        self.inline_items = 0;
+
+        if block.type_name.as_str() == "table" {
+            return self.convert_table(block_elem, block, depth + 1).map(Some);
+        }
+
+        // Check if the block has content
+        let content = self.convert_inline_children(block_elem, &mut vec![], depth + 1)?;
+        block.content = Some(Content::Basic(content));
+
+        // Check if the block has children
+        if let Some(blockgroup) = children.next() {
+            block.children = self.convert_blockgroup(blockgroup, depth + 1)?;
+        }
+
+        Ok(Some(block))
+    }
+
+    fn convert_inline_children(
+        &mut self,
+        node: Node,
+        styles: &mut Vec<Style>,
+        depth: usize,
+    ) -> Result<Vec<BasicContent>, Error> {
+        let mut content = Vec::new();
+        for child in node.children() {
+            if let Some(item) = self.convert_content(child, styles, depth + 1)? {
+                content.push(item);
+            }
+        }
+
+        Ok(content)
+    }
+
+    fn convert_content(
+        &mut self,
+        node: Node,
+        styles: &mut Vec<Style>,
+        depth: usize,
+    ) -> Result<Option<BasicContent>, Error> {
+        if node.is_element() {
+            self.check_depth(node, depth)?;
+        }
+        self.count(node, LimitKind::InlineItems)?;
+
+        match node.node_type() {
+            NodeType::Text => {
+                let mut content = BasicContent::new();
+                content.type_name = "text".to_string();
+                content.props.insert("text".to_string(), node.text().into());
+                content.styles = styles.clone();
+                Ok(Some(content))
+            }
+            NodeType::Element if encoding::text(node).is_some() => {
+                let mut content = BasicContent::new();
+                content.type_name = "text".to_string();
+                content
+                    .props
+                    .insert("text".to_string(), encoding::text(node).into());
+                content.styles = styles.clone();
+                Ok(Some(content))
+            }
+            NodeType::Element => match encoding::tag_name(node).as_ref() {
+                "bold" | "italic" | "underline" | "strike" | "code" | "textColor"
+                | "backgroundColor" | "insertion" | "deletion" | "author" => {
+                    // Style tags can have either one text child or one element child.
+                    // In the case of an element child, the tag could be surrounded by whitespace.
+                    // This seems to only happen when the XML is formatted with newlines,
+                    // so we strip whitespace from text nodes, but only next to an element;
+                    // a lone whitespace text child is real content.
+                    let mut children = node.children().collect::<Vec<_>>();
+                    if children.iter().any(|child| child.is_element()) {
+                        children.retain(|child| {
+                            child.is_element()
+                                || (child.is_text()
+                                    && !child
+                                        .text()
+                                        .expect("child.is_text() is true, but child.text() is None")
+                                        .trim()
+                                        .is_empty())
+                        });
+                    }
+                    if children.is_empty() {
+                        self.recover(
+                            self.malformed(node, ErrorKind::EmptyStyleTag),
+                            Severity::Error,
+                        )?;
+                        Ok(None)
+                    } else if children.len() > 1 {
+                        self.recover(
+                            self.malformed(node, ErrorKind::MultiChildStyleTag),
+                            Severity::Error,
+                        )?;
+                        Ok(None)
+                    } else {
+                        let string_value = || {
+                            encoding::attributes(node)
+                                .into_iter()
+                                .find(|(name, _)| name == "stringValue")
+                                .map(|(_, value)| encoding::into_string(value))
+                        };
+                        match encoding::tag_name(node).as_ref() {
+                            "textColor" => {
+                                styles.push(Style::TextColor(
+                                    string_value().unwrap_or("default".to_string()),
+                                ));
+                            }
+                            "backgroundColor" => {
+                                styles.push(Style::BackgroundColor(
+                                    string_value().unwrap_or("default".to_string()),
+                                ));
+                            }
+                            "author" => {
+                                styles.push(Style::Author(string_value().unwrap_or_default()));
+                            }
+                            style => {
+                                let style = style.try_into().map_err(|_| {
+                                    self.malformed(node, ErrorKind::UnknownStyle(style.to_string()))
+                                })?;
+                                styles.push(style);
+                            }
                         }
+                        let content = self.convert_content(
+                            *children.first().expect(
+                                "children.is_empty() is false, but children.first() is None",
+                            ),
+                            styles,
+                            depth + 1,
+                        );
+                        // the style only applies inside this tag, not to its siblings
+                        styles.pop();
+                        content
                     }
-                    convert_content(
-                        *children
-                            .first()
-                            .expect("children.is_empty() is false, but children.first() is None"),
-                        styles,
-                    )
                 }
-            }
-            name => {
-                let mut content = BasicContent::new();
-                content.type_name = name.to_string();
-                content.apply_attributes(node.attributes());
-                let children = node.children().collect::<Vec<_>>();
-                if !children.is_empty() {
-                    content.content = Some(
-                        children
-                            .iter()
-                            .map(|child| convert_content(*child, styles))
-                            .collect::<Result<Vec<_>, _>>()?,
-                    );
+                name => {
+                    let mut content = BasicContent::new();
+                    content.type_name = name.to_string();
+                    content.apply_attributes(encoding::attributes(node));
+                    if node.has_children() {
+                        content.content = Some(self.convert_inline_children(node, styles, depth)?);
+                    }
+                    Ok(Some(content))
                 }
-                Ok(content)
+            },
+            _ => {
+                self.recover(
+                    self.malformed(node, ErrorKind::UnsupportedNode),
+                    Severity::Warning,
+                )?;
+                Ok(None)
             }
-        },
-        _ => Err(Error::MalformedDocument(
-            "Unsupported node type in content position".to_string(),
-            node.document().text_pos_at(node.range().start),
-        )),
+        }
     }
-}
 
-fn convert_table(block_elem: Node, mut block: Block) -> Result<Block, Error> {
-    let row_elems = block_elem.children().filter(|child| child.is_element());
-    let rows = row_elems
-        .map(|row_elem| convert_table_row(row_elem))
-        .collect::<Result<Vec<_>, _>>()?;
+    fn convert_table(
+        &mut self,
+        block_elem: Node,
+        mut block: Block,
+        depth: usize,
+    ) -> Result<Block, Error> {
+        self.table_cells = 0;
+        let mut rows = Vec::new();
+        for row_elem in block_elem.children().filter(|child| child.is_element()) {
+            rows.push(self.convert_table_row(row_elem, depth + 1)?);
+        }
 
-    block.content = Some(Content::Table(TableContent::new(rows)));
-    Ok(block)
-}
+        block.content = Some(Content::Table(TableContent::new(rows)));
+        Ok(block)
+    }
 
-fn convert_table_row(row_elem: Node) -> Result<TableRow, Error> {
-    let cell_elems = row_elem.children().filter(|child| child.is_element());
-    let cells = cell_elems
-        .map(|cell_elem| convert_table_cell(cell_elem))
-        .collect::<Result<Vec<_>, _>>()?;
+    fn convert_table_row(&mut self, row_elem: Node, depth: usize) -> Result<TableRow, Error> {
+        self.check_depth(row_elem, depth)?;
+        let mut cells = Vec::new();
+        for cell_elem in row_elem.children().filter(|child| child.is_element()) {
+            cells.push(self.convert_table_cell(cell_elem, depth + 1)?);
+        }
 
-    Ok(cells)
-}
+        Ok(cells)
+    }
 
-fn convert_table_cell(cell_elem: Node) -> Result<TableCell, Error> {
-    let mut cell = TableCell::new();
-
-    for attr in cell_elem.attributes() {
-        match attr.name() {
-            "colspan" => {
-                cell.colspan = attr.value().parse::<u32>().map_err(|_| {
-                    Error::MalformedDocument(
-                        "Invalid colspan".to_string(),
-                        cell_elem.document().text_pos_at(cell_elem.range().start),
-                    )
-                })?;
-            }
-            "rowspan" => {
-                cell.rowspan = attr.value().parse::<u32>().map_err(|_| {
-                    Error::MalformedDocument(
-                        "Invalid rowspan".to_string(),
-                        cell_elem.document().text_pos_at(cell_elem.range().start),
-                    )
-                })?;
-            }
-            "colwidth" => {
-                cell.colwidth = Some(
-                    attr.value()
-                        .trim_start_matches("[")
-                        .trim_end_matches("]")
-                        .parse::<u32>()
-                        .map_err(|_| {
-                            Error::MalformedDocument(
-                                "Invalid colwidth".to_string(),
-                                cell_elem.document().text_pos_at(cell_elem.range().start),
-                            )
-                        })?,
-                );
+    fn convert_table_cell(&mut self, cell_elem: Node, depth: usize) -> Result<TableCell, Error> {
+        self.check_depth(cell_elem, depth)?;
+        self.count(cell_elem, LimitKind::TableCells)?;
+        let mut cell = TableCell::new();
+
+        for attr in cell_elem.attributes() {
+            match attr.name() {
+                "colspan" => match attr.value().parse::<u32>() {
+                    Ok(colspan) => cell.colspan = colspan,
+                    Err(_) => self.recover(
+                        self.malformed(cell_elem, ErrorKind::InvalidSpan("colspan".to_string())),
+                        Severity::Warning,
+                    )?,
+                },
+                "rowspan" => match attr.value().parse::<u32>() {
+                    Ok(rowspan) => cell.rowspan = rowspan,
+                    Err(_) => self.recover(
+                        self.malformed(cell_elem, ErrorKind::InvalidSpan("rowspan".to_string())),
+                        Severity::Warning,
+                    )?,
+                },
+                "colwidth" => match attr
+                    .value()
+                    .trim_start_matches("[")
+                    .trim_end_matches("]")
+                    .parse::<u32>()
+                {
+                    Ok(colwidth) => cell.colwidth = Some(colwidth),
+                    Err(_) => self.recover(
+                        self.malformed(cell_elem, ErrorKind::InvalidSpan("colwidth".to_string())),
+                        Severity::Warning,
+                    )?,
+                },
+                _ => {}
             }
-            _ => {}
         }
-    }
+        // anything else (e.g. the cell's colors) is passed through as props
+        cell.props = encoding::attributes(cell_elem)
+            .into_iter()
+            .filter(|(name, _)| !matches!(name.as_str(), "colspan" | "rowspan" | "colwidth"))
+            .collect();
 
-    let paragraph_elem = cell_elem
-        .first_element_child()
-        .ok_or(Error::MalformedDocument(
-            "table cell with no table paragraph".to_string(),
-            cell_elem.document().text_pos_at(cell_elem.range().start),
-        ))?;
+        let Some(paragraph_elem) = cell_elem.first_element_child() else {
+            self.recover(
+                self.malformed(cell_elem, ErrorKind::MissingTableParagraph),
+                Severity::Warning,
+            )?;
+            return Ok(cell);
+        };
 
-    let content = paragraph_elem
-        .children()
-        .map(|child| convert_content(child, &mut vec![]))
-        .collect::<Result<Vec<_>, _>>()?;
-    cell.content = content;
+        self.check_depth(paragraph_elem, depth + 1)?;
+        cell.content = self.convert_inline_children(paragraph_elem, &mut vec![], depth + 1)?;
 
-    Ok(cell)
+        Ok(cell)
+    }
 }
@@ -0,0 +1,38 @@
+/// How a problem found during lenient conversion was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The offending node was repaired (e.g. an invalid `colspan` was replaced by the default)
+    /// and its content is still present in the output.
+    Warning,
+    /// The offending node could not be repaired and was left out of the output.
+    Error,
+}
+
+/// A problem found in the document during lenient conversion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub pos: roxmltree::TextPos,
+    /// The id of the innermost block containing the problem, if any.
+    pub block_id: Option<String>,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}: {} at line {} column {}",
+            severity, self.message, self.pos.row, self.pos.col
+        )?;
+        if let Some(block_id) = &self.block_id {
+            write!(f, " (in block {})", block_id)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use serde_json::Value;
+
+use crate::{
+    block::Block,
+    content::{basic::BasicContent, Content},
+    converter::Error,
+    import,
+    options::ConvertOptions,
+};
+
+/// Where a block is in a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockPosition {
+    /// The id of the block this one is nested in, or `None` at the top level.
+    pub parent: Option<String>,
+    /// The index of the block among its siblings.
+    pub index: usize,
+}
+
+/// A difference between two versions of a document, as found by `diff_blocks`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum BlockChange {
+    Added {
+        id: String,
+        position: BlockPosition,
+    },
+    Removed {
+        id: String,
+        position: BlockPosition,
+    },
+    /// The block was moved to another parent, or reordered among the siblings it had before.
+    /// Blocks that only shifted because of blocks added or removed around them aren't moved.
+    Moved {
+        id: String,
+        from: BlockPosition,
+        to: BlockPosition,
+    },
+    Modified {
+        id: String,
+        /// The old and new type, if the type changed.
+        type_change: Option<(String, String)>,
+        props: Vec<PropChange>,
+        /// Whether the content changed at all, including formatting and non-text content.
+        content_changed: bool,
+        /// A word-level diff of the block's text, or empty if the text didn't change.
+        text: Vec<TextChange>,
+    },
+}
+
+/// A prop that was added, removed or changed. `old` is `None` for a prop that was added, and
+/// `new` is `None` for one that was removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropChange {
+    pub name: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// A run of text in a word-level diff. Runs are whole words and the whitespace between them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextChange {
+    Equal(String),
+    Insert(String),
+    Delete(String),
+}
+
+// A block along with where it is, indexed by id
+struct Entry<'a> {
+    block: &'a Block,
+    position: BlockPosition,
+}
+
+/// Compares two documents in the form produced by `convert_to_value`, matching blocks by id.
+/// Blocks without an id can't be matched, so they're ignored.
+pub(crate) fn diff_blocks(old: &Value, new: &Value) -> Result<Vec<BlockChange>, Error> {
+    let old = import::parse_blocks(old).map_err(Error::InvalidBlocks)?;
+    let new = import::parse_blocks(new).map_err(Error::InvalidBlocks)?;
+
+    let (mut old_index, mut old_order) = (HashMap::new(), Vec::new());
+    index_blocks(&old, None, &mut old_index, &mut old_order);
+    let (mut new_index, mut new_order) = (HashMap::new(), Vec::new());
+    index_blocks(&new, None, &mut new_index, &mut new_order);
+    let mut moved = HashSet::new();
+    moved_blocks(&new, None, &old, &old_index, &new_index, &mut moved);
+
+    let mut changes = Vec::new();
+    for id in new_order {
+        let entry = &new_index[id];
+        let Some(old_entry) = old_index.get(id) else {
+            changes.push(BlockChange::Added {
+                id: id.to_string(),
+                position: entry.position.clone(),
+            });
+            continue;
+        };
+
+        if moved.contains(id) {
+            changes.push(BlockChange::Moved {
+                id: id.to_string(),
+                from: old_entry.position.clone(),
+                to: entry.position.clone(),
+            });
+        }
+        if let Some(modified) = modified(old_entry.block, entry.block) {
+            changes.push(modified);
+        }
+    }
+    old_order.retain(|id| !new_index.contains_key(id));
+    for id in old_order {
+        changes.push(BlockChange::Removed {
+            id: id.to_string(),
+            position: old_index.remove(id).expect("indexed above").position,
+        });
+    }
+
+    Ok(changes)
+}
+
+fn index_blocks<'a>(
+    blocks: &'a [Block],
+    parent: Option<&str>,
+    index: &mut HashMap<&'a str, Entry<'a>>,
+    order: &mut Vec<&'a str>,
+) {
+    for (i, block) in blocks.iter().enumerate() {
+        if block.id.is_empty() {
+            continue;
+        }
+
+        let position = BlockPosition {
+            parent: parent.map(str::to_string),
+            index: i,
+        };
+        if index.insert(&block.id, Entry { block, position }).is_none() {
+            order.push(&block.id);
+        }
+        index_blocks(&block.children, Some(&block.id), index, order);
+    }
+}
+
+// A block has moved if its parent changed, or if it's out of order with the blocks that were
+// its siblings before and still are
+fn moved_blocks<'a>(
+    blocks: &'a [Block],
+    parent: Option<&str>,
+    old_siblings: &[Block],
+    old_index: &HashMap<&str, Entry>,
+    new_index: &HashMap<&str, Entry>,
+    moved: &mut HashSet<&'a str>,
+) {
+    let same_parent = |index: &HashMap<&str, Entry>, id: &str| {
+        index
+            .get(id)
+            .is_some_and(|entry| entry.position.parent.as_deref() == parent)
+    };
+    let new_ids = blocks
+        .iter()
+        .map(|block| block.id.as_str())
+        .filter(|id| same_parent(old_index, id))
+        .collect::<Vec<_>>();
+    let old_ids = old_siblings
+        .iter()
+        .map(|block| block.id.as_str())
+        .filter(|id| same_parent(new_index, id))
+        .collect::<Vec<_>>();
+    let in_order = kept_in_order(&old_ids, &new_ids)
+        .into_iter()
+        .map(|i| new_ids[i])
+        .collect::<HashSet<_>>();
+
+    for block in blocks {
+        if block.id.is_empty() {
+            continue;
+        }
+        if old_index.contains_key(block.id.as_str()) && !in_order.contains(block.id.as_str()) {
+            moved.insert(&block.id);
+        }
+
+        let old_children = old_index
+            .get(block.id.as_str())
+            .map_or(&[][..], |entry| &entry.block.children);
+        moved_blocks(
+            &block.children,
+            Some(&block.id),
+            old_children,
+            old_index,
+            new_index,
+            moved,
+        );
+    }
+}
+
+fn modified(old: &Block, new: &Block) -> Option<BlockChange> {
+    let type_change =
+        (old.type_name != new.type_name).then(|| (old.type_name.clone(), new.type_name.clone()));
+
+    let mut props = Vec::new();
+    for (name, value) in old.props.iter() {
+        match new.props.get(name) {
+            Some(new_value) if new_value == value => {}
+            new_value => props.push(PropChange {
+                name: name.clone(),
+                old: Some(value.clone()),
+                new: new_value.cloned(),
+            }),
+        }
+    }
+    for (name, value) in new.props.iter() {
+        if !old.props.contains_key(name) {
+            props.push(PropChange {
+                name: name.clone(),
+                old: None,
+                new: Some(value.clone()),
+            });
+        }
+    }
+
+    let options = ConvertOptions::default();
+    let content = |block: &Block| {
+        serde_json::to_value(block.content.as_ref().map(|content| options.apply(content)))
+            .expect("the document model always serializes to JSON")
+    };
+    let content_changed = content(old) != content(new);
+    let (old_text, new_text) = (block_text(old), block_text(new));
+    let text = if old_text != new_text {
+        diff_words(&old_text, &new_text)
+    } else {
+        Vec::new()
+    };
+
+    if type_change.is_none() && props.is_empty() && !content_changed {
+        return None;
+    }
+    Some(BlockChange::Modified {
+        id: new.id.clone(),
+        type_change,
+        props,
+        content_changed,
+        text,
+    })
+}
+
+// The block's text, with table cells separated by tabs and rows by newlines
+fn block_text(block: &Block) -> String {
+    let mut text = String::new();
+    match &block.content {
+        Some(Content::Basic(content)) => inline_text(content, &mut text),
+        Some(Content::Table(table)) => {
+            for (i, row) in table.rows().iter().enumerate() {
+                if i > 0 {
+                    text.push('\n');
+                }
+                for (j, cell) in row.iter().enumerate() {
+                    if j > 0 {
+                        text.push('\t');
+                    }
+                    inline_text(&cell.content, &mut text);
+                }
+            }
+        }
+        None => {}
+    }
+
+    text
+}
+
+fn inline_text(content: &[BasicContent], text: &mut String) {
+    for item in content {
+        if let Some(Value::String(s)) = item.props.get("text") {
+            text.push_str(s);
+        }
+        if let Some(content) = &item.content {
+            inline_text(content, text);
+        }
+    }
+}
+
+fn diff_words(old: &str, new: &str) -> Vec<TextChange> {
+    let (old, new) = (words(old), words(new));
+
+    // only the middle, where the texts differ, needs the quadratic diff
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_middle, new_middle) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut changes = Vec::new();
+    for word in &old[..prefix] {
+        push_change(&mut changes, TextChange::Equal, word);
+    }
+    let (mut i, mut j) = (0, 0);
+    for (old_i, new_j) in lcs(old_middle, new_middle) {
+        for word in &old_middle[i..old_i] {
+            push_change(&mut changes, TextChange::Delete, word);
+        }
+        for word in &new_middle[j..new_j] {
+            push_change(&mut changes, TextChange::Insert, word);
+        }
+        push_change(&mut changes, TextChange::Equal, old_middle[old_i]);
+        (i, j) = (old_i + 1, new_j + 1);
+    }
+    for word in &old_middle[i..] {
+        push_change(&mut changes, TextChange::Delete, word);
+    }
+    for word in &new_middle[j..] {
+        push_change(&mut changes, TextChange::Insert, word);
+    }
+    for word in &old[old.len() - suffix..] {
+        push_change(&mut changes, TextChange::Equal, word);
+    }
+
+    changes
+}
+
+// Appends the word to the last change if it's the same kind, so runs are as long as possible
+fn push_change(changes: &mut Vec<TextChange>, kind: fn(String) -> TextChange, word: &str) {
+    let change = kind(word.to_string());
+    match (changes.last_mut(), change) {
+        (Some(TextChange::Equal(text)), TextChange::Equal(word))
+        | (Some(TextChange::Insert(text)), TextChange::Insert(word))
+        | (Some(TextChange::Delete(text)), TextChange::Delete(word)) => text.push_str(&word),
+        (_, change) => changes.push(change),
+    }
+}
+
+// Splits the text into words and the runs of whitespace between them
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut whitespace = None;
+    for (i, c) in text.char_indices() {
+        if whitespace.is_some_and(|whitespace| whitespace != c.is_whitespace()) {
+            words.push(&text[start..i]);
+            start = i;
+        }
+        whitespace = Some(c.is_whitespace());
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+
+    words
+}
+
+// The indices of the items of `new` in the longest run of them kept in the same order as in
+// `old`: their longest common subsequence. This is for ids, which appear at most once in each,
+// so it's the longest increasing run of their indices in `old`, found in O(n log n).
+pub(crate) fn kept_in_order<T: Eq + Hash>(old: &[T], new: &[T]) -> HashSet<usize> {
+    let old_indexes = old
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (item, i))
+        .collect::<HashMap<_, _>>();
+    // the index in `new` and in `old` of each item in both
+    let kept = new
+        .iter()
+        .enumerate()
+        .filter_map(|(j, item)| Some((j, *old_indexes.get(item)?)))
+        .collect::<Vec<_>>();
+
+    // tails[k] is the position in `kept` of the smallest index in `old` that ends an increasing
+    // run of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; kept.len()];
+    for (position, (_, index)) in kept.iter().enumerate() {
+        let k = tails.partition_point(|&tail| kept[tail].1 < *index);
+        if k > 0 {
+            previous[position] = Some(tails[k - 1]);
+        }
+        if k == tails.len() {
+            tails.push(position);
+        } else {
+            tails[k] = position;
+        }
+    }
+
+    let mut run = HashSet::new();
+    let mut current = tails.last().copied();
+    while let Some(position) = current {
+        run.insert(kept[position].0);
+        current = previous[position];
+    }
+    run
+}
+
+// The longest common subsequence of the two slices, as pairs of matching indices. Uses
+// Hirschberg's algorithm, so it only needs space linear in the lengths of the slices.
+pub(crate) fn lcs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    lcs_between(a, b, (0, 0), &mut pairs);
+    pairs
+}
+
+fn lcs_between<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    (a_start, b_start): (usize, usize),
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    if let [item] = a {
+        if let Some(j) = b.iter().position(|other| other == item) {
+            pairs.push((a_start, b_start + j));
+        }
+        return;
+    }
+
+    // split b where the LCS of the first half of a with b's start, plus that of the second half
+    // with b's end, is longest
+    let mid = a.len() / 2;
+    let forwards = lcs_lengths(&a[..mid], b, false);
+    let backwards = lcs_lengths(&a[mid..], b, true);
+    let split = (0..=b.len())
+        .rev()
+        .max_by_key(|&j| forwards[j] + backwards[b.len() - j])
+        .expect("there's always a split");
+
+    lcs_between(&a[..mid], &b[..split], (a_start, b_start), pairs);
+    lcs_between(
+        &a[mid..],
+        &b[split..],
+        (a_start + mid, b_start + split),
+        pairs,
+    );
+}
+
+// The length of the LCS of `a` with each prefix of `b`, or with each suffix of `b` (by length)
+// when going backwards through both
+fn lcs_lengths<T: PartialEq>(a: &[T], b: &[T], backwards: bool) -> Vec<usize> {
+    let at = |items: &[T], i: usize| if backwards { items.len() - 1 - i } else { i };
+    let mut row = vec![0; b.len() + 1];
+    for i in 0..a.len() {
+        let item = &a[at(a, i)];
+        // the previous row's value for the previous prefix of b
+        let mut diagonal = 0;
+        for j in 0..b.len() {
+            let above = row[j + 1];
+            row[j + 1] = if *item == b[at(b, j)] {
+                diagonal + 1
+            } else {
+                above.max(row[j])
+            };
+            diagonal = above;
+        }
+    }
+
+    row
+}
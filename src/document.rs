@@ -0,0 +1,72 @@
+use serde_json::{json, Value};
+use yrs::Transact;
+
+use crate::{
+    converter::{self, Error},
+    inspect,
+    options::ConvertOptions,
+    serialize::{self, XmlOptions},
+};
+
+/// Options for `convert_document`.
+#[derive(Debug, Clone)]
+pub struct DocumentOptions {
+    fragment_name: String,
+    meta: Vec<String>,
+    convert: ConvertOptions,
+}
+
+impl Default for DocumentOptions {
+    fn default() -> Self {
+        DocumentOptions {
+            fragment_name: "document-store".to_string(),
+            meta: Vec::new(),
+            convert: ConvertOptions::default(),
+        }
+    }
+}
+
+impl DocumentOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The name of the fragment holding the blocks. Defaults to `"document-store"`.
+    pub fn fragment_name(mut self, fragment_name: impl Into<String>) -> Self {
+        self.fragment_name = fragment_name.into();
+        self
+    }
+
+    /// Include the named shared type (usually a `Y.Map` or `Y.Text`) under `meta`. Can be
+    /// called more than once; shared types that don't exist are left out.
+    pub fn meta(mut self, name: impl Into<String>) -> Self {
+        self.meta.push(name.into());
+        self
+    }
+
+    pub fn convert(mut self, convert: ConvertOptions) -> Self {
+        self.convert = convert;
+        self
+    }
+}
+
+pub(crate) fn convert_document(doc: &yrs::Doc, options: &DocumentOptions) -> Result<Value, Error> {
+    // read the blocks and the metadata in one transaction, so they're from the same state
+    let (xml, meta) = {
+        let txn = doc.transact();
+        let fragment = inspect::find_fragment(&txn, &options.fragment_name)?;
+        let mut xml = String::new();
+        serialize::write_fragment(fragment, &txn, &mut xml, &XmlOptions::default())
+            .expect("writing to a String cannot fail");
+
+        let meta = options
+            .meta
+            .iter()
+            .filter_map(|name| Some((name.clone(), inspect::root_to_json(&txn, name)?)))
+            .collect::<serde_json::Map<_, _>>();
+        (xml, meta)
+    };
+
+    let blocks = converter::convert(&xml, &options.convert)?;
+    Ok(json!({ "meta": meta, "blocks": blocks }))
+}
@@ -0,0 +1,475 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde_json::Value;
+use yrs::branch::Branch;
+use yrs::types::text::YChange;
+use yrs::types::Attrs;
+use yrs::{
+    Any, Doc, Map, Out, ReadTxn, Text, Transact, TransactionMut, Xml, XmlElementPrelim,
+    XmlElementRef, XmlFragment, XmlFragmentRef, XmlOut, XmlTextPrelim,
+};
+
+use crate::{
+    block::Block,
+    content::Content,
+    converter::Error,
+    import::{self, CONTAINER_PROPS},
+    inspect,
+};
+
+// Where to put a block relative to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Place {
+    Before,
+    After,
+}
+
+#[derive(Debug, Clone)]
+enum BlockEdit {
+    UpdateProps {
+        id: String,
+        props: Value,
+    },
+    ReplaceContent {
+        id: String,
+        content: Value,
+    },
+    Insert {
+        id: String,
+        place: Place,
+        blocks: Value,
+    },
+    Move {
+        id: String,
+        place: Place,
+        target: String,
+    },
+    Delete {
+        id: String,
+    },
+}
+
+/// Changes to the blocks of a fragment, made in order by `edit_blocks`, built with
+/// `BlockEdits::new()`. Blocks are found by their `id`.
+#[derive(Debug, Clone, Default)]
+pub struct BlockEdits {
+    edits: Vec<BlockEdit>,
+}
+
+impl BlockEdits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the given props of the block, leaving its other props alone. A `null` value removes
+    /// the prop.
+    pub fn update_props(mut self, id: impl Into<String>, props: Value) -> Self {
+        self.edits.push(BlockEdit::UpdateProps {
+            id: id.into(),
+            props,
+        });
+        self
+    }
+
+    /// Replaces the block's content with inline content (an array) or table content (an
+    /// object), in the form produced by the converter.
+    pub fn replace_content(mut self, id: impl Into<String>, content: Value) -> Self {
+        self.edits.push(BlockEdit::ReplaceContent {
+            id: id.into(),
+            content,
+        });
+        self
+    }
+
+    /// Inserts an array of blocks just before the block, alongside it.
+    pub fn insert_before(mut self, id: impl Into<String>, blocks: Value) -> Self {
+        self.edits.push(BlockEdit::Insert {
+            id: id.into(),
+            place: Place::Before,
+            blocks,
+        });
+        self
+    }
+
+    /// Inserts an array of blocks just after the block, alongside it.
+    pub fn insert_after(mut self, id: impl Into<String>, blocks: Value) -> Self {
+        self.edits.push(BlockEdit::Insert {
+            id: id.into(),
+            place: Place::After,
+            blocks,
+        });
+        self
+    }
+
+    /// Moves the block, along with its nested blocks, to just before the target block.
+    ///
+    /// The block is deleted and written again as a copy, so it gets new Y items: sticky
+    /// indexes into it no longer resolve, and concurrent remote edits to the old items are
+    /// lost. Its attributes and formatted text are copied, but embeds in its text are dropped.
+    pub fn move_before(mut self, id: impl Into<String>, target: impl Into<String>) -> Self {
+        self.edits.push(BlockEdit::Move {
+            id: id.into(),
+            place: Place::Before,
+            target: target.into(),
+        });
+        self
+    }
+
+    /// Moves the block, along with its nested blocks, to just after the target block. Like
+    /// `move_before`, this writes a copy of the block.
+    pub fn move_after(mut self, id: impl Into<String>, target: impl Into<String>) -> Self {
+        self.edits.push(BlockEdit::Move {
+            id: id.into(),
+            place: Place::After,
+            target: target.into(),
+        });
+        self
+    }
+
+    /// Deletes the block, along with its nested blocks. Deleting every top-level block leaves
+    /// an empty top-level blockGroup, which converts to an empty array.
+    pub fn delete(mut self, id: impl Into<String>) -> Self {
+        self.edits.push(BlockEdit::Delete { id: id.into() });
+        self
+    }
+}
+
+pub(crate) fn find_block(doc: &Doc, fragment_name: &str, id: &str) -> Result<XmlElementRef, Error> {
+    let txn = doc.transact();
+    let fragment = inspect::find_fragment(&txn, fragment_name)?;
+    inspect::find_container(&txn, &fragment, id)
+        .ok_or_else(|| Error::BlockNotFound { id: id.to_string() })
+}
+
+// An edit with its JSON checked
+enum Checked<'a> {
+    // a `None` value removes the prop
+    UpdateProps(&'a str, Vec<(&'a str, Option<Any>)>),
+    ReplaceContent(&'a str, Content),
+    Insert(&'a str, Place, Vec<Block>),
+    Move(&'a str, Place, &'a str),
+    Delete(&'a str),
+}
+
+/// Makes all the edits in one transaction. Every edit is checked against the document first,
+/// so nothing is changed if any of them would fail.
+pub(crate) fn edit_blocks(doc: &Doc, fragment_name: &str, edits: &BlockEdits) -> Result<(), Error> {
+    let mut txn = doc.transact_mut();
+    let fragment = inspect::find_fragment(&txn, fragment_name)?;
+    let edits = check(&txn, &fragment, edits)?;
+
+    for edit in edits {
+        match edit {
+            Checked::UpdateProps(id, props) => {
+                let container = container(&txn, &fragment, id);
+                let elem = block_element(&txn, &container);
+                for (name, value) in props {
+                    let attributes = match &elem {
+                        Some(elem) if !CONTAINER_PROPS.contains(&name) => import::attributes(elem),
+                        _ => import::attributes(&container),
+                    };
+                    match value {
+                        Some(value) => {
+                            attributes.insert(&mut txn, name, value);
+                        }
+                        None => {
+                            attributes.remove(&mut txn, name);
+                        }
+                    }
+                }
+            }
+            Checked::ReplaceContent(id, content) => {
+                let container = container(&txn, &fragment, id);
+                if let Some(elem) = block_element(&txn, &container) {
+                    let len = elem.len(&txn);
+                    elem.remove_range(&mut txn, 0, len);
+                    import::write_content(&mut txn, &elem, &content);
+                }
+            }
+            Checked::Insert(id, place, blocks) => {
+                let (group, index) = position(&txn, &fragment, id, place);
+                import::write_blocks(&mut txn, &group, index, &blocks);
+            }
+            Checked::Move(id, place, target) => {
+                let container = container(&txn, &fragment, id);
+                let copy = Copied::of(&txn, XmlOut::Element(container.clone()));
+                remove(&mut txn, &container);
+                let (group, index) = position(&txn, &fragment, target, place);
+                copy.insert(&mut txn, &group, index);
+            }
+            Checked::Delete(id) => {
+                let container = container(&txn, &fragment, id);
+                remove(&mut txn, &container);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Checks the JSON of every edit, and that every block it refers to will be there when it's
+// made, by playing the edits out on the tree of block ids
+fn check<'a, T: ReadTxn>(
+    txn: &T,
+    fragment: &XmlFragmentRef,
+    edits: &'a BlockEdits,
+) -> Result<Vec<Checked<'a>>, Error> {
+    // the parent of each block, by id
+    let mut parents = HashMap::new();
+    let mut stack = fragment
+        .children(txn)
+        .map(|node| (node, None))
+        .collect::<Vec<_>>();
+    while let Some((node, parent)) = stack.pop() {
+        let XmlOut::Element(elem) = node else {
+            continue;
+        };
+        let mut parent = parent;
+        if elem.tag().as_ref() == "blockContainer" {
+            if let Some(id) = elem.get_attribute(txn, "id") {
+                parents.insert(id.clone(), parent);
+                parent = Some(id);
+            }
+        }
+        stack.extend(elem.children(txn).map(|child| (child, parent.clone())));
+    }
+
+    let exists = |parents: &HashMap<String, Option<String>>, id: &str| {
+        if parents.contains_key(id) {
+            Ok(())
+        } else {
+            Err(Error::BlockNotFound { id: id.to_string() })
+        }
+    };
+
+    let mut checked = Vec::new();
+    for edit in &edits.edits {
+        checked.push(match edit {
+            BlockEdit::UpdateProps { id, props } => {
+                exists(&parents, id)?;
+                let Value::Object(props) = props else {
+                    return Err(Error::InvalidBlocks("expected an object of props".into()));
+                };
+                let props = props
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = match value {
+                            Value::Null => None,
+                            value => Some(import::to_any(value).map_err(|e| {
+                                Error::InvalidBlocks(format!("invalid prop {}: {}", name, e))
+                            })?),
+                        };
+                        Ok((name.as_str(), value))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Checked::UpdateProps(id, props)
+            }
+            BlockEdit::ReplaceContent { id, content } => {
+                exists(&parents, id)?;
+                let content = import::parse_content(content).map_err(Error::InvalidBlocks)?;
+                Checked::ReplaceContent(id, content)
+            }
+            BlockEdit::Insert { id, place, blocks } => {
+                exists(&parents, id)?;
+                let blocks = import::parse_blocks(blocks).map_err(Error::InvalidBlocks)?;
+                let parent = parents[id.as_str()].clone();
+                add_ids(&mut parents, &blocks, parent)?;
+                Checked::Insert(id, *place, blocks)
+            }
+            BlockEdit::Move { id, place, target } => {
+                exists(&parents, id)?;
+                exists(&parents, target)?;
+                if is_within(&parents, target, id) {
+                    return Err(Error::InvalidEdit(format!(
+                        "block {} can't be moved next to {}, which is itself or nested in it",
+                        id, target
+                    )));
+                }
+                let parent = parents[target.as_str()].clone();
+                parents.insert(id.clone(), parent);
+                Checked::Move(id, *place, target)
+            }
+            BlockEdit::Delete { id } => {
+                exists(&parents, id)?;
+                let deleted = parents
+                    .keys()
+                    .filter(|other| is_within(&parents, other, id))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for other in deleted {
+                    parents.remove(&other);
+                }
+                Checked::Delete(id)
+            }
+        });
+    }
+
+    Ok(checked)
+}
+
+fn add_ids(
+    parents: &mut HashMap<String, Option<String>>,
+    blocks: &[Block],
+    parent: Option<String>,
+) -> Result<(), Error> {
+    for block in blocks {
+        if block.id.is_empty() {
+            add_ids(parents, &block.children, parent.clone())?;
+            continue;
+        }
+        if parents.contains_key(&block.id) {
+            return Err(Error::InvalidBlocks(format!(
+                "a block with id {} is already in the document",
+                block.id
+            )));
+        }
+        parents.insert(block.id.clone(), parent.clone());
+        add_ids(parents, &block.children, Some(block.id.clone()))?;
+    }
+
+    Ok(())
+}
+
+// Whether the block is `ancestor` or nested in it
+fn is_within(parents: &HashMap<String, Option<String>>, id: &str, ancestor: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut current = Some(id);
+    while let Some(id) = current {
+        if id == ancestor {
+            return true;
+        }
+        if !seen.insert(id) {
+            return false;
+        }
+        current = parents.get(id).and_then(|parent| parent.as_deref());
+    }
+
+    false
+}
+
+// Only called for blocks `check` has found
+fn container<T: ReadTxn>(txn: &T, fragment: &XmlFragmentRef, id: &str) -> XmlElementRef {
+    inspect::find_container(txn, fragment, id).expect("edits are checked before they're made")
+}
+
+// The element holding a block's type, props and content
+pub(crate) fn block_element<T: ReadTxn>(
+    txn: &T,
+    container: &XmlElementRef,
+) -> Option<XmlElementRef> {
+    container.children(txn).find_map(|node| match node {
+        XmlOut::Element(elem) if elem.tag().as_ref() != "blockGroup" => Some(elem),
+        _ => None,
+    })
+}
+
+// The blockGroup holding the block, and the index to insert at to put blocks before or after it
+fn position<T: ReadTxn>(
+    txn: &T,
+    fragment: &XmlFragmentRef,
+    id: &str,
+    place: Place,
+) -> (XmlElementRef, u32) {
+    let container = container(txn, fragment, id);
+    let (group, index) = index_in_parent(txn, &container);
+    match place {
+        Place::Before => (group, index),
+        Place::After => (group, index + 1),
+    }
+}
+
+pub(crate) fn index_in_parent<T: ReadTxn>(
+    txn: &T,
+    container: &XmlElementRef,
+) -> (XmlElementRef, u32) {
+    let Some(XmlOut::Element(group)) = container.parent() else {
+        unreachable!("blockContainers are always in a blockGroup")
+    };
+    let id = AsRef::<Branch>::as_ref(container).id();
+    let index = group
+        .children(txn)
+        .position(|child| child.id() == id)
+        .expect("an element is one of its parent's children");
+
+    (group, index as u32)
+}
+
+// Removes the block, and the blockGroup it was in if it was the last block nested in another.
+// The top-level blockGroup is kept even when it's emptied, so the fragment still converts.
+pub(crate) fn remove(txn: &mut TransactionMut, container: &XmlElementRef) {
+    let (group, index) = index_in_parent(txn, container);
+    group.remove_range(txn, index, 1);
+
+    if group.len(txn) == 0 {
+        if let Some(XmlOut::Element(parent)) = group.parent() {
+            let (_, index) = index_in_parent(txn, &group);
+            parent.remove_range(txn, index, 1);
+        }
+    }
+}
+
+// A copy of part of the tree, to write back elsewhere. `XmlElementRef::as_prelim` would turn
+// every attribute into a string.
+pub(crate) enum Copied {
+    Element {
+        tag: Arc<str>,
+        attributes: Vec<(String, Any)>,
+        children: Vec<Copied>,
+    },
+    Text(Vec<(String, Attrs)>),
+}
+
+impl Copied {
+    pub(crate) fn of<T: ReadTxn>(txn: &T, node: XmlOut) -> Self {
+        match node {
+            XmlOut::Element(elem) => Copied::Element {
+                tag: elem.tag().clone(),
+                attributes: import::read_attributes(txn, &elem),
+                children: elem
+                    .children(txn)
+                    .map(|child| Copied::of(txn, child))
+                    .collect(),
+            },
+            XmlOut::Text(text) => Copied::Text(
+                text.diff(txn, YChange::identity)
+                    .into_iter()
+                    .filter_map(|diff| match diff.insert {
+                        Out::Any(Any::String(s)) => Some((
+                            s.to_string(),
+                            diff.attributes.map(|a| *a).unwrap_or_default(),
+                        )),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            XmlOut::Fragment(_) => unreachable!("fragments are only ever at the root"),
+        }
+    }
+
+    pub(crate) fn insert(self, txn: &mut TransactionMut, parent: &XmlElementRef, index: u32) {
+        match self {
+            Copied::Element {
+                tag,
+                attributes,
+                children,
+            } => {
+                let elem = parent.insert(txn, index, XmlElementPrelim::empty(tag));
+                let map = import::attributes(&elem);
+                for (name, value) in attributes {
+                    map.insert(txn, name, value);
+                }
+                for (i, child) in children.into_iter().enumerate() {
+                    child.insert(txn, &elem, i as u32);
+                }
+            }
+            Copied::Text(runs) => {
+                let text = parent.insert(txn, index, XmlTextPrelim::new(""));
+                for (chunk, attributes) in runs {
+                    let len = text.len(txn);
+                    text.insert_with_attributes(txn, len, &chunk, attributes);
+                }
+            }
+        }
+    }
+}
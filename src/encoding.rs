@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+
+use roxmltree::{Attribute, Node};
+use serde_json::Value;
+
+// XML attributes can only hold strings, so attributes that were stored in yrs as
+// numbers, booleans, arrays or maps are written in this namespace with a JSON-encoded
+// value. Consumers that don't know about the namespace still see the local name.
+pub(crate) const TYPED_NAMESPACE: &str = "https://atuin.sh/ns/ydoc-typed";
+pub(crate) const TYPED_PREFIX: &str = "ydoc";
+
+// Yjs places no restrictions on element names, attribute names or text, but XML does.
+// Anything that can't be represented directly is written in this namespace instead:
+// * `<yenc:element yenc:name="...">` for elements whose tag isn't a valid XML name
+// * `<yenc:text value="..."/>` for text containing characters XML doesn't allow
+// * `yenc:attributes="{...}"` holding every attribute whose name isn't a valid XML name
+// All values are JSON-encoded.
+pub(crate) const ENCODED_NAMESPACE: &str = "https://atuin.sh/ns/ydoc-encoded";
+pub(crate) const ENCODED_PREFIX: &str = "yenc";
+
+pub(crate) fn value(attr: &Attribute) -> Value {
+    if attr.namespace() == Some(TYPED_NAMESPACE) {
+        if let Ok(value) = serde_json::from_str(attr.value()) {
+            return value;
+        }
+    }
+
+    Value::String(attr.value().to_string())
+}
+
+/// Returns the element's attributes, decoding typed and encoded attributes.
+pub(crate) fn attributes(node: Node) -> Vec<(String, Value)> {
+    let mut attributes = Vec::new();
+    for attr in node.attributes() {
+        if attr.namespace() != Some(ENCODED_NAMESPACE) {
+            attributes.push((attr.name().to_string(), value(&attr)));
+        } else if attr.name() == "attributes" {
+            if let Ok(Value::Object(map)) = serde_json::from_str(attr.value()) {
+                attributes.extend(map);
+            }
+        }
+    }
+
+    attributes
+}
+
+/// Returns the value as a string, without quoting it if it already is one.
+pub(crate) fn into_string(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// Returns the element's tag name, decoding `yenc:element`.
+pub(crate) fn tag_name<'a>(node: Node<'a, '_>) -> Cow<'a, str> {
+    if node.tag_name().namespace() == Some(ENCODED_NAMESPACE) && node.tag_name().name() == "element"
+    {
+        if let Some(Value::String(name)) = node
+            .attribute((ENCODED_NAMESPACE, "name"))
+            .and_then(|name| serde_json::from_str(name).ok())
+        {
+            return Cow::Owned(name);
+        }
+    }
+
+    Cow::Borrowed(node.tag_name().name())
+}
+
+/// Returns the text held by a `yenc:text` element, or `None` for any other node.
+pub(crate) fn text(node: Node) -> Option<String> {
+    if node.tag_name().namespace() == Some(ENCODED_NAMESPACE) && node.tag_name().name() == "text" {
+        if let Some(Value::String(text)) = node
+            .attribute("value")
+            .and_then(|value| serde_json::from_str(value).ok())
+        {
+            return Some(text);
+        }
+    }
+
+    None
+}
+
+/// Whether the character may appear in an XML 1.0 document, escaped or otherwise.
+pub(crate) fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+/// Whether the name can be written as-is as an element or attribute name. Colons are
+/// rejected since they would be interpreted as (undeclared) namespace prefixes, as is
+/// `xmlns`, which would be interpreted as a namespace declaration.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+
+    is_name_start_char(first) && chars.all(is_name_char) && name != "xmlns"
+}
+
+fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}'
+        | '\u{D8}'..='\u{F6}'
+        | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}'
+        | '\u{37F}'..='\u{1FFF}'
+        | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}'
+        | '\u{2C00}'..='\u{2FEF}'
+        | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}'
+        | '\u{FDF0}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{EFFFF}')
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9'
+            | '\u{B7}'
+            | '\u{300}'..='\u{36F}'
+            | '\u{203F}'..='\u{2040}')
+}
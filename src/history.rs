@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+use yrs::branch::Branch;
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::{Encoder, EncoderV1};
+use yrs::{
+    Doc, OffsetKind, Options, ReadTxn, Snapshot, StateVector, Text, Transact, Update, XmlTextRef,
+};
+
+use crate::{
+    converter::{self, Error},
+    inspect,
+    options::ConvertOptions,
+    serialize::{self, XmlOptions},
+};
+
+pub(crate) fn get_fragment_xml_at(
+    doc: &Doc,
+    snapshot: &Snapshot,
+    fragment_name: &str,
+    options: &XmlOptions,
+) -> Result<String, Error> {
+    serialize::try_get_fragment_xml(&doc_at(doc, snapshot)?, fragment_name, options)
+}
+
+pub(crate) fn convert_at(
+    doc: &Doc,
+    snapshot: &Snapshot,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<serde_json::Value, Error> {
+    let xml = get_fragment_xml_at(doc, snapshot, fragment_name, &XmlOptions::default())?;
+    converter::convert(&xml, options)
+}
+
+/// Converts the fragment as it was at `to`, marking text inserted or deleted since `from` with
+/// `insertion` and `deletion` styles, and blocks added or removed since `from` with a
+/// `"change": "added"` or `"change": "removed"` field. Removed blocks are put back after the
+/// block that preceded them.
+pub(crate) fn convert_changes(
+    doc: &Doc,
+    from: &Snapshot,
+    to: &Snapshot,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<Value, Error> {
+    // a fragment that didn't exist yet is the same as an empty one
+    let old = match serialize::try_get_fragment_xml(
+        &doc_at(doc, from)?,
+        fragment_name,
+        &XmlOptions::default(),
+    ) {
+        Ok(xml) => converter::convert(&xml, options)?,
+        Err(Error::FragmentNotFound { .. }) => Value::Array(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    // The structure comes from the document as it was at `to`, but its text nodes are diffed
+    // in a copy of the whole document, which still has the deleted text. Diffing splits items,
+    // so it's done in the copy to leave the caller's document (and its observers) alone.
+    let new = doc_at(doc, to)?;
+    let copy = doc_copy(doc, doc.offset_kind())?;
+    let xml = {
+        let new_txn = new.transact();
+        let fragment = inspect::find_fragment(&new_txn, fragment_name)?;
+        let mut txn = copy.transact_mut();
+        let mut changes =
+            |text: &XmlTextRef| match AsRef::<Branch>::as_ref(text).id().get_branch(&txn) {
+                Some(branch) => XmlTextRef::from(branch).diff_range(
+                    &mut txn,
+                    Some(to),
+                    Some(from),
+                    serialize::change_mark,
+                ),
+                None => text.diff(&new_txn, serialize::change_mark),
+            };
+
+        let mut xml = String::new();
+        serialize::write_fragment_marked(
+            fragment,
+            &new_txn,
+            &mut xml,
+            &XmlOptions::default(),
+            &mut changes,
+        )
+        .expect("writing to a String cannot fail");
+        xml
+    };
+
+    let mut blocks = converter::convert(&xml, options)?;
+    if let (Value::Array(blocks), Value::Array(old)) = (&mut blocks, &old) {
+        let mut old_blocks = HashMap::new();
+        index_blocks(old, &mut old_blocks);
+        let mut new_ids = HashSet::new();
+        collect_ids(blocks, &mut new_ids);
+        mark_blocks(blocks, Some(old), &old_blocks, &new_ids);
+    }
+
+    Ok(blocks)
+}
+
+fn mark_blocks(
+    blocks: &mut Vec<Value>,
+    old: Option<&Vec<Value>>,
+    old_blocks: &HashMap<&str, &Value>,
+    new_ids: &HashSet<String>,
+) {
+    for block in blocks.iter_mut() {
+        let old_block = block_id(block).and_then(|id| old_blocks.get(id));
+        if block_id(block).is_some() && old_block.is_none() {
+            block["change"] = "added".into();
+        }
+        let old_children = old_block.and_then(|old_block| children(old_block));
+        if let Some(Value::Array(children)) = block.get_mut("children") {
+            mark_blocks(children, old_children, old_blocks, new_ids);
+        }
+    }
+
+    // blocks that were moved elsewhere aren't removed, so only put back the ones that are gone
+    let mut position = 0;
+    for old_block in old.into_iter().flatten() {
+        match block_id(old_block) {
+            Some(id) if new_ids.contains(id) => {
+                if let Some(i) = blocks.iter().position(|block| block_id(block) == Some(id)) {
+                    position = i + 1;
+                }
+            }
+            Some(_) => {
+                blocks.insert(position, removed(old_block, new_ids));
+                position += 1;
+            }
+            None => {}
+        }
+    }
+}
+
+fn removed(block: &Value, new_ids: &HashSet<String>) -> Value {
+    let mut block = block.clone();
+    block["change"] = "removed".into();
+    if let Some(content) = block.get_mut("content") {
+        mark_deleted(content);
+    }
+    if let Some(Value::Array(children)) = block.get_mut("children") {
+        *children = children
+            .iter()
+            .filter(|child| block_id(child).is_some_and(|id| !new_ids.contains(id)))
+            .map(|child| removed(child, new_ids))
+            .collect();
+    }
+
+    block
+}
+
+// Gives all the text in the content (including links and table cells) the `deletion` style
+fn mark_deleted(content: &mut Value) {
+    match content {
+        Value::Object(object) if object.get("type") == Some(&Value::from("text")) => {
+            let styles = object
+                .entry("styles")
+                .or_insert_with(|| Value::Object(Default::default()));
+            styles["deletion"] = true.into();
+        }
+        Value::Object(object) => object.values_mut().for_each(mark_deleted),
+        Value::Array(items) => items.iter_mut().for_each(mark_deleted),
+        _ => {}
+    }
+}
+
+fn index_blocks<'a>(blocks: &'a [Value], index: &mut HashMap<&'a str, &'a Value>) {
+    for block in blocks {
+        if let Some(id) = block_id(block) {
+            index.insert(id, block);
+        }
+        if let Some(children) = children(block) {
+            index_blocks(children, index);
+        }
+    }
+}
+
+fn collect_ids(blocks: &[Value], ids: &mut HashSet<String>) {
+    for block in blocks {
+        if let Some(id) = block_id(block) {
+            ids.insert(id.to_string());
+        }
+        if let Some(children) = children(block) {
+            collect_ids(children, ids);
+        }
+    }
+}
+
+fn block_id(block: &Value) -> Option<&str> {
+    block.get("id").and_then(Value::as_str)
+}
+
+fn children(block: &Value) -> Option<&Vec<Value>> {
+    block.get("children").and_then(Value::as_array)
+}
+
+// Copies the whole document into a new `Doc`, including the content of deleted items, so that
+// its history can be read from the copy. The copy counts text in `offset_kind`.
+pub(crate) fn doc_copy(doc: &Doc, offset_kind: OffsetKind) -> Result<Doc, Error> {
+    let update = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let update = Update::decode_v1(&update).map_err(|e| Error::InvalidUpdate(e.to_string()))?;
+    let copy = Doc::with_options(Options {
+        skip_gc: true,
+        offset_kind,
+        ..Options::default()
+    });
+    copy.transact_mut()
+        .apply_update(update)
+        .map_err(|e| Error::InvalidUpdate(e.to_string()))?;
+
+    Ok(copy)
+}
+
+// Rebuilds the document as it was at the snapshot in a new `Doc`, so that it can be serialized
+// the same way as the current state. Only possible if nothing has been garbage collected since.
+fn doc_at(doc: &Doc, snapshot: &Snapshot) -> Result<Doc, Error> {
+    let mut encoder = EncoderV1::new();
+    doc.transact()
+        .encode_state_from_snapshot(snapshot, &mut encoder)
+        .map_err(|_| Error::SnapshotUnavailable)?;
+
+    let update =
+        Update::decode_v1(&encoder.to_vec()).map_err(|e| Error::InvalidUpdate(e.to_string()))?;
+    let past = Doc::new();
+    past.transact_mut()
+        .apply_update(update)
+        .map_err(|e| Error::InvalidUpdate(e.to_string()))?;
+
+    Ok(past)
+}
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::Value;
+use yrs::branch::{Branch, BranchPtr};
+use yrs::{
+    Any, Doc, Map, MapRef, Out, ReadTxn, Text, Transact, TransactionMut, XmlElementPrelim,
+    XmlElementRef, XmlFragment, XmlTextPrelim, XmlTextRef,
+};
+
+use crate::{
+    block::Block,
+    content::{
+        basic::BasicContent,
+        style::Style,
+        table::{TableCell, TableContent},
+        Content,
+    },
+    converter::Error,
+    encoding,
+};
+
+// Block props that BlockNote stores on the blockContainer rather than the block's own element
+pub(crate) const CONTAINER_PROPS: &[&str] = &["textColor", "backgroundColor"];
+
+// The formatting marks applied to a run of text, keyed by mark name
+type Marks = HashMap<Arc<str>, Any>;
+
+/// Replaces the contents of the fragment with the given BlockNote blocks, stored the way
+/// y-prosemirror would store them. The JSON is checked before anything is written, so the
+/// fragment is left untouched if it isn't valid.
+pub(crate) fn set_fragment_blocks(
+    doc: &Doc,
+    fragment_name: &str,
+    blocks: &Value,
+) -> Result<(), Error> {
+    let blocks = parse_blocks(blocks).map_err(Error::InvalidBlocks)?;
+
+    let fragment = doc.get_or_insert_xml_fragment(fragment_name);
+    let mut txn = doc.transact_mut();
+    let len = fragment.len(&txn);
+    fragment.remove_range(&mut txn, 0, len);
+    let group = fragment.push_back(&mut txn, XmlElementPrelim::empty("blockGroup"));
+    write_blocks(&mut txn, &group, 0, &blocks);
+
+    Ok(())
+}
+
+pub(crate) fn parse_blocks(value: &Value) -> Result<Vec<Block>, String> {
+    let Value::Array(blocks) = value else {
+        return Err("expected an array of blocks".to_string());
+    };
+
+    blocks.iter().map(parse_block).collect()
+}
+
+fn parse_block(value: &Value) -> Result<Block, String> {
+    let Value::Object(object) = value else {
+        return Err("expected a block object".to_string());
+    };
+
+    let mut block = Block::new();
+    block.type_name = match object.get("type") {
+        Some(Value::String(type_name)) => type_name.clone(),
+        _ => return Err("block with no type".to_string()),
+    };
+    match object.get("id") {
+        Some(Value::String(id)) => block.id = id.clone(),
+        None | Some(Value::Null) => {}
+        Some(_) => return Err(format!("{} block with a non-string id", block.type_name)),
+    }
+    match object.get("props") {
+        Some(Value::Object(props)) => {
+            for (name, value) in props {
+                check_prop(name, value)?;
+            }
+            block.props = props.clone();
+        }
+        None => {}
+        Some(_) => return Err(format!("{} block with non-object props", block.type_name)),
+    }
+
+    block.content = object.get("content").map(parse_content).transpose()?;
+    if let Some(children) = object.get("children") {
+        block.children = parse_blocks(children)?;
+    }
+
+    Ok(block)
+}
+
+pub(crate) fn parse_content(value: &Value) -> Result<Content, String> {
+    match value {
+        Value::Object(table) => Ok(Content::Table(parse_table(table)?)),
+        content => Ok(Content::Basic(parse_inline(content)?)),
+    }
+}
+
+fn parse_inline(value: &Value) -> Result<Vec<BasicContent>, String> {
+    let Value::Array(items) = value else {
+        return Err("expected an array of inline content".to_string());
+    };
+
+    items.iter().map(parse_inline_item).collect()
+}
+
+fn parse_inline_item(value: &Value) -> Result<BasicContent, String> {
+    let Value::Object(object) = value else {
+        return Err("expected an inline content object".to_string());
+    };
+
+    let mut content = BasicContent::new();
+    for (name, value) in object {
+        match (name.as_str(), value) {
+            ("type", Value::String(type_name)) => content.type_name = type_name.clone(),
+            ("type", _) => return Err("inline content with a non-string type".to_string()),
+            ("styles", Value::Object(styles)) => {
+                for (style, value) in styles {
+                    match (style.as_str(), value) {
+                        (_, Value::Bool(false)) => {}
+                        ("textColor", Value::String(color)) => {
+                            content.styles.push(Style::TextColor(color.clone()))
+                        }
+                        ("backgroundColor", Value::String(color)) => {
+                            content.styles.push(Style::BackgroundColor(color.clone()))
+                        }
+                        ("author", Value::String(client)) => {
+                            content.styles.push(Style::Author(client.clone()))
+                        }
+                        (style, Value::Bool(true)) => content.styles.push(style.try_into()?),
+                        (style, _) => return Err(format!("invalid value for style {}", style)),
+                    }
+                }
+            }
+            ("styles", _) => return Err("inline content with non-object styles".to_string()),
+            ("content", content_items) if content_items.is_array() => {
+                content.content = Some(parse_inline(content_items)?)
+            }
+            (name, value) => {
+                check_prop(name, value)?;
+                content.props.insert(name.to_string(), value.clone());
+            }
+        }
+    }
+    if content.type_name.is_empty() {
+        return Err("inline content with no type".to_string());
+    }
+
+    Ok(content)
+}
+
+fn parse_table(object: &serde_json::Map<String, Value>) -> Result<TableContent, String> {
+    if object.get("type") != Some(&Value::String("tableContent".to_string())) {
+        return Err("block content object that isn't tableContent".to_string());
+    }
+
+    let column_widths = match object.get("columnWidths") {
+        Some(Value::Array(widths)) => widths.iter().map(Value::as_u64).collect(),
+        _ => Vec::new(),
+    };
+    let Some(Value::Array(rows)) = object.get("rows") else {
+        return Err("tableContent with no rows".to_string());
+    };
+
+    let mut table = Vec::new();
+    for row in rows {
+        let Some(Value::Array(cells)) = row.get("cells") else {
+            return Err("table row with no cells".to_string());
+        };
+
+        let mut table_row = Vec::new();
+        for (i, cell) in cells.iter().enumerate() {
+            let mut table_cell = parse_table_cell(cell)?;
+            table_cell.colwidth = column_widths
+                .get(i)
+                .copied()
+                .flatten()
+                .and_then(|width| u32::try_from(width).ok());
+            table_row.push(table_cell);
+        }
+        table.push(table_row);
+    }
+
+    Ok(TableContent::new(table))
+}
+
+// Cells are either an array of inline content (BlockNote 0.22) or a tableCell object (0.23)
+fn parse_table_cell(value: &Value) -> Result<TableCell, String> {
+    let mut cell = TableCell::new();
+    let content = match value {
+        Value::Object(object) => {
+            if let Some(Value::Object(props)) = object.get("props") {
+                for (name, value) in props {
+                    let span = || {
+                        value
+                            .as_u64()
+                            .and_then(|span| u32::try_from(span).ok())
+                            .ok_or_else(|| format!("Invalid {}", name))
+                    };
+                    match name.as_str() {
+                        "colspan" => cell.colspan = span()?,
+                        "rowspan" => cell.rowspan = span()?,
+                        _ => {
+                            check_prop(name, value)?;
+                            cell.props.insert(name.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+            object
+                .get("content")
+                .cloned()
+                .unwrap_or(Value::Array(Vec::new()))
+        }
+        content => content.clone(),
+    };
+    cell.content = parse_inline(&content)?;
+
+    Ok(cell)
+}
+
+// Writes the blocks into the blockGroup, starting at the given index among its children
+pub(crate) fn write_blocks(
+    txn: &mut TransactionMut,
+    group: &XmlElementRef,
+    index: u32,
+    blocks: &[Block],
+) {
+    for (i, block) in blocks.iter().enumerate() {
+        let container = group.insert(
+            txn,
+            index + i as u32,
+            XmlElementPrelim::empty("blockContainer"),
+        );
+        let container_attributes = attributes(&container);
+        if !block.id.is_empty() {
+            container_attributes.insert(txn, "id", block.id.clone());
+        }
+
+        for (name, value) in block.props.iter() {
+            if CONTAINER_PROPS.contains(&name.as_str()) {
+                container_attributes.insert(txn, name.clone(), any(value));
+            }
+        }
+        write_block_element(txn, &container, 0, block);
+
+        if !block.children.is_empty() {
+            let children = container.push_back(txn, XmlElementPrelim::empty("blockGroup"));
+            write_blocks(txn, &children, 0, &block.children);
+        }
+    }
+}
+
+// Writes the element holding the block's type, its props other than the blockContainer's, and
+// its content into the blockContainer at the given index
+pub(crate) fn write_block_element(
+    txn: &mut TransactionMut,
+    container: &XmlElementRef,
+    index: u32,
+    block: &Block,
+) {
+    let elem = container.insert(txn, index, XmlElementPrelim::empty(block.type_name.clone()));
+    let elem_attributes = attributes(&elem);
+    for (name, value) in block.props.iter() {
+        if !CONTAINER_PROPS.contains(&name.as_str()) {
+            elem_attributes.insert(txn, name.clone(), any(value));
+        }
+    }
+
+    if let Some(content) = &block.content {
+        write_content(txn, &elem, content);
+    }
+}
+
+pub(crate) fn write_content(txn: &mut TransactionMut, elem: &XmlElementRef, content: &Content) {
+    match content {
+        Content::Basic(content) => write_inline(txn, elem, content),
+        Content::Table(table) => write_table(txn, elem, table),
+    }
+}
+
+fn write_table(txn: &mut TransactionMut, elem: &XmlElementRef, table: &TableContent) {
+    for row in table.rows() {
+        let row_elem = elem.push_back(txn, XmlElementPrelim::empty("tableRow"));
+        for cell in row {
+            let cell_elem = row_elem.push_back(txn, XmlElementPrelim::empty("tableCell"));
+            let cell_attributes = attributes(&cell_elem);
+            cell_attributes.insert(txn, "colspan", cell.colspan);
+            cell_attributes.insert(txn, "rowspan", cell.rowspan);
+            if let Some(colwidth) = cell.colwidth {
+                cell_attributes.insert(txn, "colwidth", vec![colwidth]);
+            }
+            for (name, value) in cell.props.iter() {
+                cell_attributes.insert(txn, name.clone(), any(value));
+            }
+
+            let paragraph = cell_elem.push_back(txn, XmlElementPrelim::empty("tableParagraph"));
+            write_inline(txn, &paragraph, &cell.content);
+        }
+    }
+}
+
+// Text (including links, which are marks in y-prosemirror) goes into a shared Y.XmlText,
+// while any other inline content gets an element of its own
+fn write_inline(txn: &mut TransactionMut, elem: &XmlElementRef, content: &[BasicContent]) {
+    let mut text = None;
+    write_inline_items(txn, elem, &mut text, content, &mut Marks::new());
+}
+
+fn write_inline_items(
+    txn: &mut TransactionMut,
+    elem: &XmlElementRef,
+    text: &mut Option<XmlTextRef>,
+    content: &[BasicContent],
+    marks: &mut Marks,
+) {
+    for item in content {
+        match item.type_name.as_str() {
+            "text" => {
+                let mut marks = marks.clone();
+                for style in item.styles.iter() {
+                    let attributes = match style {
+                        Style::TextColor(value)
+                        | Style::BackgroundColor(value)
+                        | Style::Author(value) => {
+                            HashMap::from([("stringValue".to_string(), Any::from(value.clone()))])
+                        }
+                        _ => HashMap::new(),
+                    };
+                    marks.insert(style.name().into(), Any::Map(attributes.into()));
+                }
+
+                let value = item.props.get("text").cloned().unwrap_or_default();
+                let text = text.get_or_insert_with(|| elem.push_back(txn, XmlTextPrelim::new("")));
+                let index = text.len(txn);
+                text.insert_with_attributes(txn, index, &encoding::into_string(value), marks);
+            }
+            "link" => {
+                let attributes = item
+                    .props
+                    .iter()
+                    .map(|(name, value)| (name.clone(), any(value)))
+                    .collect::<HashMap<_, _>>();
+                let name: Arc<str> = "link".into();
+                marks.insert(name.clone(), Any::Map(attributes.into()));
+                write_inline_items(
+                    txn,
+                    elem,
+                    text,
+                    item.content.as_deref().unwrap_or_default(),
+                    marks,
+                );
+                marks.remove(&name);
+            }
+            type_name => {
+                *text = None;
+                let inline_elem = elem.push_back(txn, XmlElementPrelim::empty(type_name));
+                let inline_attributes = attributes(&inline_elem);
+                for (name, value) in item.props.iter() {
+                    inline_attributes.insert(txn, name.clone(), any(value));
+                }
+                if let Some(content) = &item.content {
+                    write_inline(txn, &inline_elem, content);
+                }
+            }
+        }
+    }
+}
+
+// `Xml::insert_attribute` only takes strings, so write through the underlying map to keep
+// the props' types
+pub(crate) fn attributes(elem: &XmlElementRef) -> MapRef {
+    MapRef::from(BranchPtr::from(AsRef::<Branch>::as_ref(elem)))
+}
+
+// Reads the element's attributes through the underlying map too, since `Xml::attributes`
+// would turn them into strings. Anything that isn't a plain value is read as a string.
+pub(crate) fn read_attributes<T: ReadTxn>(txn: &T, elem: &XmlElementRef) -> Vec<(String, Any)> {
+    attributes(elem)
+        .iter(txn)
+        .map(|(name, value)| match value {
+            Out::Any(any) => (name.to_string(), any),
+            other => (name.to_string(), Any::String(other.to_string(txn).into())),
+        })
+        .collect()
+}
+
+/// Converts a prop to the value stored in the document. Fails for integers too large for an
+/// i64, which yrs can't store.
+pub(crate) fn to_any(value: &Value) -> Result<Any, String> {
+    Ok(match value {
+        Value::Null => Any::Null,
+        Value::Bool(b) => Any::Bool(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => Any::from(i),
+            (None, Some(f)) if !n.is_u64() => Any::from(f),
+            _ => return Err(format!("number {} is out of range", n)),
+        },
+        Value::String(s) => Any::from(s.as_str()),
+        Value::Array(items) => Any::Array(
+            items
+                .iter()
+                .map(to_any)
+                .collect::<Result<Vec<_>, _>>()?
+                .into(),
+        ),
+        Value::Object(object) => Any::Map(Arc::new(
+            object
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), to_any(value)?)))
+                .collect::<Result<HashMap<_, _>, String>>()?,
+        )),
+    })
+}
+
+// Props are checked with `to_any` when they're parsed, so they can be written without failing
+// halfway through a transaction
+pub(crate) fn any(value: &Value) -> Any {
+    to_any(value).expect("props are checked when they're parsed")
+}
+
+fn check_prop(name: &str, value: &Value) -> Result<(), String> {
+    to_any(value)
+        .map(|_| ())
+        .map_err(|e| format!("invalid prop {}: {}", name, e))
+}
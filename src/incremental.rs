@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+use yrs::branch::{Branch, BranchID};
+use yrs::{
+    DeepObservable, Doc, ReadTxn, Subscription, Transact, XmlElementRef, XmlFragment, XmlOut,
+};
+
+use crate::{
+    converter::{self, Error},
+    inspect,
+    live::{self, Enclosing},
+    options::ConvertOptions,
+    serialize::{self, XmlOptions},
+};
+
+// A block's own JSON, without its children, as of a version of its blockContainer, along with
+// the length of its own XML
+struct CachedBlock {
+    version: u64,
+    block: Value,
+    bytes: usize,
+}
+
+// The XML of each blockGroup apart from the blocks in it
+const GROUP_BYTES: usize = "<blockGroup></blockGroup>".len();
+
+/// Converts a fragment over and over as its document changes, caching the JSON of each
+/// blockContainer by its Y item id and reusing it until the block's content changes.
+pub struct IncrementalConverter {
+    doc: Doc,
+    fragment_name: String,
+    options: ConvertOptions,
+    // bumped by the observer for each blockContainer whose own content changes
+    versions: Arc<Mutex<HashMap<BranchID, u64>>>,
+    cache: HashMap<BranchID, CachedBlock>,
+    converted: usize,
+    _subscription: Subscription,
+}
+
+impl IncrementalConverter {
+    pub fn new(doc: &Doc, fragment_name: &str, options: &ConvertOptions) -> Result<Self, Error> {
+        let fragment = {
+            let txn = doc.transact();
+            inspect::find_fragment(&txn, fragment_name)?
+        };
+
+        let versions = Arc::new(Mutex::new(HashMap::new()));
+        let observed = versions.clone();
+        let subscription = fragment.observe_deep(move |_, events| {
+            let mut versions = observed.lock().unwrap();
+            for node in events.iter().filter_map(live::event_node) {
+                // which blocks are where is read afresh on every conversion
+                if let XmlOut::Element(elem) = &node {
+                    if elem.tag().as_ref() == "blockGroup" {
+                        continue;
+                    }
+                }
+                if let Enclosing::Container(container) = live::enclosing_container(node) {
+                    *versions.entry(branch_id(&container)).or_default() += 1;
+                }
+            }
+        });
+
+        Ok(IncrementalConverter {
+            doc: doc.clone(),
+            fragment_name: fragment_name.to_string(),
+            options: options.clone(),
+            versions,
+            cache: HashMap::new(),
+            converted: 0,
+            _subscription: subscription,
+        })
+    }
+
+    pub fn options(&self) -> &ConvertOptions {
+        &self.options
+    }
+
+    /// Converts the fragment as it is now, only converting the blocks that changed since they
+    /// were last converted.
+    pub fn convert(&mut self) -> Result<Value, Error> {
+        let txn = self.doc.transact();
+        let fragment = inspect::find_fragment(&txn, &self.fragment_name)?;
+        let versions = self.versions.lock().unwrap().clone();
+
+        let mut walk = Walk {
+            txn: &txn,
+            options: &self.options,
+            versions: &versions,
+            cache: &mut self.cache,
+            seen: HashMap::new(),
+            blocks: 0,
+            bytes: 0,
+            converted: 0,
+        };
+        let groups = fragment.children(&txn).collect::<Vec<_>>();
+        let blocks = match walk.convert_groups(groups, 1)? {
+            Some(blocks) => blocks,
+            None => {
+                // let the whole conversion decide whether the document is within its limits
+                self.cache.clear();
+                self.converted = 0;
+                let mut xml = String::new();
+                serialize::write_fragment(fragment, &txn, &mut xml, &XmlOptions::default())
+                    .expect("writing to a String cannot fail");
+                return converter::convert(&xml, &self.options);
+            }
+        };
+
+        // forget blocks that are no longer in the fragment
+        self.converted = walk.converted;
+        self.cache = walk.seen;
+        self.versions
+            .lock()
+            .unwrap()
+            .retain(|id, _| self.cache.contains_key(id));
+        Ok(Value::Array(blocks))
+    }
+
+    /// Drops the cached JSON of the block with the given id, so that the next conversion
+    /// converts it again.
+    pub fn invalidate(&mut self, block_id: &str) {
+        self.cache
+            .retain(|_, cached| cached.block.get("id").and_then(Value::as_str) != Some(block_id));
+    }
+
+    /// Drops the cached JSON of every block.
+    pub fn invalidate_all(&mut self) {
+        self.cache.clear();
+    }
+
+    /// The number of blocks the last conversion converted rather than took from the cache.
+    pub fn converted(&self) -> usize {
+        self.converted
+    }
+}
+
+struct Walk<'a, T: ReadTxn> {
+    txn: &'a T,
+    options: &'a ConvertOptions,
+    versions: &'a HashMap<BranchID, u64>,
+    cache: &'a mut HashMap<BranchID, CachedBlock>,
+    // the cache for the blocks in the fragment now
+    seen: HashMap<BranchID, CachedBlock>,
+    blocks: usize,
+    // the length of the fragment's XML so far
+    bytes: usize,
+    converted: usize,
+}
+
+impl<T: ReadTxn> Walk<'_, T> {
+    // Converts the blocks in the given blockGroups, or returns None if the document goes over
+    // the nesting, size or block count limits
+    fn convert_groups(
+        &mut self,
+        groups: Vec<XmlOut>,
+        depth: usize,
+    ) -> Result<Option<Vec<Value>>, Error> {
+        if depth + 1 >= self.options.limits.max_depth {
+            return Ok(None);
+        }
+
+        let mut blocks = Vec::new();
+        for group in groups
+            .into_iter()
+            .filter_map(|node| inspect::element(node, "blockGroup"))
+        {
+            if self.add_bytes(GROUP_BYTES) {
+                return Ok(None);
+            }
+            for container in group
+                .children(self.txn)
+                .filter_map(|node| inspect::element(node, "blockContainer"))
+            {
+                self.blocks += 1;
+                if matches!(self.options.limits.max_blocks, Some(max) if self.blocks > max) {
+                    return Ok(None);
+                }
+
+                let nested = container
+                    .children(self.txn)
+                    .filter(|node| inspect::element(node.clone(), "blockGroup").is_some())
+                    .collect::<Vec<_>>();
+                let Some(mut block) = self.convert_container(container)? else {
+                    return Ok(None);
+                };
+                let Some(children) = self.convert_groups(nested, depth + 2)? else {
+                    return Ok(None);
+                };
+                if !children.is_empty() {
+                    block["children"] = Value::Array(children);
+                }
+                blocks.push(block);
+            }
+        }
+
+        Ok(Some(blocks))
+    }
+
+    // Counts XML towards the fragment's length, returning true if that goes over the limit
+    fn add_bytes(&mut self, bytes: usize) -> bool {
+        self.bytes += bytes;
+        matches!(self.options.limits.max_input_bytes, Some(max) if self.bytes > max)
+    }
+
+    // Converts the block without its children, or returns None if the document goes over the
+    // size limit
+    fn convert_container(&mut self, container: XmlElementRef) -> Result<Option<Value>, Error> {
+        let id = branch_id(&container);
+        let version = self.versions.get(&id).copied().unwrap_or_default();
+        if let Some(cached) = self.cache.remove(&id) {
+            if cached.version == version {
+                if self.add_bytes(cached.bytes) {
+                    return Ok(None);
+                }
+                let block = cached.block.clone();
+                self.seen.insert(id, cached);
+                return Ok(Some(block));
+            }
+        }
+
+        let mut xml = String::from("<blockGroup>");
+        serialize::write_element_shallow(container, self.txn, &mut xml, &XmlOptions::default())
+            .expect("writing to a String cannot fail");
+        xml.push_str("</blockGroup>");
+        let bytes = xml.len() - GROUP_BYTES;
+        if self.add_bytes(bytes) {
+            return Ok(None);
+        }
+        let block = match converter::convert(&xml, self.options)? {
+            Value::Array(mut blocks) if blocks.len() == 1 => blocks.remove(0),
+            _ => Value::Null,
+        };
+
+        self.converted += 1;
+        self.seen.insert(
+            id,
+            CachedBlock {
+                version,
+                block: block.clone(),
+                bytes,
+            },
+        );
+        Ok(Some(block))
+    }
+}
+
+fn branch_id(elem: &XmlElementRef) -> BranchID {
+    AsRef::<Branch>::as_ref(elem).id()
+}
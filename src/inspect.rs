@@ -0,0 +1,182 @@
+use serde_json::Value;
+use yrs::branch::BranchPtr;
+use yrs::types::{AsPrelim, ToJson};
+use yrs::{
+    Array, ArrayRef, GetString, In, Map, MapRef, Out, ReadTxn, TextRef, Transact, Xml,
+    XmlElementRef, XmlFragment, XmlFragmentRef, XmlOut,
+};
+
+use crate::converter::Error;
+
+/// The kind of a root-level shared type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SharedTypeKind {
+    XmlFragment,
+    Map,
+    Text,
+    Array,
+    /// A root that was received in an update but never accessed locally, and is empty, so there
+    /// is nothing to tell what it holds.
+    Unknown,
+}
+
+impl std::fmt::Display for SharedTypeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::XmlFragment => write!(f, "XmlFragment"),
+            Self::Map => write!(f, "Map"),
+            Self::Text => write!(f, "Text"),
+            Self::Array => write!(f, "Array"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// A root-level shared type in a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedTypeInfo {
+    pub name: String,
+    pub kind: SharedTypeKind,
+    /// The number of children of a fragment, entries of a map, characters of a text or items of
+    /// an array.
+    pub size: usize,
+}
+
+pub(crate) fn list_shared_types(doc: &yrs::Doc) -> Vec<SharedTypeInfo> {
+    let txn = doc.transact();
+    let mut types = txn
+        .root_refs()
+        .map(|(name, root)| {
+            let kind = kind(&txn, &root);
+            SharedTypeInfo {
+                name: name.to_string(),
+                kind,
+                size: size(&txn, &root, kind),
+            }
+        })
+        .collect::<Vec<_>>();
+    types.sort_by(|a, b| a.name.cmp(&b.name));
+
+    types
+}
+
+/// Looks up the named fragment without creating it, unlike `Doc::get_or_insert_xml_fragment`.
+pub(crate) fn find_fragment<T: ReadTxn>(
+    txn: &T,
+    fragment_name: &str,
+) -> Result<XmlFragmentRef, Error> {
+    let Some((_, root)) = txn.root_refs().find(|(name, _)| *name == fragment_name) else {
+        return Err(Error::FragmentNotFound {
+            name: fragment_name.to_string(),
+        });
+    };
+
+    match (kind(txn, &root), root) {
+        (_, Out::YXmlFragment(fragment)) => Ok(fragment),
+        // roots that haven't been accessed locally yet are still untyped
+        (SharedTypeKind::XmlFragment | SharedTypeKind::Unknown, Out::UndefinedRef(branch)) => {
+            Ok(XmlFragmentRef::from(branch))
+        }
+        (kind, _) => Err(Error::NotAFragment {
+            name: fragment_name.to_string(),
+            kind,
+        }),
+    }
+}
+
+/// Looks up the blockContainer with the given `id` attribute anywhere in the fragment.
+pub(crate) fn find_container<T: ReadTxn>(
+    txn: &T,
+    fragment: &XmlFragmentRef,
+    id: &str,
+) -> Option<XmlElementRef> {
+    let mut stack = fragment.children(txn).collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        if let XmlOut::Element(elem) = node {
+            if elem.tag().as_ref() == "blockContainer"
+                && elem.get_attribute(txn, "id").as_deref() == Some(id)
+            {
+                return Some(elem);
+            }
+            stack.extend(elem.children(txn));
+        }
+    }
+
+    None
+}
+
+/// The node as an element, if it is one with the given tag.
+pub(crate) fn element(node: XmlOut, tag: &str) -> Option<XmlElementRef> {
+    match node {
+        XmlOut::Element(elem) if elem.tag().as_ref() == tag => Some(elem),
+        _ => None,
+    }
+}
+
+pub(crate) fn xml_parent(node: &XmlOut) -> Option<XmlOut> {
+    match node {
+        XmlOut::Element(elem) => elem.parent(),
+        XmlOut::Fragment(fragment) => fragment.parent(),
+        XmlOut::Text(text) => text.parent(),
+    }
+}
+
+/// Returns the named root as JSON, without creating it: maps become objects, arrays become
+/// arrays, and text (or an XML fragment) becomes a string.
+pub(crate) fn root_to_json<T: ReadTxn>(txn: &T, name: &str) -> Option<Value> {
+    let (_, root) = txn.root_refs().find(|(root_name, _)| *root_name == name)?;
+    let branch = BranchPtr::from(root.try_branch()?);
+
+    let any = match kind(txn, &root) {
+        SharedTypeKind::Map => MapRef::from(branch).to_json(txn),
+        SharedTypeKind::Array => ArrayRef::from(branch).to_json(txn),
+        SharedTypeKind::Text => return Some(Value::String(TextRef::from(branch).get_string(txn))),
+        SharedTypeKind::XmlFragment => {
+            return Some(Value::String(XmlFragmentRef::from(branch).get_string(txn)))
+        }
+        SharedTypeKind::Unknown => return Some(Value::Null),
+    };
+    Some(serde_json::to_value(any).expect("yrs values always serialize to JSON"))
+}
+
+fn kind<T: ReadTxn>(txn: &T, root: &Out) -> SharedTypeKind {
+    match root {
+        Out::YXmlFragment(_) | Out::YXmlElement(_) => SharedTypeKind::XmlFragment,
+        Out::YMap(_) => SharedTypeKind::Map,
+        Out::YText(_) | Out::YXmlText(_) => SharedTypeKind::Text,
+        Out::YArray(_) => SharedTypeKind::Array,
+        Out::UndefinedRef(branch) => infer_kind(txn, *branch),
+        _ => SharedTypeKind::Unknown,
+    }
+}
+
+// Root types decoded from an update only get their type once they're accessed, so work it out
+// from their contents the way yrs does, telling arrays of XML nodes apart from other arrays
+fn infer_kind<T: ReadTxn>(txn: &T, branch: BranchPtr) -> SharedTypeKind {
+    match Out::UndefinedRef(branch).as_prelim(txn) {
+        In::Text(_) | In::XmlText(_) => SharedTypeKind::Text,
+        In::XmlElement(_) | In::XmlFragment(_) => SharedTypeKind::XmlFragment,
+        In::Map(_) if MapRef::from(branch).len(txn) == 0 => SharedTypeKind::Unknown,
+        In::Map(_) => SharedTypeKind::Map,
+        In::Array(_) => match ArrayRef::from(branch).iter(txn).next() {
+            Some(Out::YXmlElement(_) | Out::YXmlText(_)) => SharedTypeKind::XmlFragment,
+            _ => SharedTypeKind::Array,
+        },
+        _ => SharedTypeKind::Unknown,
+    }
+}
+
+fn size<T: ReadTxn>(txn: &T, root: &Out, kind: SharedTypeKind) -> usize {
+    let Some(branch) = root.try_branch().map(BranchPtr::from) else {
+        return 0;
+    };
+
+    match kind {
+        SharedTypeKind::XmlFragment => XmlFragmentRef::from(branch).len(txn) as usize,
+        SharedTypeKind::Map => MapRef::from(branch).len(txn) as usize,
+        SharedTypeKind::Text => TextRef::from(branch).get_string(txn).chars().count(),
+        SharedTypeKind::Array => ArrayRef::from(branch).len(txn) as usize,
+        SharedTypeKind::Unknown => 0,
+    }
+}
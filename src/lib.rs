@@ -1,24 +1,341 @@
+mod attribution;
+mod batch;
 mod block;
 mod content;
 mod converter;
+mod diagnostic;
+mod diff;
+mod document;
+mod edit;
+mod encoding;
+mod history;
+mod import;
+mod incremental;
+mod inspect;
+mod limits;
+mod live;
+mod merge;
+mod options;
+mod position;
+mod reusable;
 mod serialize;
+mod validate;
 
-pub use converter::Error;
+pub use attribution::Attribution;
+pub use batch::{BatchInput, BatchOptions, BatchResult, BatchStats};
+pub use converter::{Error, ErrorKind};
+pub use diagnostic::{Diagnostic, Severity};
+pub use diff::{BlockChange, BlockPosition, PropChange, TextChange};
+pub use document::DocumentOptions;
+pub use edit::BlockEdits;
+pub use incremental::IncrementalConverter;
+pub use inspect::{SharedTypeInfo, SharedTypeKind};
+pub use limits::{LimitKind, Limits, DEFAULT_MAX_DEPTH};
+pub use live::{BlockEvent, LiveDocument};
+pub use merge::{Conflict, ConflictPolicy, MergeOptions, MergeResult, Side};
+pub use options::{BlockNoteVersion, ConvertOptions};
+pub use position::{BlockOffset, OffsetUnit};
+pub use reusable::ReusableConverter;
+pub use serialize::XmlOptions;
 
 pub fn convert_to_value(xml: String) -> Result<serde_json::Value, Error> {
-    converter::convert(xml)
+    converter::convert(&xml, &ConvertOptions::default())
+}
+
+pub fn convert_to_value_with_limits(
+    xml: String,
+    limits: &Limits,
+) -> Result<serde_json::Value, Error> {
+    converter::convert(&xml, &ConvertOptions::new().limits(limits.clone()))
 }
 
 pub fn convert_to_json(xml: String) -> Result<String, Error> {
-    let val = convert_to_value(xml)?;
-    Ok(serde_json::to_string(&val).unwrap())
+    convert_to_json_with(xml, &ConvertOptions::default())
 }
 
 pub fn convert_to_json_pretty(xml: String) -> Result<String, Error> {
-    let val = convert_to_value(xml)?;
-    Ok(serde_json::to_string_pretty(&val).unwrap())
+    convert_to_json_pretty_with(xml, &ConvertOptions::default())
+}
+
+pub fn convert_to_writer<W: std::io::Write>(xml: String, writer: W) -> Result<(), Error> {
+    converter::convert_to_writer(&xml, writer, &ConvertOptions::default(), false)
+}
+
+pub fn convert_to_value_with(
+    xml: String,
+    options: &ConvertOptions,
+) -> Result<serde_json::Value, Error> {
+    converter::convert(&xml, options)
+}
+
+pub fn convert_to_json_with(xml: String, options: &ConvertOptions) -> Result<String, Error> {
+    let mut json = Vec::new();
+    converter::convert_to_writer(&xml, &mut json, options, false)?;
+    Ok(String::from_utf8(json).expect("serde_json always writes UTF-8"))
+}
+
+pub fn convert_to_json_pretty_with(xml: String, options: &ConvertOptions) -> Result<String, Error> {
+    let mut json = Vec::new();
+    converter::convert_to_writer(&xml, &mut json, options, true)?;
+    Ok(String::from_utf8(json).expect("serde_json always writes UTF-8"))
+}
+
+pub fn convert_to_writer_with<W: std::io::Write>(
+    xml: String,
+    writer: W,
+    options: &ConvertOptions,
+) -> Result<(), Error> {
+    converter::convert_to_writer(&xml, writer, options, false)
+}
+
+pub fn convert_str_to_value(xml: &str) -> Result<serde_json::Value, Error> {
+    converter::convert(xml, &ConvertOptions::default())
+}
+
+pub fn convert_str_to_json(xml: &str) -> Result<String, Error> {
+    let mut json = Vec::new();
+    converter::convert_to_writer(xml, &mut json, &ConvertOptions::default(), false)?;
+    Ok(String::from_utf8(json).expect("serde_json always writes UTF-8"))
+}
+
+pub fn convert_bytes_to_value(xml: &[u8]) -> Result<serde_json::Value, Error> {
+    convert_str_to_value(std::str::from_utf8(xml).map_err(Error::InvalidUtf8)?)
+}
+
+pub fn convert_bytes_to_json(xml: &[u8]) -> Result<String, Error> {
+    convert_str_to_json(std::str::from_utf8(xml).map_err(Error::InvalidUtf8)?)
+}
+
+pub fn convert_batch<I>(inputs: I, options: &BatchOptions) -> BatchResult
+where
+    I: IntoIterator,
+    I::Item: Into<BatchInput>,
+{
+    batch::convert_batch(inputs, options)
+}
+
+pub fn convert_document(
+    doc: &yrs::Doc,
+    options: &DocumentOptions,
+) -> Result<serde_json::Value, Error> {
+    document::convert_document(doc, options)
+}
+
+pub fn convert_document_to_json(
+    doc: &yrs::Doc,
+    options: &DocumentOptions,
+) -> Result<String, Error> {
+    let document = document::convert_document(doc, options)?;
+    Ok(serde_json::to_string(&document).unwrap())
+}
+
+pub fn diff_blocks(
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+) -> Result<Vec<BlockChange>, Error> {
+    diff::diff_blocks(old, new)
+}
+
+pub fn merge_blocks(
+    base: &serde_json::Value,
+    ours: &serde_json::Value,
+    theirs: &serde_json::Value,
+    options: &MergeOptions,
+) -> Result<MergeResult, Error> {
+    merge::merge_blocks(base, ours, theirs, options)
+}
+
+pub fn merge_into_fragment(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    base: &serde_json::Value,
+    theirs: &serde_json::Value,
+    options: &MergeOptions,
+) -> Result<Vec<Conflict>, Error> {
+    merge::merge_into_fragment(doc, fragment_name, base, theirs, options)
+}
+
+pub fn convert_to_value_lenient(
+    xml: String,
+) -> Result<(serde_json::Value, Vec<Diagnostic>), Error> {
+    convert_to_value_lenient_with(xml, &ConvertOptions::default())
+}
+
+pub fn convert_to_value_lenient_with(
+    xml: String,
+    options: &ConvertOptions,
+) -> Result<(serde_json::Value, Vec<Diagnostic>), Error> {
+    converter::convert_lenient(&xml, options)
+}
+
+pub fn convert_to_json_lenient(xml: String) -> Result<(String, Vec<Diagnostic>), Error> {
+    let (val, diagnostics) = convert_to_value_lenient(xml)?;
+    Ok((serde_json::to_string(&val).unwrap(), diagnostics))
+}
+
+pub fn validate(xml: String) -> Result<Vec<Diagnostic>, Error> {
+    validate_with_limits(xml, &Limits::default())
+}
+
+pub fn validate_with_limits(xml: String, limits: &Limits) -> Result<Vec<Diagnostic>, Error> {
+    validate::validate(&xml, limits)
+}
+
+pub fn convert_to_value_strict(xml: String) -> Result<serde_json::Value, Error> {
+    let violations = validate::validate(&xml, &Limits::default())?;
+    if !violations.is_empty() {
+        return Err(Error::InvalidDocument(violations));
+    }
+
+    convert_to_value(xml)
 }
 
 pub fn get_fragment_xml(doc: yrs::Doc, fragment_name: String) -> String {
-    serialize::get_fragment_xml(doc, fragment_name)
+    serialize::get_fragment_xml(&doc, &fragment_name, &XmlOptions::default())
+}
+
+pub fn get_fragment_xml_with(doc: yrs::Doc, fragment_name: String, options: &XmlOptions) -> String {
+    serialize::get_fragment_xml(&doc, &fragment_name, options)
+}
+
+pub fn get_fragment_xml_ref(doc: &yrs::Doc, fragment_name: &str, options: &XmlOptions) -> String {
+    serialize::get_fragment_xml(doc, fragment_name, options)
+}
+
+pub fn set_fragment_blocks(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    blocks: &serde_json::Value,
+) -> Result<(), Error> {
+    import::set_fragment_blocks(doc, fragment_name, blocks)
+}
+
+pub fn find_block(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    id: &str,
+) -> Result<yrs::XmlElementRef, Error> {
+    edit::find_block(doc, fragment_name, id)
+}
+
+pub fn edit_blocks(doc: &yrs::Doc, fragment_name: &str, edits: &BlockEdits) -> Result<(), Error> {
+    edit::edit_blocks(doc, fragment_name, edits)
+}
+
+pub fn try_get_fragment_xml(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    options: &XmlOptions,
+) -> Result<String, Error> {
+    serialize::try_get_fragment_xml(doc, fragment_name, options)
+}
+
+pub fn find_fragment(doc: &yrs::Doc, fragment_name: &str) -> Result<yrs::XmlFragmentRef, Error> {
+    inspect::find_fragment(&yrs::Transact::transact(doc), fragment_name)
+}
+
+pub fn list_shared_types(doc: &yrs::Doc) -> Vec<SharedTypeInfo> {
+    inspect::list_shared_types(doc)
+}
+
+pub fn get_fragment_xml_at(
+    doc: &yrs::Doc,
+    snapshot: &yrs::Snapshot,
+    fragment_name: &str,
+) -> Result<String, Error> {
+    history::get_fragment_xml_at(doc, snapshot, fragment_name, &XmlOptions::default())
+}
+
+pub fn get_fragment_xml_at_with(
+    doc: &yrs::Doc,
+    snapshot: &yrs::Snapshot,
+    fragment_name: &str,
+    options: &XmlOptions,
+) -> Result<String, Error> {
+    history::get_fragment_xml_at(doc, snapshot, fragment_name, options)
+}
+
+pub fn convert_at(
+    doc: &yrs::Doc,
+    snapshot: &yrs::Snapshot,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<serde_json::Value, Error> {
+    history::convert_at(doc, snapshot, fragment_name, options)
+}
+
+pub fn convert_to_json_at(
+    doc: &yrs::Doc,
+    snapshot: &yrs::Snapshot,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<String, Error> {
+    let blocks = history::convert_at(doc, snapshot, fragment_name, options)?;
+    Ok(serde_json::to_string(&blocks).unwrap())
+}
+
+pub fn convert_changes(
+    doc: &yrs::Doc,
+    from: &yrs::Snapshot,
+    to: &yrs::Snapshot,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<serde_json::Value, Error> {
+    history::convert_changes(doc, from, to, fragment_name, options)
+}
+
+pub fn convert_changes_to_json(
+    doc: &yrs::Doc,
+    from: &yrs::Snapshot,
+    to: &yrs::Snapshot,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<String, Error> {
+    let blocks = history::convert_changes(doc, from, to, fragment_name, options)?;
+    Ok(serde_json::to_string(&blocks).unwrap())
+}
+
+pub fn convert_attributed(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<Attribution, Error> {
+    attribution::convert_attributed(doc, fragment_name, options)
+}
+
+pub fn sticky_to_block_offset(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    index: &yrs::StickyIndex,
+    unit: OffsetUnit,
+) -> Result<BlockOffset, Error> {
+    position::sticky_to_block_offset(doc, fragment_name, index, unit)
+}
+
+pub fn block_offset_to_sticky(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    position: &BlockOffset,
+    assoc: yrs::Assoc,
+    unit: OffsetUnit,
+) -> Result<yrs::StickyIndex, Error> {
+    position::block_offset_to_sticky(doc, fragment_name, position, assoc, unit)
+}
+
+pub fn write_fragment_xml<W: std::fmt::Write>(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    out: &mut W,
+    options: &XmlOptions,
+) -> std::fmt::Result {
+    serialize::write_fragment_xml(doc, fragment_name, out, options)
+}
+
+pub fn write_fragment_xml_io<W: std::io::Write>(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    out: W,
+    options: &XmlOptions,
+) -> std::io::Result<()> {
+    serialize::write_fragment_xml_io(doc, fragment_name, out, options)
 }
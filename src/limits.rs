@@ -0,0 +1,175 @@
+use crate::converter::Error;
+
+/// The deepest element nesting accepted by default. Each level of nested blocks takes two
+/// elements (a blockGroup and a blockContainer), so this allows blocks to be nested about 60
+/// deep, well beyond anything BlockNote's UI produces.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Bounds on the documents accepted by `convert_to_value_with_limits`,
+/// `ConvertOptions::limits` and `validate_with_limits`, to protect against untrusted input. Apart from the nesting depth, nothing is limited by default.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub(crate) max_depth: usize,
+    pub(crate) max_input_bytes: Option<usize>,
+    pub(crate) max_blocks: Option<usize>,
+    pub(crate) max_inline_items: Option<usize>,
+    pub(crate) max_table_cells: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_input_bytes: None,
+            max_blocks: None,
+            max_inline_items: None,
+            max_table_cells: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maximum element nesting depth, counting the root blockGroup as 1. Parsing and
+    /// conversion recurse for each level, so this bounds their stack usage.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The maximum length of the XML in bytes, checked before anything else is done with it.
+    pub fn max_input_bytes(mut self, max_input_bytes: usize) -> Self {
+        self.max_input_bytes = Some(max_input_bytes);
+        self
+    }
+
+    /// The maximum number of blocks in the document, including nested blocks.
+    pub fn max_blocks(mut self, max_blocks: usize) -> Self {
+        self.max_blocks = Some(max_blocks);
+        self
+    }
+
+    /// The maximum number of inline nodes (text, style tags and inline content such as links)
+    /// in any one block. For tables, this counts the content of all cells together.
+    pub fn max_inline_items(mut self, max_inline_items: usize) -> Self {
+        self.max_inline_items = Some(max_inline_items);
+        self
+    }
+
+    /// The maximum number of cells in any one table.
+    pub fn max_table_cells(mut self, max_table_cells: usize) -> Self {
+        self.max_table_cells = Some(max_table_cells);
+        self
+    }
+}
+
+/// The limit exceeded by a document rejected with `Error::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LimitKind {
+    Blocks,
+    InlineItems,
+    TableCells,
+}
+
+impl std::fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Blocks => write!(f, "blocks"),
+            Self::InlineItems => write!(f, "inline items in a block"),
+            Self::TableCells => write!(f, "cells in a table"),
+        }
+    }
+}
+
+pub(crate) fn check_input_bytes(xml: &str, max_input_bytes: Option<usize>) -> Result<(), Error> {
+    match max_input_bytes {
+        Some(max_bytes) if xml.len() > max_bytes => Err(Error::InputTooLarge {
+            max_bytes,
+            len: xml.len(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// roxmltree recurses for each nested element, so the depth has to be checked before parsing.
+// This only needs to agree with the parser on well-formed XML: the parser stops at the first
+// error, so it never descends past anything this could misread.
+pub(crate) fn check_depth(xml: &str, max_depth: usize) -> Result<(), Error> {
+    let bytes = xml.as_bytes();
+    let mut depth: usize = 0;
+    let mut i = 0;
+    while let Some(offset) = bytes[i..].iter().position(|b| *b == b'<') {
+        let start = i + offset;
+        let rest = &xml[start..];
+        let end = if rest.starts_with("<!--") {
+            find_after(xml, start, "-->")
+        } else if rest.starts_with("<![CDATA[") {
+            find_after(xml, start, "]]>")
+        } else if rest.starts_with("<?") {
+            find_after(xml, start, "?>")
+        } else if rest.starts_with("</") {
+            // unbalanced end tags are a parse error, so just avoid underflowing
+            depth = depth.saturating_sub(1);
+            find_after(xml, start, ">")
+        } else if rest.starts_with("<!") {
+            find_after(xml, start, ">")
+        } else {
+            let end = start_tag_end(bytes, start);
+            if end.is_some_and(|end| bytes[end - 2] != b'/') {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(Error::DepthLimitExceeded {
+                        max_depth,
+                        pos: text_pos(xml, start),
+                    });
+                }
+            }
+            end
+        };
+
+        match end {
+            Some(end) => i = end,
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the index just past the next `terminator` after `start`
+fn find_after(xml: &str, start: usize, terminator: &str) -> Option<usize> {
+    xml[start..]
+        .find(terminator)
+        .map(|offset| start + offset + terminator.len())
+}
+
+// Returns the index just past the `>` closing the start tag at `start`, skipping any in
+// attribute values
+fn start_tag_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, b) in bytes.iter().enumerate().skip(start) {
+        match (quote, *b) {
+            (Some(q), b) if b == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') => quote = Some(*b),
+            (None, b'>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Matches `roxmltree::Document::text_pos_at`, which counts columns in characters
+fn text_pos(xml: &str, offset: usize) -> roxmltree::TextPos {
+    let before = &xml[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let row = before.matches('\n').count() + 1;
+    let col = before[line_start..].chars().count() + 1;
+
+    roxmltree::TextPos::new(row as u32, col as u32)
+}
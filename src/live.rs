@@ -0,0 +1,524 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+use yrs::branch::{Branch, BranchID};
+use yrs::types::{Change, Event, Events};
+use yrs::{
+    DeepObservable, Doc, Out, ReadTxn, Subscription, Transact, TransactionMut, Xml, XmlElementRef,
+    XmlFragment, XmlOut,
+};
+
+use crate::{
+    converter::{self, Error},
+    diff, inspect,
+    options::ConvertOptions,
+    serialize::{self, XmlOptions},
+};
+
+/// A change to the converted document, as reported by a `LiveDocument`.
+///
+/// Only top-level blocks are inserted, moved or removed; any change inside a block (including
+/// its nested blocks) is reported as an update of the nearest enclosing block that changed.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum BlockEvent {
+    Inserted {
+        id: String,
+        index: usize,
+        block: Value,
+    },
+    Updated {
+        id: String,
+        block: Value,
+    },
+    Moved {
+        id: String,
+        index: usize,
+    },
+    Removed {
+        id: String,
+    },
+}
+
+type OnChange = Box<dyn FnMut(&[BlockEvent]) + Send>;
+
+struct State {
+    fragment_name: String,
+    options: ConvertOptions,
+    blocks: Value,
+    // set when an update failed to convert, so that the mirror may be out of date and the next
+    // update reconverts the whole fragment
+    error: Option<Error>,
+}
+
+/// Keeps the converted JSON of a fragment up to date as its document changes.
+///
+/// Each transaction that touches the fragment only reconverts the blockContainers it changed,
+/// then calls the `on_change` callback with the resulting events. The callback runs while the
+/// transaction is being committed, so it must not start a transaction on the same document.
+pub struct LiveDocument {
+    state: Arc<Mutex<State>>,
+    _subscription: Subscription,
+}
+
+impl LiveDocument {
+    pub fn new<F>(
+        doc: &Doc,
+        fragment_name: &str,
+        options: &ConvertOptions,
+        on_change: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnMut(&[BlockEvent]) + Send + 'static,
+    {
+        let (fragment, blocks) = {
+            let txn = doc.transact();
+            let fragment = inspect::find_fragment(&txn, fragment_name)?;
+            let blocks = convert_fragment(&txn, fragment_name, options)?;
+            (fragment, blocks)
+        };
+
+        let state = Arc::new(Mutex::new(State {
+            fragment_name: fragment_name.to_string(),
+            options: options.clone(),
+            blocks,
+            error: None,
+        }));
+
+        let on_change: Mutex<OnChange> = Mutex::new(Box::new(on_change));
+        let observed = state.clone();
+        let subscription = fragment.observe_deep(move |txn, events| {
+            let changes = observed.lock().unwrap().update(txn, events);
+            // the state is unlocked, so the callback may read the document
+            if !changes.is_empty() {
+                (on_change.lock().unwrap())(&changes);
+            }
+        });
+
+        Ok(LiveDocument {
+            state,
+            _subscription: subscription,
+        })
+    }
+
+    /// The converted blocks, as of the last transaction.
+    pub fn blocks(&self) -> Value {
+        self.state.lock().unwrap().blocks.clone()
+    }
+
+    /// The error from the last transaction, if it couldn't be converted. The blocks are left as
+    /// they were before that transaction.
+    pub fn error(&self) -> Option<Error> {
+        self.state.lock().unwrap().error.clone()
+    }
+}
+
+impl State {
+    fn update(&mut self, txn: &TransactionMut, events: &Events) -> Vec<BlockEvent> {
+        let result = match self.error {
+            None => self.update_changed(txn, events),
+            Some(_) => self.update_all(txn),
+        };
+
+        match result {
+            Ok(changes) => {
+                self.error = None;
+                changes
+            }
+            Err(err) => {
+                self.error = Some(err);
+                Vec::new()
+            }
+        }
+    }
+
+    // Updates the blocks the events changed, or reconverts the whole fragment if a change
+    // wasn't in the top-level blockGroup or inside a blockContainer
+    fn update_changed(
+        &mut self,
+        txn: &TransactionMut,
+        events: &Events,
+    ) -> Result<Vec<BlockEvent>, Error> {
+        let mut top_level = None;
+        let mut nodes = Vec::new();
+        for event in events.iter() {
+            if let Event::XmlFragment(event) = event {
+                if let XmlOut::Element(group) = event.target() {
+                    if is_top_level_group(txn, group) {
+                        // changes to the group's attributes don't change any blocks
+                        if event.children_changed() {
+                            top_level = Some(event.delta(txn));
+                        }
+                        continue;
+                    }
+                }
+            }
+            nodes.extend(event_node(event));
+        }
+        let Some(containers) = changed_containers(nodes) else {
+            return self.update_all(txn);
+        };
+
+        let mut changes = Vec::new();
+        let mut inserted = HashSet::new();
+        if let Some(delta) = top_level {
+            match self.update_top_level(txn, delta, &mut inserted)? {
+                Some(top_level_changes) => changes.extend(top_level_changes),
+                None => return self.update_all(txn),
+            }
+        }
+
+        // inserted blocks were converted as they are now
+        let containers = containers
+            .into_iter()
+            .filter(|container| !inserted.contains(&AsRef::<Branch>::as_ref(container).id()))
+            .collect();
+        changes.extend(self.update_containers(txn, containers)?);
+        Ok(changes)
+    }
+
+    // Applies the change to the top-level blockGroup's children to the blocks, converting only
+    // the inserted blockContainers, which are added to `inserted`. Returns None if the blocks
+    // don't line up with the blockGroup's children.
+    fn update_top_level(
+        &mut self,
+        txn: &TransactionMut,
+        delta: &[Change],
+        inserted: &mut HashSet<BranchID>,
+    ) -> Result<Option<Vec<BlockEvent>>, Error> {
+        let Some(old_len) = self.blocks.as_array().map(Vec::len) else {
+            return Ok(None);
+        };
+
+        // convert the inserted blocks first, so the blocks are left alone if one fails
+        let mut added = Vec::new();
+        let (mut consumed, mut removed_count) = (0, 0);
+        for change in delta {
+            match change {
+                Change::Added(nodes) => {
+                    for node in nodes {
+                        let Out::YXmlElement(container) = node else {
+                            return Ok(None);
+                        };
+                        if container.tag().as_ref() != "blockContainer" {
+                            return Ok(None);
+                        }
+                        inserted.insert(AsRef::<Branch>::as_ref(container).id());
+                        added.push(convert_container(txn, container.clone(), &self.options)?);
+                    }
+                }
+                Change::Removed(len) => {
+                    consumed += *len as usize;
+                    removed_count += *len as usize;
+                }
+                Change::Retain(len) => consumed += *len as usize,
+            }
+        }
+        let group_len = match top_level_group(txn, &self.fragment_name) {
+            Some(group) => group.len(txn) as usize,
+            None => return Ok(None),
+        };
+        if consumed > old_len || old_len - removed_count + added.len() != group_len {
+            return Ok(None);
+        }
+
+        let Value::Array(old) = std::mem::take(&mut self.blocks) else {
+            unreachable!("the blocks were checked to be an array")
+        };
+        let mut old = old.into_iter();
+        let mut removed = Vec::new();
+        let mut blocks = Vec::new();
+        let mut added = added.into_iter();
+        for change in delta {
+            match change {
+                Change::Retain(len) => {
+                    let kept = old.by_ref().take(*len as usize);
+                    blocks.extend(kept.map(TopLevel::Kept));
+                }
+                Change::Removed(len) => removed.extend(old.by_ref().take(*len as usize)),
+                Change::Added(nodes) => {
+                    let inserted = added.by_ref().take(nodes.len());
+                    blocks.extend(inserted.map(TopLevel::Inserted));
+                }
+            }
+        }
+        blocks.extend(old.map(TopLevel::Kept));
+
+        // a block removed and inserted again in the same transaction was moved
+        let removed = removed
+            .into_iter()
+            .filter_map(|block| Some((block_id(&block)?, block)))
+            .collect::<Vec<_>>();
+        let removed_ids = removed
+            .iter()
+            .enumerate()
+            .map(|(i, (id, _))| (id.clone(), i))
+            .collect::<HashMap<_, _>>();
+        let inserted_ids = blocks
+            .iter()
+            .filter_map(|block| match block {
+                TopLevel::Inserted(block) => block_id(block),
+                TopLevel::Kept(_) => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let mut changes = removed
+            .iter()
+            .filter(|(id, _)| !inserted_ids.contains(id))
+            .map(|(id, _)| BlockEvent::Removed { id: id.clone() })
+            .collect::<Vec<_>>();
+        let mut new = Vec::with_capacity(blocks.len());
+        for (index, block) in blocks.into_iter().enumerate() {
+            let block = match block {
+                TopLevel::Kept(block) => block,
+                TopLevel::Inserted(block) => {
+                    if let Some(id) = block_id(&block) {
+                        match removed_ids.get(&id) {
+                            None => changes.push(BlockEvent::Inserted {
+                                id,
+                                index,
+                                block: block.clone(),
+                            }),
+                            Some(&i) => {
+                                changes.push(BlockEvent::Moved {
+                                    id: id.clone(),
+                                    index,
+                                });
+                                if removed[i].1 != block {
+                                    changes.push(BlockEvent::Updated {
+                                        id,
+                                        block: block.clone(),
+                                    });
+                                }
+                            }
+                        }
+                    }
+                    block
+                }
+            };
+            new.push(block);
+        }
+        self.blocks = Value::Array(new);
+
+        Ok(Some(changes))
+    }
+
+    fn update_containers<T: ReadTxn>(
+        &mut self,
+        txn: &T,
+        containers: Vec<XmlElementRef>,
+    ) -> Result<Vec<BlockEvent>, Error> {
+        let mut changes = Vec::new();
+        for container in containers {
+            let block = convert_container(txn, container, &self.options)?;
+            let found =
+                block_id(&block).and_then(|id| Some((find_block(&mut self.blocks, &id)?, id)));
+            let Some((old, id)) = found else {
+                // the block isn't where the mirror expects it, so start over; blocks updated so
+                // far now match and won't be reported twice
+                changes.extend(self.update_all(txn)?);
+                return Ok(changes);
+            };
+
+            if *old != block {
+                *old = block.clone();
+                changes.push(BlockEvent::Updated { id, block });
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn update_all<T: ReadTxn>(&mut self, txn: &T) -> Result<Vec<BlockEvent>, Error> {
+        let blocks = convert_fragment(txn, &self.fragment_name, &self.options)?;
+        let old = std::mem::replace(&mut self.blocks, blocks);
+        Ok(top_level_changes(&old, &self.blocks))
+    }
+}
+
+// A top-level block after a change to the top-level blockGroup
+enum TopLevel {
+    Kept(Value),
+    Inserted(Value),
+}
+
+// The outermost blockContainers enclosing the changed nodes, or None if a change wasn't inside
+// any blockContainer
+fn changed_containers(nodes: Vec<XmlOut>) -> Option<Vec<XmlElementRef>> {
+    let mut containers = HashMap::new();
+    for node in nodes {
+        match enclosing_container(node) {
+            Enclosing::Container(container) => {
+                containers.insert(AsRef::<Branch>::as_ref(&container).id(), container);
+            }
+            Enclosing::Root => return None,
+            Enclosing::Deleted => {}
+        }
+    }
+
+    // a container enclosing another one is reconverted along with its descendants
+    let ids = containers.keys().cloned().collect::<HashSet<_>>();
+    let outermost = containers
+        .into_values()
+        .filter(|container| {
+            let mut node = container.parent();
+            while let Some(parent) = node {
+                if ids.contains(&parent.id()) {
+                    return false;
+                }
+                node = inspect::xml_parent(&parent);
+            }
+            true
+        })
+        .collect();
+
+    Some(outermost)
+}
+
+// Whether the element is the blockGroup holding the top-level blocks, as the only child of the
+// fragment
+fn is_top_level_group<T: ReadTxn>(txn: &T, group: &XmlElementRef) -> bool {
+    group.tag().as_ref() == "blockGroup"
+        && !AsRef::<Branch>::as_ref(group).is_deleted()
+        && matches!(group.parent(), Some(XmlOut::Fragment(fragment)) if fragment.len(txn) == 1)
+}
+
+fn top_level_group<T: ReadTxn>(txn: &T, fragment_name: &str) -> Option<XmlElementRef> {
+    let fragment = inspect::find_fragment(txn, fragment_name).ok()?;
+    let group = inspect::element(fragment.get(txn, 0)?, "blockGroup")?;
+    is_top_level_group(txn, &group).then_some(group)
+}
+
+fn convert_container<T: ReadTxn>(
+    txn: &T,
+    container: XmlElementRef,
+    options: &ConvertOptions,
+) -> Result<Value, Error> {
+    let mut xml = String::from("<blockGroup>");
+    serialize::write_element(container, txn, &mut xml, &XmlOptions::default())
+        .expect("writing to a String cannot fail");
+    xml.push_str("</blockGroup>");
+
+    Ok(match converter::convert(&xml, options)? {
+        Value::Array(mut blocks) if blocks.len() == 1 => blocks.remove(0),
+        _ => Value::Null,
+    })
+}
+
+fn convert_fragment<T: ReadTxn>(
+    txn: &T,
+    fragment_name: &str,
+    options: &ConvertOptions,
+) -> Result<Value, Error> {
+    let fragment = inspect::find_fragment(txn, fragment_name)?;
+    let mut xml = String::new();
+    serialize::write_fragment(fragment, txn, &mut xml, &XmlOptions::default())
+        .expect("writing to a String cannot fail");
+    converter::convert(&xml, options)
+}
+
+pub(crate) fn event_node(event: &Event) -> Option<XmlOut> {
+    match event {
+        Event::XmlFragment(event) => Some(event.target().clone()),
+        Event::XmlText(event) => Some(XmlOut::Text(event.target().clone())),
+        _ => None,
+    }
+}
+
+pub(crate) enum Enclosing {
+    Container(XmlElementRef),
+    Root,
+    Deleted,
+}
+
+pub(crate) fn enclosing_container(node: XmlOut) -> Enclosing {
+    let mut node = Some(node);
+    while let Some(current) = node {
+        if current.as_ptr().is_deleted() {
+            // the change is also seen by the parent the node was deleted from
+            return Enclosing::Deleted;
+        }
+        match current {
+            XmlOut::Element(elem) if elem.tag().as_ref() == "blockContainer" => {
+                return Enclosing::Container(elem);
+            }
+            _ => node = inspect::xml_parent(&current),
+        }
+    }
+
+    Enclosing::Root
+}
+
+fn block_id(block: &Value) -> Option<String> {
+    block.get("id").and_then(Value::as_str).map(str::to_string)
+}
+
+fn find_block<'a>(blocks: &'a mut Value, id: &str) -> Option<&'a mut Value> {
+    for block in blocks.as_array_mut()? {
+        if block.get("id").and_then(Value::as_str) == Some(id) {
+            return Some(block);
+        }
+        if let Some(found) = block.get_mut("children").and_then(|c| find_block(c, id)) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+fn top_level_changes(old: &Value, new: &Value) -> Vec<BlockEvent> {
+    let empty = Vec::new();
+    let old = old.as_array().unwrap_or(&empty);
+    let new = new.as_array().unwrap_or(&empty);
+    let old_indexes = old
+        .iter()
+        .enumerate()
+        .filter_map(|(i, block)| Some((block_id(block)?, i)))
+        .collect::<HashMap<_, _>>();
+    let new_ids = new.iter().filter_map(block_id).collect::<Vec<_>>();
+    let in_new = new_ids.iter().collect::<HashSet<_>>();
+
+    let mut changes = old
+        .iter()
+        .filter_map(block_id)
+        .filter(|id| !in_new.contains(id))
+        .map(|id| BlockEvent::Removed { id })
+        .collect::<Vec<_>>();
+
+    // blocks kept in both are moved if they're outside the longest run kept in order
+    let old_ids = old.iter().filter_map(block_id).collect::<Vec<_>>();
+    let in_order = diff::kept_in_order(&old_ids, &new_ids)
+        .into_iter()
+        .map(|i| new_ids[i].as_str())
+        .collect::<HashSet<_>>();
+
+    for (index, block) in new.iter().enumerate() {
+        let Some(id) = block_id(block) else {
+            continue;
+        };
+        match old_indexes.get(&id) {
+            None => changes.push(BlockEvent::Inserted {
+                id,
+                index,
+                block: block.clone(),
+            }),
+            Some(&old_index) => {
+                if !in_order.contains(id.as_str()) {
+                    changes.push(BlockEvent::Moved {
+                        id: id.clone(),
+                        index,
+                    });
+                }
+                if old[old_index] != *block {
+                    changes.push(BlockEvent::Updated {
+                        id,
+                        block: block.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    changes
+}
@@ -0,0 +1,724 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+use yrs::{
+    Doc, Map, Transact, TransactionMut, XmlElementPrelim, XmlElementRef, XmlFragment,
+    XmlFragmentRef, XmlOut,
+};
+
+use crate::{
+    block::Block,
+    converter::{self, Error},
+    diff, edit,
+    import::{self, CONTAINER_PROPS},
+    inspect,
+    options::ConvertOptions,
+    serialize::{self, XmlOptions},
+};
+
+/// One of the two edited versions in a merge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// How `merge_blocks` resolves conflicting changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep our version of anything both sides changed.
+    #[default]
+    Ours,
+    /// Keep their version of anything both sides changed.
+    Theirs,
+    /// Fail with `Error::MergeConflict` if there are any conflicts.
+    Fail,
+}
+
+/// A change made by both sides that couldn't be merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Conflict {
+    /// Both sides changed the block's type, to different types.
+    Type { id: String },
+    /// Both sides changed (or added, or removed) the prop, to different values.
+    Prop { id: String, name: String },
+    /// Both sides changed the block's content differently.
+    Content { id: String },
+    /// Both sides moved the block to different parents, or their moves together would make the
+    /// block its own ancestor.
+    Moved { id: String },
+    /// Both sides reordered the blocks nested in `parent` (`None` for the top level)
+    /// differently.
+    Order { parent: Option<String> },
+    /// One side deleted the block while the other changed it.
+    Deleted { id: String, deleted_by: Side },
+}
+
+/// Options for `merge_blocks`.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    policy: ConflictPolicy,
+    convert: ConvertOptions,
+}
+
+impl MergeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How to resolve conflicting changes. Defaults to `ConflictPolicy::Ours`.
+    pub fn policy(mut self, policy: ConflictPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The `ConvertOptions` for the merged blocks.
+    pub fn convert(mut self, convert: ConvertOptions) -> Self {
+        self.convert = convert;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeResult {
+    pub blocks: Value,
+    /// The conflicts found, each resolved according to the `ConflictPolicy`.
+    pub conflicts: Vec<Conflict>,
+}
+
+// The blocks of one version, by id, with the id of the block each is nested in
+struct Version<'a> {
+    blocks: HashMap<&'a str, (&'a Block, Option<&'a str>)>,
+    children: HashMap<Option<&'a str>, Vec<&'a str>>,
+    order: Vec<&'a str>,
+}
+
+impl<'a> Version<'a> {
+    fn new(blocks: &'a [Block]) -> Result<Self, Error> {
+        let mut version = Version {
+            blocks: HashMap::new(),
+            children: HashMap::new(),
+            order: Vec::new(),
+        };
+        version.add(blocks, None)?;
+        Ok(version)
+    }
+
+    fn add(&mut self, blocks: &'a [Block], parent: Option<&'a str>) -> Result<(), Error> {
+        for block in blocks {
+            if block.id.is_empty() {
+                return Err(Error::InvalidBlocks(format!(
+                    "{} block with no id",
+                    block.type_name
+                )));
+            }
+            if self.blocks.insert(&block.id, (block, parent)).is_some() {
+                return Err(Error::InvalidBlocks(format!(
+                    "more than one block with id {}",
+                    block.id
+                )));
+            }
+            self.order.push(&block.id);
+            self.children.entry(parent).or_default().push(&block.id);
+            self.add(&block.children, Some(&block.id))?;
+        }
+
+        Ok(())
+    }
+
+    fn block(&self, id: &str) -> Option<&'a Block> {
+        self.blocks.get(id).map(|(block, _)| *block)
+    }
+
+    fn parent(&self, id: &str) -> Option<Option<&'a str>> {
+        self.blocks.get(id).map(|(_, parent)| *parent)
+    }
+
+    fn children(&self, parent: Option<&'a str>) -> &[&'a str] {
+        self.children.get(&parent).map_or(&[], Vec::as_slice)
+    }
+}
+
+// Which side's version to take of something both sides might have changed
+#[derive(Clone, Copy, PartialEq)]
+enum Choice {
+    Ours,
+    Theirs,
+    Conflict,
+}
+
+fn choose<T: PartialEq>(base: Option<T>, ours: Option<T>, theirs: Option<T>) -> Choice {
+    if ours == theirs || theirs == base {
+        Choice::Ours
+    } else if ours == base {
+        Choice::Theirs
+    } else {
+        Choice::Conflict
+    }
+}
+
+struct Merger {
+    policy: ConflictPolicy,
+    conflicts: Vec<Conflict>,
+}
+
+impl Merger {
+    // Records a conflict if there is one, and returns the side to take
+    fn resolve(&mut self, choice: Choice, conflict: impl FnOnce() -> Conflict) -> Side {
+        match choice {
+            Choice::Ours => Side::Ours,
+            Choice::Theirs => Side::Theirs,
+            Choice::Conflict => {
+                let conflict = conflict();
+                if !self.conflicts.contains(&conflict) {
+                    self.conflicts.push(conflict);
+                }
+                match self.policy {
+                    ConflictPolicy::Theirs => Side::Theirs,
+                    ConflictPolicy::Ours | ConflictPolicy::Fail => Side::Ours,
+                }
+            }
+        }
+    }
+}
+
+/// Merges the changes made in `ours` and `theirs` since `base`, all in the form produced by
+/// `convert_to_value`, matching blocks by id.
+pub(crate) fn merge_blocks(
+    base: &Value,
+    ours: &Value,
+    theirs: &Value,
+    options: &MergeOptions,
+) -> Result<MergeResult, Error> {
+    let base = import::parse_blocks(base).map_err(Error::InvalidBlocks)?;
+    let ours = import::parse_blocks(ours).map_err(Error::InvalidBlocks)?;
+    let theirs = import::parse_blocks(theirs).map_err(Error::InvalidBlocks)?;
+    let (blocks, conflicts) = merge(
+        &Version::new(&base)?,
+        &Version::new(&ours)?,
+        &Version::new(&theirs)?,
+        options.policy,
+    );
+    if options.policy == ConflictPolicy::Fail && !conflicts.is_empty() {
+        return Err(Error::MergeConflict(conflicts));
+    }
+    Ok(MergeResult {
+        blocks: converter::to_value(&blocks, &options.convert),
+        conflicts,
+    })
+}
+
+/// Merges `theirs` into the fragment, using the fragment's current blocks as `ours`, and
+/// writes the differences between them and the result into the fragment, all in one
+/// transaction.
+pub(crate) fn merge_into_fragment(
+    doc: &Doc,
+    fragment_name: &str,
+    base: &Value,
+    theirs: &Value,
+    options: &MergeOptions,
+) -> Result<Vec<Conflict>, Error> {
+    let mut txn = doc.transact_mut();
+    let fragment = inspect::find_fragment(&txn, fragment_name)?;
+    let mut xml = String::new();
+    serialize::write_fragment(fragment.clone(), &txn, &mut xml, &XmlOptions::default())
+        .expect("writing to a String cannot fail");
+    let ours = converter::convert(&xml, &options.convert)?;
+    let merged = merge_blocks(base, &ours, theirs, options)?;
+
+    let ours = import::parse_blocks(&ours).map_err(Error::InvalidBlocks)?;
+    let blocks = import::parse_blocks(&merged.blocks).map_err(Error::InvalidBlocks)?;
+    write_changes(
+        &mut txn,
+        &fragment,
+        &Version::new(&ours)?,
+        &Version::new(&blocks)?,
+    );
+
+    Ok(merged.conflicts)
+}
+
+// Changes the fragment from `ours` to `merged`, leaving blocks that didn't change alone
+fn write_changes(
+    txn: &mut TransactionMut,
+    fragment: &XmlFragmentRef,
+    ours: &Version,
+    merged: &Version,
+) {
+    // put each block where it goes, parents before their children, moving only the blocks
+    // outside the longest run kept in order under the same parent
+    let parents = std::iter::once(None).chain(merged.order.iter().map(|id| Some(*id)));
+    for parent in parents {
+        let children = merged.children(parent);
+        let kept = diff::kept_in_order(ours.children(parent), children)
+            .into_iter()
+            .map(|j| children[j])
+            .collect::<HashSet<_>>();
+        let mut previous = None;
+        for id in children {
+            if !kept.contains(id) {
+                let block = merged.block(id).expect("children are in the version");
+                place(txn, fragment, block, parent, previous);
+            }
+            previous = Some(*id);
+        }
+    }
+
+    for id in &merged.order {
+        if let (Some(old), Some(new)) = (ours.block(id), merged.block(id)) {
+            if !same_block(old, new) {
+                update(txn, fragment, old, new);
+            }
+        }
+    }
+
+    // blocks nested in a deleted block have been moved out of it or go with it
+    for id in &ours.order {
+        if merged.block(id).is_none() {
+            if let Some(container) = inspect::find_container(txn, fragment, id) {
+                edit::remove(txn, &container);
+            }
+        }
+    }
+}
+
+// Moves the block (or writes it, without its children, if it's new) to just after `previous`,
+// or to the start of `parent`
+fn place(
+    txn: &mut TransactionMut,
+    fragment: &XmlFragmentRef,
+    block: &Block,
+    parent: Option<&str>,
+    previous: Option<&str>,
+) {
+    let copy = inspect::find_container(txn, fragment, &block.id).map(|container| {
+        let copy = edit::Copied::of(txn, XmlOut::Element(container.clone()));
+        edit::remove(txn, &container);
+        copy
+    });
+
+    let (group, index) = match previous {
+        Some(previous) => {
+            let container = inspect::find_container(txn, fragment, previous)
+                .expect("blocks are placed in order");
+            let (group, index) = edit::index_in_parent(txn, &container);
+            (group, index + 1)
+        }
+        None => (child_group(txn, fragment, parent), 0),
+    };
+    match copy {
+        Some(copy) => copy.insert(txn, &group, index),
+        None => import::write_blocks(txn, &group, index, &[strip(block)]),
+    }
+}
+
+// The blockGroup holding the blocks nested in `parent` (the top-level blocks for `None`),
+// added if there isn't one
+fn child_group(
+    txn: &mut TransactionMut,
+    fragment: &XmlFragmentRef,
+    parent: Option<&str>,
+) -> XmlElementRef {
+    let group = match parent {
+        None => fragment
+            .children(txn)
+            .find_map(|node| inspect::element(node, "blockGroup")),
+        Some(parent) => {
+            let container = inspect::find_container(txn, fragment, parent)
+                .expect("parents are placed before their children");
+            let group = container
+                .children(txn)
+                .find_map(|node| inspect::element(node, "blockGroup"));
+            match group {
+                Some(group) => Some(group),
+                None => Some(container.push_back(txn, XmlElementPrelim::empty("blockGroup"))),
+            }
+        }
+    };
+
+    group.unwrap_or_else(|| fragment.push_back(txn, XmlElementPrelim::empty("blockGroup")))
+}
+
+// Writes the type, props and content of a block that's in both versions, where they differ
+fn update(txn: &mut TransactionMut, fragment: &XmlFragmentRef, old: &Block, new: &Block) {
+    let container =
+        inspect::find_container(txn, fragment, &new.id).expect("merged blocks are all placed");
+    let mut elem = edit::block_element(txn, &container);
+
+    // the type is the element's tag, so a new type needs a new element
+    if old.type_name != new.type_name || elem.is_none() {
+        let index = match elem.take() {
+            Some(elem) => {
+                let (_, index) = edit::index_in_parent(txn, &elem);
+                container.remove_range(txn, index, 1);
+                index
+            }
+            None => 0,
+        };
+        import::write_block_element(txn, &container, index, new);
+    }
+
+    let removed = old
+        .props
+        .keys()
+        .filter(|name| !new.props.contains_key(*name))
+        .map(|name| (name, None));
+    let changed = new
+        .props
+        .iter()
+        .filter(|(name, value)| old.props.get(*name) != Some(*value))
+        .map(|(name, value)| (name, Some(value)));
+    for (name, value) in removed.chain(changed).collect::<Vec<_>>() {
+        let attributes = match &elem {
+            _ if CONTAINER_PROPS.contains(&name.as_str()) => import::attributes(&container),
+            Some(elem) => import::attributes(elem),
+            // written with the new element
+            None => continue,
+        };
+        match value {
+            Some(value) => {
+                attributes.insert(txn, name.clone(), import::any(value));
+            }
+            None => {
+                attributes.remove(txn, name);
+            }
+        }
+    }
+
+    if let Some(elem) = elem {
+        if content_value(old) != content_value(new) {
+            let len = elem.len(txn);
+            elem.remove_range(txn, 0, len);
+            if let Some(content) = &new.content {
+                import::write_content(txn, &elem, content);
+            }
+        }
+    }
+}
+
+fn merge(
+    base: &Version,
+    ours: &Version,
+    theirs: &Version,
+    policy: ConflictPolicy,
+) -> (Vec<Block>, Vec<Conflict>) {
+    let mut merger = Merger {
+        policy,
+        conflicts: Vec::new(),
+    };
+
+    let mut ids = Vec::new();
+    let mut seen = HashSet::new();
+    for id in ours.order.iter().chain(&theirs.order).chain(&base.order) {
+        if seen.insert(*id) {
+            ids.push(*id);
+        }
+    }
+
+    // the merged blocks, without their children, and where each one goes
+    let mut blocks = HashMap::new();
+    let mut parents = HashMap::new();
+    for id in ids.iter().copied() {
+        let (block, parent) = match (ours.block(id), theirs.block(id)) {
+            (None, None) => continue,
+            (Some(ours_block), Some(theirs_block)) => merge_block(
+                &mut merger,
+                id,
+                base,
+                ours_block,
+                theirs_block,
+                ours,
+                theirs,
+            ),
+            (Some(block), None) | (None, Some(block)) => {
+                let (side, version) = match ours.block(id) {
+                    Some(_) => (Side::Ours, ours),
+                    None => (Side::Theirs, theirs),
+                };
+                match base.block(id) {
+                    // added on one side
+                    None => (strip(block), version.parent(id).flatten()),
+                    // deleted on the other, which is only a conflict if this side changed it
+                    Some(base_block) => {
+                        if same_block(base_block, block) && base.parent(id) == version.parent(id) {
+                            continue;
+                        }
+                        let deleted_by = match side {
+                            Side::Ours => Side::Theirs,
+                            Side::Theirs => Side::Ours,
+                        };
+                        let kept = merger.resolve(Choice::Conflict, || Conflict::Deleted {
+                            id: id.to_string(),
+                            deleted_by,
+                        });
+                        if kept != side {
+                            continue;
+                        }
+                        (strip(block), version.parent(id).flatten())
+                    }
+                }
+            }
+        };
+        blocks.insert(id, block);
+        parents.insert(id, parent);
+    }
+
+    fix_parents(&mut merger, &mut parents, base, ours, theirs);
+
+    // order the blocks nested in each parent
+    let mut members: HashMap<Option<&str>, HashSet<&str>> = HashMap::new();
+    for (id, parent) in parents.iter() {
+        members.entry(*parent).or_default().insert(id);
+    }
+    let mut order = HashMap::new();
+    for (parent, members) in members.iter() {
+        let children = order_children(&mut merger, *parent, members, base, ours, theirs);
+        order.insert(*parent, children);
+    }
+
+    (build(None, &order, &mut blocks), merger.conflicts)
+}
+
+fn merge_block<'a>(
+    merger: &mut Merger,
+    id: &str,
+    base: &Version<'a>,
+    ours_block: &Block,
+    theirs_block: &Block,
+    ours: &Version<'a>,
+    theirs: &Version<'a>,
+) -> (Block, Option<&'a str>) {
+    let base_block = base.block(id);
+    let mut block = Block::new();
+    block.id = id.to_string();
+
+    let choice = choose(
+        base_block.map(|block| &block.type_name),
+        Some(&ours_block.type_name),
+        Some(&theirs_block.type_name),
+    );
+    block.type_name = match merger.resolve(choice, || Conflict::Type { id: id.to_string() }) {
+        Side::Ours => ours_block.type_name.clone(),
+        Side::Theirs => theirs_block.type_name.clone(),
+    };
+
+    let mut names = ours_block.props.keys().collect::<Vec<_>>();
+    for name in theirs_block
+        .props
+        .keys()
+        .chain(base_block.into_iter().flat_map(|block| block.props.keys()))
+    {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    for name in names {
+        let (ours_value, theirs_value) = (ours_block.props.get(name), theirs_block.props.get(name));
+        let choice = choose(
+            base_block.map(|block| block.props.get(name)),
+            Some(ours_value),
+            Some(theirs_value),
+        );
+        let value = match merger.resolve(choice, || Conflict::Prop {
+            id: id.to_string(),
+            name: name.clone(),
+        }) {
+            Side::Ours => ours_value,
+            Side::Theirs => theirs_value,
+        };
+        if let Some(value) = value {
+            block.props.insert(name.clone(), value.clone());
+        }
+    }
+
+    let choice = choose(
+        base_block.map(content_value),
+        Some(content_value(ours_block)),
+        Some(content_value(theirs_block)),
+    );
+    block.content = match merger.resolve(choice, || Conflict::Content { id: id.to_string() }) {
+        Side::Ours => ours_block.content.clone(),
+        Side::Theirs => theirs_block.content.clone(),
+    };
+
+    let choice = choose(base.parent(id), ours.parent(id), theirs.parent(id));
+    let parent = match merger.resolve(choice, || Conflict::Moved { id: id.to_string() }) {
+        Side::Ours => ours.parent(id),
+        Side::Theirs => theirs.parent(id),
+    };
+
+    (block, parent.flatten())
+}
+
+// Makes sure every block's parent is in the merged document, and that no block ends up as its
+// own ancestor
+fn fix_parents<'a>(
+    merger: &mut Merger,
+    parents: &mut HashMap<&'a str, Option<&'a str>>,
+    base: &Version<'a>,
+    ours: &Version<'a>,
+    theirs: &Version<'a>,
+) {
+    let ids = parents.keys().copied().collect::<Vec<_>>();
+
+    // blocks nested in a block that was deleted go where the deleted block was
+    for id in ids.iter() {
+        let mut parent = parents[id];
+        let mut visited = HashSet::new();
+        while let Some(deleted) = parent.filter(|parent| !parents.contains_key(parent)) {
+            if !visited.insert(deleted) {
+                parent = None;
+                break;
+            }
+            parent = [ours, theirs, base]
+                .iter()
+                .find_map(|version| version.parent(deleted))
+                .flatten();
+        }
+        parents.insert(id, parent);
+    }
+
+    // moves on both sides can form a cycle (e.g. we moved a into b while they moved b into
+    // a), so break any cycle by moving one of its blocks to the top level
+    for id in ids.iter() {
+        let mut visited = HashSet::from([*id]);
+        let mut parent = parents[id];
+        while let Some(ancestor) = parent {
+            if !visited.insert(ancestor) {
+                parents.insert(ancestor, None);
+                merger.resolve(Choice::Conflict, || Conflict::Moved {
+                    id: ancestor.to_string(),
+                });
+                break;
+            }
+            parent = parents[ancestor];
+        }
+    }
+}
+
+// Takes the order of whichever side reordered the blocks, and puts the blocks only the other
+// side has after the block that preceded them there
+fn order_children<'a>(
+    merger: &mut Merger,
+    parent: Option<&'a str>,
+    members: &HashSet<&'a str>,
+    base: &Version<'a>,
+    ours: &Version<'a>,
+    theirs: &Version<'a>,
+) -> Vec<&'a str> {
+    let list = |version: &Version<'a>| {
+        version
+            .children(parent)
+            .iter()
+            .copied()
+            .filter(|id| members.contains(id))
+            .collect::<Vec<_>>()
+    };
+    let (base_list, ours_list, theirs_list) = (list(base), list(ours), list(theirs));
+    let index = |list: &[&'a str]| {
+        list.iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i))
+            .collect::<HashMap<_, _>>()
+    };
+    let (base_index, ours_index, theirs_index) =
+        (index(&base_list), index(&ours_list), index(&theirs_list));
+
+    // the relative order of the blocks both lists have
+    let common = |a: &[&'a str], b: &HashMap<&'a str, usize>| {
+        a.iter()
+            .copied()
+            .filter(|id| b.contains_key(id))
+            .collect::<Vec<_>>()
+    };
+    let reordered = |list: &[&'a str], index: &HashMap<&'a str, usize>| {
+        common(list, &base_index) != common(&base_list, index)
+    };
+    let choice = match (
+        reordered(&ours_list, &ours_index),
+        reordered(&theirs_list, &theirs_index),
+    ) {
+        (_, false) => Choice::Ours,
+        (false, true) => Choice::Theirs,
+        (true, true) if common(&ours_list, &theirs_index) == common(&theirs_list, &ours_index) => {
+            Choice::Ours
+        }
+        (true, true) => Choice::Conflict,
+    };
+    let side = merger.resolve(choice, || Conflict::Order {
+        parent: parent.map(str::to_string),
+    });
+    let (order, other) = match side {
+        Side::Ours => (ours_list, theirs_list),
+        Side::Theirs => (theirs_list, ours_list),
+    };
+
+    // the blocks to put straight after each block (or at the start, for None), latest first
+    let mut placed = order.iter().copied().collect::<HashSet<_>>();
+    let mut after = HashMap::<Option<&'a str>, Vec<&'a str>>::new();
+    for source in [&other, &base_list] {
+        let mut previous = None;
+        for id in source {
+            if placed.insert(id) {
+                after.entry(previous).or_default().push(id);
+            }
+            previous = Some(*id);
+        }
+    }
+
+    let mut stack = order.iter().rev().copied().collect::<Vec<_>>();
+    stack.extend(after.get(&None).into_iter().flatten());
+    let mut merged = Vec::with_capacity(placed.len());
+    while let Some(id) = stack.pop() {
+        merged.push(id);
+        stack.extend(after.get(&Some(id)).into_iter().flatten());
+    }
+    // blocks moved here from a deleted parent
+    let mut rest = members
+        .iter()
+        .copied()
+        .filter(|id| !placed.contains(id))
+        .collect::<Vec<_>>();
+    rest.sort_unstable();
+    merged.extend(rest);
+
+    merged
+}
+
+fn build<'a>(
+    parent: Option<&'a str>,
+    order: &HashMap<Option<&'a str>, Vec<&'a str>>,
+    blocks: &mut HashMap<&'a str, Block>,
+) -> Vec<Block> {
+    let Some(ids) = order.get(&parent) else {
+        return Vec::new();
+    };
+
+    ids.iter()
+        .filter_map(|id| {
+            let mut block = blocks.remove(id)?;
+            block.children = build(Some(id), order, blocks);
+            Some(block)
+        })
+        .collect()
+}
+
+// The block without its children, which are merged separately
+fn strip(block: &Block) -> Block {
+    Block {
+        children: Vec::new(),
+        ..block.clone()
+    }
+}
+
+fn same_block(a: &Block, b: &Block) -> bool {
+    a.type_name == b.type_name && a.props == b.props && content_value(a) == content_value(b)
+}
+
+// The content as JSON, since that's what matters when comparing it
+fn content_value(block: &Block) -> Value {
+    let options = ConvertOptions::default();
+    serde_json::to_value(block.content.as_ref().map(|content| options.apply(content)))
+        .expect("the document model always serializes to JSON")
+}
@@ -0,0 +1,84 @@
+use crate::limits::Limits;
+
+/// The version of BlockNote's JSON format to produce.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BlockNoteVersion {
+    /// BlockNote 0.22 and earlier, where each table cell is an array of inline content.
+    #[default]
+    V0_22,
+    /// BlockNote 0.23 and later, where each table cell is a `tableCell` object with its own
+    /// props (including `colspan` and `rowspan`) and content.
+    V0_23,
+}
+
+/// Options controlling the JSON produced by the `convert_*_with` functions.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    pub(crate) omit_empty_content: bool,
+    pub(crate) omit_empty_children: bool,
+    pub(crate) drop_default_props: bool,
+    pub(crate) partial_blocks: bool,
+    pub(crate) version: BlockNoteVersion,
+    pub(crate) limits: Limits,
+}
+
+impl ConvertOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leave out `content` on blocks that can't have inline content (images, videos, audio
+    /// and files), rather than giving them an empty array.
+    pub fn omit_empty_content(mut self, omit_empty_content: bool) -> Self {
+        self.omit_empty_content = omit_empty_content;
+        self
+    }
+
+    /// Leave out `children` on blocks that have no nested blocks.
+    pub fn omit_empty_children(mut self, omit_empty_children: bool) -> Self {
+        self.omit_empty_children = omit_empty_children;
+        self
+    }
+
+    /// Leave out props of built-in blocks that have BlockNote's default value (e.g.
+    /// `textColor: "default"` or `textAlignment: "left"`).
+    pub fn drop_default_props(mut self, drop_default_props: bool) -> Self {
+        self.drop_default_props = drop_default_props;
+        self
+    }
+
+    /// Produce BlockNote's `PartialBlock` form, as accepted by `editor.insertBlocks` and
+    /// friends: default props are dropped, and empty `props`, `content`, `children` and
+    /// `styles` are left out entirely.
+    pub fn partial_blocks(mut self, partial_blocks: bool) -> Self {
+        self.partial_blocks = partial_blocks;
+        self
+    }
+
+    pub fn version(mut self, version: BlockNoteVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+/// A piece of the document model paired with the options that shape its JSON, so that it can
+/// be serialized directly without building a `serde_json::Value` first.
+pub(crate) struct WithOptions<'a, T: ?Sized> {
+    pub value: &'a T,
+    pub options: &'a ConvertOptions,
+}
+
+impl ConvertOptions {
+    pub(crate) fn apply<'a, T: ?Sized>(&'a self, value: &'a T) -> WithOptions<'a, T> {
+        WithOptions {
+            value,
+            options: self,
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::borrow::Cow;
+
+use yrs::branch::{Branch, BranchID, BranchPtr};
+use yrs::types::text::YChange;
+use yrs::{
+    Any, Assoc, Doc, OffsetKind, Out, ReadTxn, StickyIndex, Text, Transact, Xml, XmlElementRef,
+    XmlFragment, XmlFragmentRef, XmlOut, XmlTextRef,
+};
+
+use crate::{converter::Error, history, inspect};
+
+/// A position in the inline content of a block in the JSON model.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlockOffset {
+    pub block_id: String,
+    /// The offset into the text of the block's content, counting every text item (including
+    /// the text of links) in order. For tables, the text of the cells follows on in row order.
+    pub offset: usize,
+}
+
+/// The unit a `BlockOffset` counts in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OffsetUnit {
+    /// UTF-16 code units, like JavaScript strings (and so BlockNote and ProseMirror).
+    #[default]
+    Utf16,
+    /// UTF-8 bytes, like Rust strings.
+    Utf8,
+    /// Unicode scalar values, like `str::chars`.
+    Chars,
+}
+
+impl OffsetUnit {
+    fn len(self, s: &str) -> usize {
+        match self {
+            OffsetUnit::Utf16 => s.encode_utf16().count(),
+            OffsetUnit::Utf8 => s.len(),
+            OffsetUnit::Chars => s.chars().count(),
+        }
+    }
+
+    fn char_len(self, c: char) -> usize {
+        match self {
+            OffsetUnit::Utf16 => c.len_utf16(),
+            OffsetUnit::Utf8 => c.len_utf8(),
+            OffsetUnit::Chars => 1,
+        }
+    }
+}
+
+// Converts an offset into `s` from one unit to another, failing if it's past the end or in the
+// middle of a character
+fn convert_offset(s: &str, offset: usize, from: OffsetUnit, to: OffsetUnit) -> Option<usize> {
+    let (mut from_len, mut to_len) = (0, 0);
+    for c in s.chars() {
+        if from_len >= offset {
+            break;
+        }
+        from_len += from.char_len(c);
+        to_len += to.char_len(c);
+    }
+
+    (from_len == offset).then_some(to_len)
+}
+
+// yrs counts the items before a position in the document's offset kind, but the part of the
+// item the position is in in UTF-16 code units, so positions in a document that counts in
+// bytes are worked out in a copy that counts in UTF-16. Sticky indexes refer to items by id, so
+// they mean the same in the copy. The fragment is typed in the copy, so its blocks have a parent.
+fn utf16_doc<'a>(doc: &'a Doc, fragment_name: &str) -> Result<Cow<'a, Doc>, Error> {
+    if doc.offset_kind() == OffsetKind::Utf16 {
+        return Ok(Cow::Borrowed(doc));
+    }
+    let copy = history::doc_copy(doc, OffsetKind::Utf16)?;
+    inspect::find_fragment(&copy.transact(), fragment_name)?;
+    copy.get_or_insert_xml_fragment(fragment_name);
+    Ok(Cow::Owned(copy))
+}
+
+pub(crate) fn sticky_to_block_offset(
+    doc: &Doc,
+    fragment_name: &str,
+    index: &StickyIndex,
+    unit: OffsetUnit,
+) -> Result<BlockOffset, Error> {
+    let doc = utf16_doc(doc, fragment_name)?;
+    let txn = doc.transact();
+    let fragment = inspect::find_fragment(&txn, fragment_name)?;
+    let offset = index
+        .get_offset(&txn)
+        .ok_or_else(|| Error::InvalidPosition("the position no longer exists".to_string()))?;
+    let node = XmlOut::try_from(offset.branch)
+        .map_err(|_| Error::InvalidPosition("the position isn't in XML".to_string()))?;
+    let index = offset.index as usize;
+
+    // find the block the position is in, checking it's in the fragment. A position in a
+    // blockGroup is between the blocks nested in its block, rather than in that block.
+    let mut container = None;
+    let mut current = Some(node.clone());
+    let mut top = node.clone();
+    while let Some(node) = current {
+        if node.as_ptr().is_deleted() {
+            return Err(Error::InvalidPosition(
+                "the position was deleted".to_string(),
+            ));
+        }
+        if let XmlOut::Element(elem) = &node {
+            if container.is_none() && elem.tag().as_ref() == "blockContainer" {
+                container = Some(elem.clone());
+            }
+        }
+        current = inspect::xml_parent(&node);
+        top = node;
+    }
+    if top.id() != fragment_id(&fragment) {
+        return Err(Error::InvalidPosition(format!(
+            "the position isn't in {}",
+            fragment_name
+        )));
+    }
+
+    let between_blocks = match &node {
+        XmlOut::Element(elem) => elem.tag().as_ref() == "blockGroup",
+        XmlOut::Fragment(_) => true,
+        XmlOut::Text(_) => false,
+    };
+    let container = match container {
+        Some(container) if !between_blocks => container,
+        _ => {
+            // between blocks, so take the block after the position (or the last one)
+            let (containers, index) = match &node {
+                // the index is among the fragment's blockGroups, so the position is at the start
+                // of one of them, or after the last
+                XmlOut::Fragment(fragment) => {
+                    let groups = fragment
+                        .children(&txn)
+                        .filter_map(|node| inspect::element(node, "blockGroup"))
+                        .collect::<Vec<_>>();
+                    match groups.get(index) {
+                        Some(group) => (group.children(&txn).collect(), 0),
+                        None => match groups.last() {
+                            Some(group) => (group.children(&txn).collect(), usize::MAX),
+                            None => (Vec::new(), 0),
+                        },
+                    }
+                }
+                XmlOut::Element(group) => (group.children(&txn).collect::<Vec<_>>(), index),
+                XmlOut::Text(_) => (Vec::new(), 0),
+            };
+            let container = containers
+                .into_iter()
+                .take(index.saturating_add(1))
+                .filter_map(|node| inspect::element(node, "blockContainer"))
+                .last()
+                .ok_or_else(|| Error::InvalidPosition("the position has no blocks".to_string()))?;
+            return Ok(BlockOffset {
+                block_id: block_id(&container, &txn)?,
+                offset: 0,
+            });
+        }
+    };
+
+    let target = Target {
+        id: node.id(),
+        index,
+    };
+    let mut offset = 0;
+    if !matches!(&node, XmlOut::Element(elem) if elem.tag().as_ref() == "blockContainer") {
+        let mut walk = Walk {
+            txn: &txn,
+            unit,
+            offset: &mut offset,
+        };
+        walk.text_before(XmlOut::Element(container.clone()), &target)?;
+    }
+
+    Ok(BlockOffset {
+        block_id: block_id(&container, &txn)?,
+        offset,
+    })
+}
+
+pub(crate) fn block_offset_to_sticky(
+    doc: &Doc,
+    fragment_name: &str,
+    position: &BlockOffset,
+    assoc: Assoc,
+    unit: OffsetUnit,
+) -> Result<StickyIndex, Error> {
+    let doc = utf16_doc(doc, fragment_name)?;
+    let txn = doc.transact();
+    let fragment = inspect::find_fragment(&txn, fragment_name)?;
+    let container =
+        inspect::find_container(&txn, &fragment, &position.block_id).ok_or_else(|| {
+            Error::BlockNotFound {
+                id: position.block_id.clone(),
+            }
+        })?;
+
+    let mut texts = Vec::new();
+    collect_texts(&txn, XmlOut::Element(container.clone()), &mut texts);
+
+    let mut remaining = position.offset;
+    for (text, content) in &texts {
+        let len = unit.len(content);
+        if remaining > len {
+            remaining -= len;
+            continue;
+        }
+
+        let index =
+            convert_offset(content, remaining, unit, OffsetUnit::Utf16).ok_or_else(|| {
+                Error::InvalidPosition("the offset is in the middle of a character".to_string())
+            })?;
+        return sticky_at(&txn, text, index as u32, assoc);
+    }
+
+    if remaining > 0 || !texts.is_empty() {
+        return Err(Error::InvalidPosition(format!(
+            "the offset is past the end of block {}",
+            position.block_id
+        )));
+    }
+
+    // a block without text, so point into its content element
+    let content = container
+        .children(&txn)
+        .find_map(|node| match node {
+            XmlOut::Element(elem) if elem.tag().as_ref() != "blockGroup" => Some(elem),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            Error::InvalidPosition(format!("block {} has no content", position.block_id))
+        })?;
+    Ok(StickyIndex::from_type(&txn, &content, assoc))
+}
+
+fn sticky_at<T: ReadTxn>(
+    txn: &T,
+    text: &XmlTextRef,
+    index: u32,
+    assoc: Assoc,
+) -> Result<StickyIndex, Error> {
+    let branch = BranchPtr::from(AsRef::<Branch>::as_ref(text));
+    StickyIndex::at(txn, branch, index, assoc)
+        // yrs can't stick to the right of the end of a text, so stick to its last character
+        .or_else(|| StickyIndex::at(txn, branch, index, Assoc::Before))
+        .ok_or_else(|| Error::InvalidPosition("the offset is past the end of the text".to_string()))
+}
+
+// A child index in an element, or a text index in a text node
+struct Target {
+    id: BranchID,
+    index: usize,
+}
+
+struct Walk<'a, T: ReadTxn> {
+    txn: &'a T,
+    unit: OffsetUnit,
+    offset: &'a mut usize,
+}
+
+impl<T: ReadTxn> Walk<'_, T> {
+    // Adds up the text in the block before the target, returning whether the target was found
+    fn text_before(&mut self, node: XmlOut, target: &Target) -> Result<bool, Error> {
+        let is_target = node.id() == target.id;
+        match node {
+            XmlOut::Text(text) => {
+                let content = text_content(self.txn, &text);
+                if is_target {
+                    let index =
+                        convert_offset(&content, target.index, OffsetUnit::Utf16, self.unit)
+                            .ok_or_else(|| {
+                                Error::InvalidPosition(
+                                    "the position is outside its text".to_string(),
+                                )
+                            })?;
+                    *self.offset += index;
+                    return Ok(true);
+                }
+                *self.offset += self.unit.len(&content);
+            }
+            XmlOut::Element(elem) => {
+                for (i, child) in elem.children(self.txn).enumerate() {
+                    if is_target && i == target.index {
+                        return Ok(true);
+                    }
+                    if inspect::element(child.clone(), "blockGroup").is_some() {
+                        continue;
+                    }
+                    if self.text_before(child, target)? {
+                        return Ok(true);
+                    }
+                }
+                if is_target {
+                    return Ok(true);
+                }
+            }
+            XmlOut::Fragment(_) => {}
+        }
+
+        Ok(false)
+    }
+}
+
+// The text nodes in a block's content, in order, with their text
+fn collect_texts<T: ReadTxn>(txn: &T, node: XmlOut, texts: &mut Vec<(XmlTextRef, String)>) {
+    match node {
+        XmlOut::Text(text) => {
+            let content = text_content(txn, &text);
+            texts.push((text, content));
+        }
+        XmlOut::Element(elem) => {
+            for child in elem.children(txn) {
+                if inspect::element(child.clone(), "blockGroup").is_none() {
+                    collect_texts(txn, child, texts);
+                }
+            }
+        }
+        XmlOut::Fragment(_) => {}
+    }
+}
+
+// The text of a text node without its formatting
+fn text_content<T: ReadTxn>(txn: &T, text: &XmlTextRef) -> String {
+    text.diff(txn, YChange::identity)
+        .into_iter()
+        .filter_map(|diff| match diff.insert {
+            Out::Any(Any::String(s)) => Some(s),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+fn block_id<T: ReadTxn>(container: &XmlElementRef, txn: &T) -> Result<String, Error> {
+    container
+        .get_attribute(txn, "id")
+        .ok_or_else(|| Error::InvalidPosition("the position is in a block without an id".into()))
+}
+
+fn fragment_id(fragment: &XmlFragmentRef) -> BranchID {
+    AsRef::<Branch>::as_ref(fragment).id()
+}
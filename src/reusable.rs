@@ -0,0 +1,54 @@
+use std::io;
+
+use crate::{
+    converter::{self, Converter, Error},
+    options::ConvertOptions,
+};
+
+/// Converts many documents with the same options, reusing its buffers (including the JSON
+/// output buffer) from one document to the next.
+pub struct ReusableConverter {
+    converter: Converter,
+    options: ConvertOptions,
+    json: Vec<u8>,
+}
+
+impl Default for ReusableConverter {
+    fn default() -> Self {
+        Self::new(ConvertOptions::default())
+    }
+}
+
+impl ReusableConverter {
+    pub fn new(options: ConvertOptions) -> Self {
+        ReusableConverter {
+            converter: Converter::new(false, options.limits.clone()),
+            options,
+            json: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &ConvertOptions {
+        &self.options
+    }
+
+    pub fn to_value(&mut self, xml: &str) -> Result<serde_json::Value, Error> {
+        let blocks = self.converter.convert(xml)?;
+        Ok(converter::to_value(&blocks, &self.options))
+    }
+
+    /// Converts the XML to a JSON string, which borrows the converter's buffer until the next
+    /// conversion.
+    pub fn to_json(&mut self, xml: &str) -> Result<&str, Error> {
+        let blocks = self.converter.convert(xml)?;
+        self.json.clear();
+        converter::write_json(&blocks, &mut self.json, &self.options, false)?;
+
+        Ok(std::str::from_utf8(&self.json).expect("serde_json always writes UTF-8"))
+    }
+
+    pub fn to_writer<W: io::Write>(&mut self, xml: &str, writer: W) -> Result<(), Error> {
+        let blocks = self.converter.convert(xml)?;
+        converter::write_json(&blocks, writer, &self.options, false)
+    }
+}
@@ -1,108 +1,573 @@
 use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::io;
 use std::sync::Arc;
-use yrs::types::text::YChange;
-use yrs::{Text, Transact, Transaction, Xml, XmlFragment, XmlFragmentRef, XmlOut};
+use yrs::types::text::{ChangeKind, Diff, YChange};
+use yrs::{
+    Any, ReadTxn, Text, Transact, XmlElementRef, XmlFragment, XmlFragmentRef, XmlOut, XmlTextRef,
+};
 
-pub(crate) fn get_fragment_xml(doc: yrs::Doc, fragment_name: String) -> String {
-    let xml = doc.get_or_insert_xml_fragment(fragment_name);
+use crate::converter::Error;
+use crate::encoding::{
+    is_valid_name, is_xml_char, ENCODED_NAMESPACE, ENCODED_PREFIX, TYPED_NAMESPACE, TYPED_PREFIX,
+};
+use crate::{import, inspect};
+
+/// Options controlling the XML produced by `get_fragment_xml_with`.
+#[derive(Debug, Clone, Default)]
+pub struct XmlOptions {
+    canonical: bool,
+    indent: usize,
+}
+
+impl XmlOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Produce canonical XML: attributes (including keys of typed map values) are sorted by
+    /// name and formatting marks are nested in name order, so identical documents always
+    /// produce byte-identical XML.
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// Put each block-level element on its own line, indented by the given number of spaces
+    /// per nesting level. Inline content is never reformatted, since whitespace inside it is
+    /// significant. `0` (the default) disables indentation.
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+}
+
+// Elements whose children are other elements rather than inline content, and so can
+// safely be broken onto separate lines when indenting.
+const STRUCTURAL_TAGS: &[&str] = &[
+    "blockgroup",
+    "blockcontainer",
+    "table",
+    "tablerow",
+    "tablecell",
+    "tableheader",
+];
+
+fn is_structural(tag: &str) -> bool {
+    STRUCTURAL_TAGS
+        .iter()
+        .any(|structural| structural.eq_ignore_ascii_case(tag))
+}
+
+pub(crate) fn get_fragment_xml(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    options: &XmlOptions,
+) -> String {
     let mut out = String::new();
-    serialize_xml_fragment(xml, &mut out, &doc.transact());
+    write_fragment_xml(doc, fragment_name, &mut out, options)
+        .expect("writing to a String cannot fail");
 
     out
 }
 
-fn serialize_xml_fragment(frag: XmlFragmentRef, out: &mut String, txn: &Transaction) {
-    for node in frag.children(txn) {
-        serialize_xml_out(node, out, txn);
+pub(crate) fn write_fragment_xml<W: Write>(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    out: &mut W,
+    options: &XmlOptions,
+) -> fmt::Result {
+    let xml = doc.get_or_insert_xml_fragment(fragment_name);
+    let txn = doc.transact();
+    write_fragment(xml, &txn, out, options)
+}
+
+// Like `get_fragment_xml`, but fails rather than creating the fragment if it doesn't exist
+pub(crate) fn try_get_fragment_xml(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    options: &XmlOptions,
+) -> Result<String, Error> {
+    let txn = doc.transact();
+    let xml = inspect::find_fragment(&txn, fragment_name)?;
+    let mut out = String::new();
+    write_fragment(xml, &txn, &mut out, options).expect("writing to a String cannot fail");
+
+    Ok(out)
+}
+
+pub(crate) fn write_fragment<W: Write, T: ReadTxn>(
+    xml: XmlFragmentRef,
+    txn: &T,
+    out: &mut W,
+    options: &XmlOptions,
+) -> fmt::Result {
+    write_node(XmlOut::Fragment(xml), txn, out, options, None, false)
+}
+
+// Writes a single element (e.g. one blockContainer) the way it's written in its fragment
+pub(crate) fn write_element<W: Write, T: ReadTxn>(
+    elem: XmlElementRef,
+    txn: &T,
+    out: &mut W,
+    options: &XmlOptions,
+) -> fmt::Result {
+    write_node(XmlOut::Element(elem), txn, out, options, None, false)
+}
+
+// Like `write_element`, but leaves out the blockGroups nested in the element, so a
+// blockContainer is written without its child blocks
+pub(crate) fn write_element_shallow<W: Write, T: ReadTxn>(
+    elem: XmlElementRef,
+    txn: &T,
+    out: &mut W,
+    options: &XmlOptions,
+) -> fmt::Result {
+    write_node(XmlOut::Element(elem), txn, out, options, None, true)
+}
+
+// A formatting mark (name and attributes) to wrap a run of text in, on top of its own
+pub(crate) type Mark = (Arc<str>, Any);
+
+// The mark for text inserted or deleted between two snapshots
+pub(crate) fn change_mark(change: YChange) -> Mark {
+    let name = match change.kind {
+        ChangeKind::Added => "insertion",
+        ChangeKind::Removed => "deletion",
+    };
+    (name.into(), Any::Map(Default::default()))
+}
+
+// Returns the contents of a text node, with an extra mark for some runs
+pub(crate) type TextMarks<'a> = dyn FnMut(&XmlTextRef) -> Vec<Diff<Mark>> + 'a;
+
+// Like `write_fragment`, but takes the contents of each text node from `marks`, so that runs
+// can be annotated (e.g. with how they changed between two snapshots)
+pub(crate) fn write_fragment_marked<W: Write, T: ReadTxn>(
+    xml: XmlFragmentRef,
+    txn: &T,
+    out: &mut W,
+    options: &XmlOptions,
+    marks: &mut TextMarks,
+) -> fmt::Result {
+    write_node(XmlOut::Fragment(xml), txn, out, options, Some(marks), false)
+}
+
+fn write_node<'a, W: Write, T: ReadTxn>(
+    node: XmlOut,
+    txn: &'a T,
+    out: &'a mut W,
+    options: &'a XmlOptions,
+    marks: Option<&'a mut TextMarks<'a>>,
+    shallow: bool,
+) -> fmt::Result {
+    let mut serializer = Serializer {
+        out,
+        txn,
+        options,
+        marks,
+        shallow,
+        started: false,
+    };
+    serializer.serialize_node(node, 0)?;
+    if options.indent > 0 && serializer.started {
+        serializer.out.write_char('\n')?;
     }
+
+    Ok(())
 }
 
-fn serialize_xml_out(elem: XmlOut, out: &mut String, txn: &Transaction) {
-    match elem {
-        XmlOut::Element(elem) => {
-            let tag = elem.tag().to_string();
-            out.push_str(&format!("<{}", tag));
+pub(crate) fn write_fragment_xml_io<W: io::Write>(
+    doc: &yrs::Doc,
+    fragment_name: &str,
+    out: W,
+    options: &XmlOptions,
+) -> io::Result<()> {
+    let mut adapter = IoAdapter {
+        inner: out,
+        error: None,
+    };
+    match write_fragment_xml(doc, fragment_name, &mut adapter, options) {
+        Ok(()) => adapter.inner.flush(),
+        Err(_) => Err(adapter
+            .error
+            .unwrap_or_else(|| io::Error::other("failed to serialize fragment"))),
+    }
+}
 
-            for (name, value) in elem.attributes(txn) {
-                out.push_str(&format!(" {}=\"{}\"", name, escape_xml_text(&value)));
-            }
+// `fmt::Error` carries no information, so hold on to the underlying I/O error
+struct IoAdapter<W: io::Write> {
+    inner: W,
+    error: Option<io::Error>,
+}
 
-            out.push('>');
+impl<W: io::Write> Write for IoAdapter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+struct Serializer<'a, W: Write, T: ReadTxn> {
+    out: &'a mut W,
+    txn: &'a T,
+    options: &'a XmlOptions,
+    marks: Option<&'a mut TextMarks<'a>>,
+    // whether to skip blockGroups below the root node
+    shallow: bool,
+    // whether anything has been written yet, so indented output doesn't start with a newline
+    started: bool,
+}
+
+// A pending step of the depth-first walk over the fragment
+enum Frame {
+    Node {
+        node: XmlOut,
+        depth: usize,
+        inline: bool,
+    },
+    Close {
+        tag: String,
+        depth: usize,
+        // whether the children were put on their own lines, so the close tag should be too
+        indented: bool,
+    },
+}
 
-            for child in elem.children(txn) {
-                serialize_xml_out(child, out, txn);
+// Pushes `children` so that they're popped in document order
+fn push_children(stack: &mut Vec<Frame>, children: Vec<XmlOut>, depth: usize, inline: bool) {
+    stack.extend(children.into_iter().rev().map(|node| Frame::Node {
+        node,
+        depth,
+        inline,
+    }));
+}
+
+impl<W: Write, T: ReadTxn> Serializer<'_, W, T> {
+    // Walks the tree with an explicit stack rather than recursing, so that deeply nested
+    // documents can't overflow the call stack.
+    fn serialize_node(&mut self, node: XmlOut, depth: usize) -> fmt::Result {
+        let mut stack = Vec::new();
+        push_children(&mut stack, vec![node], depth, false);
+
+        while let Some(frame) = stack.pop() {
+            match frame {
+                Frame::Node {
+                    node,
+                    depth,
+                    inline,
+                } => self.serialize_xml_out(node, depth, inline, &mut stack)?,
+                Frame::Close {
+                    tag,
+                    depth,
+                    indented,
+                } => {
+                    if indented {
+                        self.newline(depth)?;
+                    }
+                    self.serialize_close_tag(&tag)?;
+                }
             }
+        }
+
+        Ok(())
+    }
+
+    fn newline(&mut self, depth: usize) -> fmt::Result {
+        if self.options.indent == 0 {
+            return Ok(());
+        }
 
-            out.push_str(&format!("</{}>", tag));
+        if self.started {
+            self.out.write_char('\n')?;
         }
-        XmlOut::Fragment(frag) => {
-            serialize_xml_fragment(frag, out, txn);
+        for _ in 0..depth * self.options.indent {
+            self.out.write_char(' ')?;
         }
-        XmlOut::Text(text) => {
-            let diffs = text.diff(txn, YChange::identity);
-            for diff in diffs {
-                if let yrs::Out::Any(yrs::Any::String(s)) = diff.insert {
-                    let mut attributes = Vec::new();
-                    if let Some(attr_map) = &diff.attributes {
-                        attributes.extend(attr_map.iter());
-                    }
 
-                    serialize_diff_insert_string(s, &attributes, out);
+        Ok(())
+    }
+
+    // Writes everything up to the node's children, pushing them (and the close tag) onto `stack`
+    fn serialize_xml_out(
+        &mut self,
+        elem: XmlOut,
+        depth: usize,
+        inline: bool,
+        stack: &mut Vec<Frame>,
+    ) -> fmt::Result {
+        match elem {
+            XmlOut::Element(elem) => {
+                let tag = elem.tag().to_string();
+                if self.shallow && depth > 0 && tag == "blockGroup" {
+                    return Ok(());
+                }
+                if !inline {
+                    self.newline(depth)?;
+                }
+                self.started = true;
+                let encoded = self.serialize_open_tag(&tag)?;
+
+                let attributes = import::read_attributes(self.txn, &elem);
+                self.serialize_attributes(attributes, encoded)?;
+
+                self.out.write_char('>')?;
+
+                let children_inline = inline || !is_structural(&tag);
+                let children = elem.children(self.txn).collect::<Vec<_>>();
+                stack.push(Frame::Close {
+                    tag,
+                    depth,
+                    indented: !children.is_empty() && !children_inline,
+                });
+                push_children(stack, children, depth + 1, children_inline);
+            }
+            XmlOut::Fragment(frag) => {
+                push_children(stack, frag.children(self.txn).collect(), depth, false);
+            }
+            XmlOut::Text(text) => {
+                self.started = true;
+                let diffs = match self.marks.as_mut() {
+                    Some(marks) => marks(&text),
+                    // only diffs between snapshots have changes, so there are no marks to add
+                    None => text.diff(self.txn, change_mark),
+                };
+                for diff in diffs {
+                    if let yrs::Out::Any(yrs::Any::String(s)) = diff.insert {
+                        let mut attributes = Vec::new();
+                        if let Some(attr_map) = &diff.attributes {
+                            attributes.extend(attr_map.iter());
+                        }
+                        if self.options.canonical {
+                            attributes.sort_by(|a, b| a.0.cmp(b.0));
+                        }
+                        // extra marks wrap the text's own formatting
+                        if let Some((name, mark_attributes)) = &diff.ychange {
+                            attributes.insert(0, (name, mark_attributes));
+                        }
+
+                        self.serialize_diff_insert_string(s, &attributes)?;
+                    }
                 }
             }
         }
+
+        Ok(())
     }
-}
 
-// top-level attrs represent wrapping tags (e.g. "bold", "strike", and "link")
-fn serialize_diff_insert_string(
-    s: Arc<str>,
-    diff_attrs: &[(&Arc<str>, &yrs::Any)],
-    out: &mut String,
-) {
-    for (tag_name, attributes) in diff_attrs.iter() {
-        out.push_str(&format!("<{}", tag_name));
+    // top-level attrs represent wrapping tags (e.g. "bold", "strike", and "link")
+    fn serialize_diff_insert_string(
+        &mut self,
+        s: Arc<str>,
+        diff_attrs: &[(&Arc<str>, &yrs::Any)],
+    ) -> fmt::Result {
+        for (tag_name, attributes) in diff_attrs.iter() {
+            let encoded = self.serialize_open_tag(tag_name)?;
+
+            if let yrs::Any::Map(m) = attributes {
+                self.serialize_diff_attr_map(m.clone(), encoded)?;
+            }
+
+            self.out.write_char('>')?;
+        }
+
+        if s.chars().all(is_xml_char) {
+            escape_xml_text(&s, self.out)?;
+        } else {
+            write!(self.out, "<{}:text", ENCODED_PREFIX)?;
+            self.serialize_namespace(ENCODED_PREFIX, ENCODED_NAMESPACE)?;
+            self.out.write_str(" value=\"")?;
+            escape_xml_text(&self.json(&Any::String(s)), self.out)?;
+            self.out.write_str("\"/>")?;
+        }
 
-        if let yrs::Any::Map(m) = attributes {
-            serialize_diff_attr_map(m.clone(), out);
+        for (tag_name, _) in diff_attrs.iter().rev() {
+            self.serialize_close_tag(tag_name)?;
         }
 
-        out.push('>');
+        Ok(())
     }
 
-    out.push_str(&escape_xml_text(&s));
+    fn serialize_diff_attr_map(
+        &mut self,
+        m: Arc<HashMap<String, yrs::Any>>,
+        encoded: bool,
+    ) -> fmt::Result {
+        let attributes = m
+            .iter()
+            .map(|(name, value)| match value {
+                yrs::Any::Null => (name.as_str(), Any::String("".into())),
+                value => (name.as_str(), value.clone()),
+            })
+            .collect::<Vec<_>>();
 
-    for (tag_name, _) in diff_attrs.iter().rev() {
-        out.push_str(&format!("</{}>", tag_name));
+        self.serialize_attributes(attributes, encoded)
     }
-}
 
-fn serialize_diff_attr_map(m: Arc<HashMap<String, yrs::Any>>, out: &mut String) {
-    for (name, value) in m.iter() {
+    // Writes the start of an opening tag, returning true if the tag name had to be encoded
+    // (in which case the encoded namespace has already been declared on the element).
+    fn serialize_open_tag(&mut self, tag: &str) -> Result<bool, fmt::Error> {
+        if is_valid_name(tag) {
+            write!(self.out, "<{}", tag)?;
+            return Ok(false);
+        }
+
+        write!(self.out, "<{}:element", ENCODED_PREFIX)?;
+        self.serialize_namespace(ENCODED_PREFIX, ENCODED_NAMESPACE)?;
+        write!(self.out, " {}:name=\"", ENCODED_PREFIX)?;
+        escape_xml_text(&self.json(&Any::String(tag.into())), self.out)?;
+        self.out.write_char('"')?;
+        Ok(true)
+    }
+
+    fn serialize_close_tag(&mut self, tag: &str) -> fmt::Result {
+        if is_valid_name(tag) {
+            write!(self.out, "</{}>", tag)
+        } else {
+            write!(self.out, "</{}:element>", ENCODED_PREFIX)
+        }
+    }
+
+    // `encoded` is whether the encoded namespace has already been declared on the element
+    fn serialize_attributes<N: AsRef<str>>(
+        &mut self,
+        mut attributes: Vec<(N, Any)>,
+        encoded: bool,
+    ) -> fmt::Result {
+        if self.options.canonical {
+            attributes.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        }
+
+        let mut typed = false;
+        let mut invalid = HashMap::new();
+        for (name, value) in attributes {
+            let name = name.as_ref();
+            if !is_valid_name(name) {
+                if !matches!(value, Any::Undefined) {
+                    invalid.insert(name.to_string(), value);
+                }
+                continue;
+            }
+
+            typed |= self.serialize_attribute(name, &value)?;
+        }
+        if typed {
+            self.serialize_namespace(TYPED_PREFIX, TYPED_NAMESPACE)?;
+        }
+
+        if !invalid.is_empty() {
+            if !encoded {
+                self.serialize_namespace(ENCODED_PREFIX, ENCODED_NAMESPACE)?;
+            }
+            write!(self.out, " {}:attributes=\"", ENCODED_PREFIX)?;
+            escape_xml_text(&self.json(&Any::Map(invalid.into())), self.out)?;
+            self.out.write_char('"')?;
+        }
+
+        Ok(())
+    }
+
+    // Writes a single attribute, returning true if it had to be written as a typed attribute
+    // (in which case the caller must declare the typed namespace on the element).
+    fn serialize_attribute(&mut self, name: &str, value: &Any) -> Result<bool, fmt::Error> {
         match value {
-            yrs::Any::Null => {
-                out.push_str(&format!(" {}=\"\"", name));
+            Any::String(s) if s.chars().all(is_xml_char) => {
+                write!(self.out, " {}=\"", name)?;
+                escape_xml_text(s, self.out)?;
+                self.out.write_char('"')?;
+                Ok(false)
             }
-            yrs::Any::String(s) => {
-                out.push_str(&format!(" {}=\"{}\"", name, escape_xml_text(s)));
+            // JSON has no representation for undefined, so treat it as a missing attribute
+            Any::Undefined => Ok(false),
+            // Strings with characters XML can't represent are written as JSON strings, which
+            // escape them
+            value => {
+                write!(self.out, " {}:{}=\"", TYPED_PREFIX, name)?;
+                escape_xml_text(&self.json(value), self.out)?;
+                self.out.write_char('"')?;
+                Ok(true)
             }
-            _ => {}
         }
     }
+
+    fn serialize_namespace(&mut self, prefix: &str, namespace: &str) -> fmt::Result {
+        write!(self.out, " xmlns:{}=\"{}\"", prefix, namespace)
+    }
+
+    // JSON escapes control characters, but not U+FFFE and U+FFFF, which XML also disallows
+    fn json(&self, value: &Any) -> String {
+        let mut json = String::new();
+        if self.options.canonical {
+            serialize_canonical_json(value, &mut json);
+        } else {
+            value.to_json(&mut json);
+        }
+
+        if json.contains(['\u{FFFE}', '\u{FFFF}']) {
+            json = json
+                .replace('\u{FFFE}', "\\ufffe")
+                .replace('\u{FFFF}', "\\uffff");
+        }
+        json
+    }
 }
 
-fn escape_xml_text(text: &str) -> String {
-    text.chars()
-        .map(|c| match c {
-            '<' => "&lt;".to_string(),
-            '>' => "&gt;".to_string(),
-            '&' => "&amp;".to_string(),
-            '"' => "&quot;".to_string(),
-            '\'' => "&apos;".to_string(),
-            '\n' => "&#10;".to_string(),
-            '\r' => "&#13;".to_string(),
-            '\t' => "&#9;".to_string(),
-            _ => c.to_string(),
-        })
-        .collect()
+// `Any::to_json` writes maps in `HashMap` iteration order, so sort keys ourselves
+fn serialize_canonical_json(value: &Any, out: &mut String) {
+    match value {
+        Any::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                serialize_canonical_json(value, out);
+            }
+            out.push(']');
+        }
+        Any::Map(entries) => {
+            let mut entries = entries.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key).unwrap());
+                out.push(':');
+                serialize_canonical_json(value, out);
+            }
+            out.push('}');
+        }
+        scalar => {
+            // `Any::to_json` writes from the start of the buffer, so it needs a fresh one
+            let mut json = String::new();
+            scalar.to_json(&mut json);
+            out.push_str(&json);
+        }
+    }
+}
+
+fn escape_xml_text<W: Write>(text: &str, out: &mut W) -> fmt::Result {
+    for c in text.chars() {
+        match c {
+            '<' => out.write_str("&lt;")?,
+            '>' => out.write_str("&gt;")?,
+            '&' => out.write_str("&amp;")?,
+            '"' => out.write_str("&quot;")?,
+            '\'' => out.write_str("&apos;")?,
+            '\n' => out.write_str("&#10;")?,
+            '\r' => out.write_str("&#13;")?,
+            '\t' => out.write_str("&#9;")?,
+            // callers encode text containing these, so this is only a safety net to
+            // guarantee the output is always well-formed
+            c if !is_xml_char(c) => out.write_char('\u{FFFD}')?,
+            _ => out.write_char(c)?,
+        }
+    }
+
+    Ok(())
 }
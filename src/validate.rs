@@ -0,0 +1,391 @@
+use std::collections::HashSet;
+
+use roxmltree::{Document, Node};
+use serde_json::Value;
+
+use crate::{
+    block::CONTENTLESS_BLOCKS,
+    converter::Error,
+    diagnostic::{Diagnostic, Severity},
+    encoding,
+    limits::{self, Limits},
+};
+
+const COLORS: &[&str] = &[
+    "default", "gray", "brown", "red", "orange", "yellow", "green", "blue", "purple", "pink",
+];
+// The largest colspan or rowspan accepted, so that a huge span can't make validation allocate
+// a column for every column it covers
+const MAX_SPAN: u32 = 1000;
+const TEXT_ALIGNMENTS: &[&str] = &["left", "center", "right", "justify"];
+const STYLES: &[&str] = &[
+    "bold",
+    "italic",
+    "underline",
+    "strike",
+    "code",
+    "textColor",
+    "backgroundColor",
+    "insertion",
+    "deletion",
+    "author",
+];
+
+pub(crate) fn validate(xml: &str, limits: &Limits) -> Result<Vec<Diagnostic>, Error> {
+    limits::check_input_bytes(xml, limits.max_input_bytes)?;
+    limits::check_depth(xml, limits.max_depth)?;
+    let doc = Document::parse(xml).map_err(Error::ParseError)?;
+    let mut validator = Validator {
+        violations: Vec::new(),
+        block_path: Vec::new(),
+        ids: HashSet::new(),
+    };
+
+    let root = doc.root_element();
+    if validator.expect_tag(root, "blockGroup") {
+        validator.validate_blockgroup(root);
+    }
+
+    Ok(validator.violations)
+}
+
+fn is_tag(node: Node, tag: &str) -> bool {
+    node.is_element() && encoding::tag_name(node).eq_ignore_ascii_case(tag)
+}
+
+fn is_blank_text(node: Node) -> bool {
+    node.is_text() && node.text().is_some_and(|text| text.trim().is_empty())
+}
+
+struct Validator {
+    violations: Vec<Diagnostic>,
+    // ids of the blockcontainers enclosing the node currently being validated
+    block_path: Vec<String>,
+    ids: HashSet<String>,
+}
+
+impl Validator {
+    fn violation(&mut self, node: Node, message: String) {
+        self.violations.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+            pos: node.document().text_pos_at(node.range().start),
+            block_id: self.block_path.last().cloned(),
+        });
+    }
+
+    fn expect_tag(&mut self, node: Node, tag: &str) -> bool {
+        if is_tag(node, tag) {
+            return true;
+        }
+
+        let message = format!("expected {}, found {}", tag, describe(node));
+        self.violation(node, message);
+        false
+    }
+
+    // Reports anything other than elements and formatting whitespace, returning the elements.
+    fn element_children<'a, 'input>(&mut self, node: Node<'a, 'input>) -> Vec<Node<'a, 'input>> {
+        let mut elements = Vec::new();
+        for child in node.children() {
+            if child.is_element() {
+                elements.push(child);
+            } else if !is_blank_text(child) && !child.is_comment() {
+                let message = format!(
+                    "unexpected {} in {}",
+                    describe(child),
+                    encoding::tag_name(node)
+                );
+                self.violation(child, message);
+            }
+        }
+
+        elements
+    }
+
+    fn validate_blockgroup(&mut self, blockgroup: Node) {
+        let children = self.element_children(blockgroup);
+        if children.is_empty() {
+            self.violation(
+                blockgroup,
+                "blockGroup must contain at least one blockContainer".into(),
+            );
+        }
+
+        for child in children {
+            if self.expect_tag(child, "blockContainer") {
+                self.validate_block_container(child);
+            }
+        }
+    }
+
+    fn validate_block_container(&mut self, block_container: Node) {
+        let attributes = encoding::attributes(block_container);
+        let id = attributes
+            .iter()
+            .find(|(name, _)| name == "id")
+            .map(|(_, value)| encoding::into_string(value.clone()));
+
+        self.block_path.push(id.clone().unwrap_or_default());
+        match &id {
+            None => self.violation(block_container, "blockContainer has no id".into()),
+            Some(id) if id.is_empty() => {
+                self.violation(block_container, "blockContainer has an empty id".into())
+            }
+            Some(id) if !self.ids.insert(id.clone()) => {
+                self.violation(block_container, format!("duplicate block id {}", id))
+            }
+            _ => {}
+        }
+
+        self.validate_attributes(block_container, &attributes, "blockContainer");
+
+        let children = self.element_children(block_container);
+        match children.as_slice() {
+            [] => self.violation(
+                block_container,
+                "blockContainer must contain a block".into(),
+            ),
+            [block, rest @ ..] => {
+                if is_tag(*block, "blockGroup") || is_tag(*block, "blockContainer") {
+                    let message = format!("expected a block, found {}", describe(*block));
+                    self.violation(*block, message);
+                } else {
+                    self.validate_block(*block);
+                }
+
+                if let Some(blockgroup) = rest.first() {
+                    if self.expect_tag(*blockgroup, "blockGroup") {
+                        self.validate_blockgroup(*blockgroup);
+                    }
+                }
+                for extra in rest.iter().skip(1) {
+                    let message = format!(
+                        "blockContainer can contain at most a block and a blockGroup, found {}",
+                        describe(*extra)
+                    );
+                    self.violation(*extra, message);
+                }
+            }
+        }
+
+        self.block_path.pop();
+    }
+
+    fn validate_block(&mut self, block: Node) {
+        let type_name = encoding::tag_name(block).into_owned();
+        let attributes = encoding::attributes(block);
+        self.validate_attributes(block, &attributes, &type_name);
+
+        if type_name == "table" {
+            self.validate_table(block);
+        } else if CONTENTLESS_BLOCKS.contains(&type_name.as_str()) {
+            if let Some(child) = block.children().find(|child| !is_blank_text(*child)) {
+                let message = format!(
+                    "{} blocks cannot have content, found {}",
+                    type_name,
+                    describe(child)
+                );
+                self.violation(child, message);
+            }
+        } else {
+            for child in block.children() {
+                self.validate_inline(child);
+            }
+        }
+    }
+
+    fn validate_attributes(&mut self, node: Node, attributes: &[(String, Value)], type_name: &str) {
+        for (name, value) in attributes {
+            let value = encoding::into_string(value.clone());
+            let valid = match (type_name, name.as_str()) {
+                (_, "textColor") | (_, "backgroundColor") => COLORS.contains(&value.as_str()),
+                (_, "textAlignment") => TEXT_ALIGNMENTS.contains(&value.as_str()),
+                // BlockNote only offers levels 1-3 by default, but can be configured up to 6
+                ("heading", "level") => matches!(value.parse::<u8>(), Ok(1..=6)),
+                ("checkListItem", "checked")
+                | ("image", "showPreview")
+                | ("audio", "showPreview")
+                | ("video", "showPreview") => value.parse::<bool>().is_ok(),
+                ("image", "previewWidth") | ("video", "previewWidth") => {
+                    value.parse::<u64>().is_ok_and(|width| width > 0)
+                }
+                _ => true,
+            };
+
+            if !valid {
+                self.violation(
+                    node,
+                    format!("invalid {} {:?} on {}", name, value, type_name),
+                );
+            }
+        }
+    }
+
+    fn validate_inline(&mut self, node: Node) {
+        if node.is_text() || encoding::text(node).is_some() {
+            return;
+        }
+        if !node.is_element() {
+            let message = format!("unexpected {} in inline content", describe(node));
+            self.violation(node, message);
+            return;
+        }
+
+        let tag_name = encoding::tag_name(node);
+        if STYLES.contains(&tag_name.as_ref()) {
+            if matches!(tag_name.as_ref(), "textColor" | "backgroundColor") {
+                let color = encoding::attributes(node)
+                    .into_iter()
+                    .find(|(name, _)| name == "stringValue")
+                    .map(|(_, value)| encoding::into_string(value));
+                match color {
+                    None => self.violation(node, format!("{} has no stringValue", tag_name)),
+                    Some(color) if !COLORS.contains(&color.as_str()) => {
+                        self.violation(node, format!("invalid {} {:?}", tag_name, color))
+                    }
+                    _ => {}
+                }
+            }
+
+            let children = node.children().collect::<Vec<_>>();
+            if children.len() != 1 {
+                self.violation(
+                    node,
+                    format!(
+                        "{} must contain exactly one child, found {}",
+                        tag_name,
+                        children.len()
+                    ),
+                );
+            }
+        } else if tag_name == "link"
+            && !encoding::attributes(node)
+                .iter()
+                .any(|(name, _)| name == "href")
+        {
+            self.violation(node, "link has no href".into());
+        }
+
+        for child in node.children() {
+            self.validate_inline(child);
+        }
+    }
+
+    fn validate_table(&mut self, table: Node) {
+        let rows = self.element_children(table);
+        if rows.is_empty() {
+            self.violation(table, "table must contain at least one tableRow".into());
+        }
+
+        // Number of further rows each column is covered for by a cell with a rowspan
+        let mut spanned: Vec<u32> = Vec::new();
+        let mut width = None;
+        for row in rows {
+            if !self.expect_tag(row, "tableRow") {
+                continue;
+            }
+
+            let mut column = 0;
+            let cells = self.element_children(row);
+            if cells.is_empty() {
+                self.violation(row, "tableRow must contain at least one cell".into());
+            }
+            for cell in cells {
+                if !is_tag(cell, "tableCell") && !is_tag(cell, "tableHeader") {
+                    let message = format!("expected tableCell, found {}", describe(cell));
+                    self.violation(cell, message);
+                    continue;
+                }
+
+                let (colspan, rowspan) = self.validate_table_cell(cell);
+                column = skip_spanned(&spanned, column);
+                for _ in 0..colspan {
+                    if spanned.len() <= column {
+                        spanned.resize(column + 1, 0);
+                    }
+                    spanned[column] = rowspan;
+                    column += 1;
+                }
+            }
+            let row_width = skip_spanned(&spanned, column);
+
+            match width {
+                None => width = Some(row_width),
+                Some(width) if width != row_width => self.violation(
+                    row,
+                    format!("tableRow spans {} columns, expected {}", row_width, width),
+                ),
+                _ => {}
+            }
+
+            for rows in spanned.iter_mut() {
+                *rows = rows.saturating_sub(1);
+            }
+        }
+    }
+
+    // Returns the cell's colspan and rowspan, defaulting to 1 where they're invalid or too large
+    fn validate_table_cell(&mut self, cell: Node) -> (u32, u32) {
+        let mut colspan = 1;
+        let mut rowspan = 1;
+        for (name, value) in encoding::attributes(cell) {
+            let value = encoding::into_string(value);
+            match name.as_str() {
+                "colspan" | "rowspan" => match value.parse::<u32>() {
+                    Ok(span) if span > MAX_SPAN => {
+                        self.violation(cell, format!("{} {} is more than {}", name, span, MAX_SPAN))
+                    }
+                    Ok(span) if span > 0 => {
+                        if name == "colspan" {
+                            colspan = span;
+                        } else {
+                            rowspan = span;
+                        }
+                    }
+                    _ => self.violation(cell, format!("invalid {} {:?}", name, value)),
+                },
+                "colwidth" => {
+                    let width = value.trim_start_matches('[').trim_end_matches(']');
+                    if width != "null" && width.parse::<u32>().is_err() {
+                        self.violation(cell, format!("invalid colwidth {:?}", value));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let children = self.element_children(cell);
+        match children.as_slice() {
+            [paragraph] if is_tag(*paragraph, "tableParagraph") => {
+                for child in paragraph.children() {
+                    self.validate_inline(child);
+                }
+            }
+            _ => self.violation(
+                cell,
+                "tableCell must contain exactly one tableParagraph".into(),
+            ),
+        }
+
+        (colspan, rowspan)
+    }
+}
+
+// Returns the first column from `column` onwards that isn't covered by a cell from a previous row
+fn skip_spanned(spanned: &[u32], mut column: usize) -> usize {
+    while spanned.get(column).is_some_and(|rows| *rows > 0) {
+        column += 1;
+    }
+    column
+}
+
+fn describe(node: Node) -> String {
+    if node.is_element() {
+        format!("<{}>", encoding::tag_name(node))
+    } else if node.is_text() {
+        "text".to_string()
+    } else {
+        format!("{:?} node", node.node_type()).to_lowercase()
+    }
+}
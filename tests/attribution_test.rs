@@ -0,0 +1,94 @@
+mod common;
+
+use atuin_ydoc_convert::{convert_attributed, set_fragment_blocks, ConvertOptions, Error};
+use common::element;
+use serde_json::json;
+use std::collections::BTreeMap;
+use yrs::updates::decoder::Decode;
+use yrs::{
+    Doc, ReadTxn, StateVector, Text, Transact, Update, Xml, XmlElementPrelim, XmlFragment, XmlOut,
+    XmlTextPrelim,
+};
+
+#[test]
+fn test_convert_attributed() {
+    let alice = Doc::with_client_id(1);
+    set_fragment_blocks(
+        &alice,
+        "document-store",
+        &json!([{
+            "id": "1",
+            "type": "paragraph",
+            "props": {"textAlignment": "left"},
+            "content": [{"type": "text", "text": "Hello", "styles": {}}],
+            "children": [],
+        }]),
+    )
+    .unwrap();
+
+    let bob = Doc::with_client_id(2);
+    sync(&alice, &bob);
+    {
+        let fragment = bob.get_or_insert_xml_fragment("document-store");
+        let mut txn = bob.transact_mut();
+        let group = element(fragment.get(&txn, 0));
+        let text = match element(element(group.get(&txn, 0)).get(&txn, 0)).get(&txn, 0) {
+            Some(XmlOut::Text(text)) => text,
+            _ => panic!("expected a text node"),
+        };
+        text.insert(&mut txn, 5, " world");
+        let container = group.push_back(&mut txn, XmlElementPrelim::empty("blockContainer"));
+        container.insert_attribute(&mut txn, "id", "2");
+        let elem = container.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+        elem.push_back(&mut txn, XmlTextPrelim::new("Hi"));
+    }
+    sync(&bob, &alice);
+
+    let attribution = {
+        // the document is only read, so other readers don't get in the way
+        let _txn = alice.transact();
+        convert_attributed(&alice, "document-store", &ConvertOptions::new()).unwrap()
+    };
+    assert_eq!(
+        attribution.blocks,
+        json!([
+            {
+                "id": "1",
+                "type": "paragraph",
+                "props": {"textAlignment": "left"},
+                "content": [
+                    {"type": "text", "text": "Hello", "styles": {"author": "1"}},
+                    {"type": "text", "text": " world", "styles": {"author": "2"}},
+                ],
+                "children": [],
+                "author": "1",
+            },
+            {
+                "id": "2",
+                "type": "paragraph",
+                "props": {},
+                "content": [{"type": "text", "text": "Hi", "styles": {"author": "2"}}],
+                "children": [],
+                "author": "2",
+            },
+        ])
+    );
+    assert_eq!(
+        attribution.characters,
+        BTreeMap::from([("1".to_string(), 5), ("2".to_string(), 8)])
+    );
+
+    assert!(matches!(
+        convert_attributed(&alice, "missing", &ConvertOptions::new()),
+        Err(Error::FragmentNotFound { .. })
+    ));
+}
+
+fn sync(from: &Doc, to: &Doc) {
+    let update = from
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    to.transact_mut()
+        .apply_update(Update::decode_v1(&update).unwrap())
+        .unwrap();
+}
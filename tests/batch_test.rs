@@ -0,0 +1,113 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    convert_batch, convert_to_value, get_fragment_xml, BatchInput, BatchOptions, ConvertOptions,
+    Error,
+};
+use common::doc_from_xml;
+use serde_json::Value;
+use std::fs;
+use yrs::{ReadTxn, StateVector, Transact};
+
+#[test]
+fn test_convert_batch() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let expected = convert_to_value(input.clone()).unwrap();
+
+    let doc = doc_from_xml(&input, "blocknote");
+    let update = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let from_update = convert_to_value(get_fragment_xml(doc, "blocknote".to_string())).unwrap();
+
+    let inputs = vec![
+        BatchInput::Xml(input.clone()),
+        BatchInput::Xml("<blockgroup>".to_string()),
+        BatchInput::Update(update.clone()),
+        BatchInput::Update(vec![0xff, 0xff, 0xff]),
+        BatchInput::Xml(input.clone()),
+    ];
+    let input_bytes = inputs
+        .iter()
+        .map(|input| match input {
+            BatchInput::Xml(xml) => xml.len(),
+            BatchInput::Update(update) => update.len(),
+        })
+        .sum::<usize>();
+
+    let options = BatchOptions::new().threads(3).fragment_name("blocknote");
+    let batch = convert_batch(inputs, &options);
+
+    assert_eq!(batch.results.len(), 5);
+    let value =
+        |i: usize| serde_json::from_str::<Value>(batch.results[i].as_ref().unwrap()).unwrap();
+    assert_eq!(value(0), expected);
+    assert!(matches!(batch.results[1], Err(Error::ParseError(_))));
+    assert_eq!(value(2), from_update);
+    assert!(matches!(batch.results[3], Err(Error::InvalidUpdate(_))));
+    assert_eq!(value(4), expected);
+
+    assert_eq!(batch.stats.documents, 5);
+    assert_eq!(batch.stats.succeeded, 3);
+    assert_eq!(batch.stats.failed, 2);
+    assert_eq!(batch.stats.input_bytes, input_bytes);
+    assert_eq!(
+        batch.stats.output_bytes,
+        batch
+            .results
+            .iter()
+            .flatten()
+            .map(String::len)
+            .sum::<usize>()
+    );
+
+    // the options are passed on to each conversion, and an empty batch is fine
+    let options = BatchOptions::new()
+        .threads(1)
+        .convert(ConvertOptions::new().partial_blocks(true));
+    let batch = convert_batch(vec![input.clone()], &options);
+    assert!(
+        batch.results[0].as_ref().unwrap().len() < serde_json::to_string(&expected).unwrap().len()
+    );
+
+    let batch = convert_batch(Vec::<String>::new(), &BatchOptions::new());
+    assert!(batch.results.is_empty());
+    assert_eq!(batch.stats.documents, 0);
+}
+
+#[test]
+fn test_convert_batch_streams_inputs() {
+    // an iterator of unknown length, whose documents are only made as they're taken
+    let mut next = 0;
+    let inputs = std::iter::from_fn(|| {
+        next += 1;
+        (next <= 200).then(|| {
+            format!(
+                r#"<blockgroup><blockcontainer id="{0}"><paragraph>Document {0}</paragraph></blockcontainer></blockgroup>"#,
+                next
+            )
+        })
+    });
+
+    let batch = convert_batch(inputs, &BatchOptions::new().threads(4));
+    assert_eq!(batch.stats.documents, 200);
+    assert_eq!(batch.stats.succeeded, 200);
+    for (i, result) in batch.results.iter().enumerate() {
+        let value = serde_json::from_str::<Value>(result.as_ref().unwrap()).unwrap();
+        assert_eq!(value[0]["id"], (i + 1).to_string());
+    }
+}
+
+#[test]
+fn test_convert_batch_missing_fragment() {
+    let doc = doc_from_xml("<blockGroup></blockGroup>", "blocknote");
+    let update = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+
+    let batch = convert_batch(vec![update], &BatchOptions::new().fragment_name("missing"));
+    assert!(matches!(
+        &batch.results[0],
+        Err(Error::FragmentNotFound { name }) if name == "missing"
+    ));
+}
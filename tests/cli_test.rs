@@ -0,0 +1,92 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    convert_to_value, convert_to_value_with, BlockNoteVersion, ConvertOptions,
+};
+use common::doc_from_xml;
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use yrs::{ReadTxn, StateVector, Text, Transact};
+
+fn ydoc_convert(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ydoc-convert"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn json(output: &Output) -> Value {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_cli() {
+    let path = "tests/fixtures/everything_input.xml";
+    let input = fs::read_to_string(path).unwrap();
+    let expected = convert_to_value(input.clone()).unwrap();
+
+    // XML from a file, with the format guessed
+    assert_eq!(json(&ydoc_convert(&[path], b"")), expected);
+
+    // a Yjs update from stdin, in a named fragment
+    let doc = doc_from_xml(&input, "blocknote");
+    let title = doc.get_or_insert_text("title");
+    title.insert(&mut doc.transact_mut(), 0, "Deploy");
+    let update = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let output = ydoc_convert(&["--to", "pretty", "-n", "blocknote"], &update);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\n  {\n"));
+    assert_eq!(json(&output), expected);
+
+    // with metadata from the same update
+    let output = ydoc_convert(&["-n", "blocknote", "--meta", "title"], &update);
+    assert_eq!(
+        json(&output),
+        serde_json::json!({"meta": {"title": "Deploy"}, "blocks": expected})
+    );
+
+    // BlockNote JSON to XML and back, with the converter's options
+    let xml = ydoc_convert(
+        &["--to", "xml", "--indent", "2"],
+        expected.to_string().as_bytes(),
+    );
+    assert!(xml.status.success());
+    let output = ydoc_convert(
+        &["-f", "xml", "--blocknote", "0.23", "--partial-blocks"],
+        &xml.stdout,
+    );
+    assert_eq!(
+        json(&output),
+        convert_to_value_with(
+            input,
+            &ConvertOptions::new()
+                .version(BlockNoteVersion::V0_23)
+                .partial_blocks(true)
+        )
+        .unwrap()
+    );
+
+    let output = ydoc_convert(&[path, "--max-blocks", "1"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("more than 1 blocks"));
+
+    let output = ydoc_convert(&[], &update);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no shared type named document-store"));
+
+    let output = ydoc_convert(&["--to", "yaml"], b"");
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use atuin_ydoc_convert::{convert_to_value, get_fragment_xml_ref, XmlOptions};
+use roxmltree::{Document, Node};
+use serde_json::Value;
+use yrs::{
+    Any, Doc, Text, Transact, TransactionMut, Xml, XmlElementPrelim, XmlElementRef, XmlFragment,
+    XmlFragmentRef, XmlOut, XmlTextPrelim, XmlTextRef,
+};
+
+// Tags that y-prosemirror stores as formatting attributes on Y.XmlText rather than as elements
+const MARK_TAGS: &[&str] = &[
+    "bold",
+    "italic",
+    "underline",
+    "strike",
+    "code",
+    "textColor",
+    "backgroundColor",
+    "link",
+];
+
+/// Builds a yrs document containing the given BlockNote XML in the named fragment,
+/// the same way y-prosemirror would store it.
+#[allow(dead_code)]
+pub fn doc_from_xml(xml: &str, fragment_name: &str) -> Doc {
+    let parsed = Document::parse(xml).unwrap();
+    let doc = Doc::new();
+    let fragment = doc.get_or_insert_xml_fragment(fragment_name);
+    {
+        let mut txn = doc.transact_mut();
+        let root = fragment_push_element(&fragment, &mut txn, parsed.root_element());
+        build_children(&mut txn, &root, parsed.root_element());
+    }
+
+    doc
+}
+
+/// Converts the `document-store` fragment as it is now.
+#[allow(dead_code)]
+pub fn current(doc: &Doc) -> Value {
+    convert_to_value(get_fragment_xml_ref(
+        doc,
+        "document-store",
+        &XmlOptions::default(),
+    ))
+    .unwrap()
+}
+
+/// Unwraps a node that should be an element.
+#[allow(dead_code)]
+pub fn element(node: Option<XmlOut>) -> XmlElementRef {
+    match node {
+        Some(XmlOut::Element(elem)) => elem,
+        _ => panic!("expected an element"),
+    }
+}
+
+fn fragment_push_element(
+    fragment: &XmlFragmentRef,
+    txn: &mut TransactionMut,
+    node: Node,
+) -> XmlElementRef {
+    let elem = fragment.push_back(txn, XmlElementPrelim::empty(node.tag_name().name()));
+    for attr in node.attributes() {
+        elem.insert_attribute(txn, attr.name(), attr.value());
+    }
+    elem
+}
+
+fn build_children(txn: &mut TransactionMut, parent: &XmlElementRef, node: Node) {
+    let mut text: Option<XmlTextRef> = None;
+    for child in node.children() {
+        if child.is_text() || is_mark(child) {
+            if child.is_text() && child.text().unwrap().trim().is_empty() && text.is_none() {
+                // formatting whitespace between elements
+                continue;
+            }
+            let text = text.get_or_insert_with(|| parent.push_back(txn, XmlTextPrelim::new("")));
+            push_inline(txn, text, child, &mut HashMap::new());
+        } else if child.is_element() {
+            text = None;
+            let elem = parent.push_back(txn, XmlElementPrelim::empty(child.tag_name().name()));
+            for attr in child.attributes() {
+                elem.insert_attribute(txn, attr.name(), attr.value());
+            }
+            build_children(txn, &elem, child);
+        }
+    }
+}
+
+fn is_mark(node: Node) -> bool {
+    node.is_element() && MARK_TAGS.contains(&node.tag_name().name())
+}
+
+fn push_inline(
+    txn: &mut TransactionMut,
+    text: &XmlTextRef,
+    node: Node,
+    marks: &mut HashMap<std::sync::Arc<str>, Any>,
+) {
+    if node.is_text() {
+        let index = text.len(txn);
+        text.insert_with_attributes(txn, index, node.text().unwrap(), marks.clone());
+        return;
+    }
+
+    let attrs = node
+        .attributes()
+        .map(|attr| (attr.name().to_string(), Any::String(attr.value().into())))
+        .collect::<HashMap<_, _>>();
+    let name: std::sync::Arc<str> = node.tag_name().name().into();
+    marks.insert(name.clone(), Any::Map(attrs.into()));
+    for child in node.children() {
+        push_inline(txn, text, child, marks);
+    }
+    marks.remove(&name);
+}
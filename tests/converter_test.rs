@@ -1,6 +1,11 @@
 use assert_json_diff::assert_json_include;
-use atuin_ydoc_convert::convert_to_value;
-use serde_json::Value;
+use atuin_ydoc_convert::{
+    convert_bytes_to_json, convert_bytes_to_value, convert_str_to_value, convert_to_json_with,
+    convert_to_value, convert_to_value_lenient, convert_to_value_lenient_with,
+    convert_to_value_with, convert_to_value_with_limits, convert_to_writer, BlockNoteVersion,
+    ConvertOptions, Error, ErrorKind, LimitKind, Limits, ReusableConverter, Severity,
+};
+use serde_json::{json, Value};
 use std::fs;
 
 fn assert_json_incl(expected: &Value, actual: &Value) {
@@ -475,3 +480,390 @@ fn test_everything_bagel() {
 
     assert_json_incl(&expected, &result);
 }
+
+#[test]
+fn test_convert_lenient() {
+    let input = r#"
+    <blockgroup>
+      <blockcontainer backgroundColor="default" id="broken" textColor="default"></blockcontainer>
+      <blockcontainer backgroundColor="default" id="styled" textColor="default">
+        <paragraph textAlignment="left">Some <bold></bold>text</paragraph>
+      </blockcontainer>
+      <blockcontainer backgroundColor="default" id="table" textColor="default">
+        <table textColor="default">
+          <tableRow>
+            <tableCell colspan="wide" rowspan="1"><tableParagraph>Cell</tableParagraph></tableCell>
+          </tableRow>
+        </table>
+      </blockcontainer>
+    </blockgroup>
+    "#;
+
+    assert!(convert_to_value(input.to_string()).is_err());
+
+    let (result, diagnostics) = convert_to_value_lenient(input.to_string()).unwrap();
+
+    let expected: Value = serde_json::from_str(
+        r#"
+    [
+      {
+        "id": "styled",
+        "type": "paragraph",
+        "content": [
+          { "type": "text", "text": "Some ", "styles": {} },
+          { "type": "text", "text": "text", "styles": {} }
+        ],
+        "children": []
+      },
+      {
+        "id": "table",
+        "type": "table",
+        "content": {
+          "type": "tableContent",
+          "rows": [
+            { "cells": [[{ "type": "text", "text": "Cell", "styles": {} }]] }
+          ]
+        }
+      }
+    ]
+    "#,
+    )
+    .unwrap();
+    assert_json_incl(&expected, &result);
+    assert_eq!(result.as_array().unwrap().len(), 2);
+
+    let summary = diagnostics
+        .iter()
+        .map(|d| {
+            (
+                d.severity,
+                d.message.as_str(),
+                d.block_id.as_deref(),
+                d.pos.row,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                Severity::Error,
+                "blockcontainer with no children",
+                Some("broken"),
+                3
+            ),
+            (
+                Severity::Error,
+                "style tag with no children",
+                Some("styled"),
+                5
+            ),
+            (Severity::Warning, "Invalid colspan", Some("table"), 10),
+        ]
+    );
+}
+
+#[test]
+fn test_convert_lenient_with() {
+    let input = r#"
+    <blockgroup>
+      <blockcontainer backgroundColor="default" id="broken" textColor="default"></blockcontainer>
+      <blockcontainer backgroundColor="default" id="kept" textColor="default">
+        <paragraph textAlignment="left">Text</paragraph>
+      </blockcontainer>
+    </blockgroup>
+    "#;
+
+    let options = ConvertOptions::new().drop_default_props(true);
+    let (result, diagnostics) = convert_to_value_lenient_with(input.to_string(), &options).unwrap();
+    assert_eq!(
+        result,
+        json!([{
+            "id": "kept",
+            "type": "paragraph",
+            "props": {},
+            "content": [{ "type": "text", "text": "Text", "styles": {} }],
+            "children": []
+        }])
+    );
+    assert_eq!(diagnostics.len(), 1);
+
+    // limits are still errors
+    let options = ConvertOptions::new().limits(Limits::new().max_blocks(1));
+    let input = input.replace(
+        "id=\"broken\" textColor=\"default\"></blockcontainer>",
+        "id=\"other\" textColor=\"default\"><paragraph>Other</paragraph></blockcontainer>",
+    );
+    assert!(matches!(
+        convert_to_value_lenient_with(input, &options),
+        Err(Error::LimitExceeded { .. })
+    ));
+}
+
+#[test]
+fn test_malformed_document_error() {
+    let input = r#"<blockgroup><blockcontainer id="outer"><paragraph>Outer</paragraph><blockgroup><blockcontainer id="inner"><table><tablerow><tablecell colspan="wide"><tableparagraph>Cell</tableparagraph></tablecell></tablerow></table></blockcontainer></blockgroup></blockcontainer></blockgroup>"#;
+
+    let Err(Error::MalformedDocument {
+        kind,
+        pos,
+        block_path,
+    }) = convert_to_value(input.to_string())
+    else {
+        panic!("expected a malformed document error");
+    };
+
+    assert_eq!(kind, ErrorKind::InvalidSpan("colspan".to_string()));
+    assert_eq!((pos.row, pos.col), (1, 124));
+    assert_eq!(block_path, vec!["outer".to_string(), "inner".to_string()]);
+}
+
+fn nested_blocks(levels: usize) -> String {
+    let mut xml = String::new();
+    for i in 0..levels {
+        xml.push_str(&format!(
+            r#"<blockgroup><blockcontainer id="{}"><paragraph>Level {}</paragraph>"#,
+            i, i
+        ));
+    }
+    for _ in 0..levels {
+        xml.push_str("</blockcontainer></blockgroup>");
+    }
+    xml
+}
+
+#[test]
+fn test_depth_limit() {
+    // deep enough to overflow the stack if conversion recursed without a limit
+    let Err(Error::DepthLimitExceeded { max_depth, pos }) =
+        convert_to_value(nested_blocks(100_000))
+    else {
+        panic!("expected a depth limit error");
+    };
+    assert_eq!(max_depth, 128);
+    // the paragraph in the 64th level of blocks, 129 elements deep
+    assert_eq!((pos.row, pos.col), (1, 4238));
+
+    let mut styled = "<bold>".repeat(100_000);
+    styled.push_str(&"</bold>".repeat(100_000));
+    let xml = format!(
+        r#"<blockgroup><blockcontainer id="1"><paragraph>{}</paragraph></blockcontainer></blockgroup>"#,
+        styled
+    );
+    assert!(matches!(
+        convert_to_value(xml),
+        Err(Error::DepthLimitExceeded { .. })
+    ));
+
+    assert!(convert_to_value(nested_blocks(63)).is_ok());
+
+    // three levels of blocks are 6 elements deep, plus one for the innermost paragraph
+    let limits = Limits::new().max_depth(6);
+    assert!(convert_to_value_with_limits(nested_blocks(3), &limits).is_err());
+    let limits = Limits::new().max_depth(7);
+    assert!(convert_to_value_with_limits(nested_blocks(3), &limits).is_ok());
+}
+
+#[test]
+fn test_limits() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    assert!(convert_to_value_with_limits(input.clone(), &Limits::new()).is_ok());
+
+    let limits = Limits::new().max_input_bytes(1024);
+    assert!(matches!(
+        convert_to_value_with_limits(input.clone(), &limits),
+        Err(Error::InputTooLarge {
+            max_bytes: 1024,
+            ..
+        })
+    ));
+
+    let exceeded = |limits: Limits| match convert_to_value_with_limits(input.clone(), &limits) {
+        Err(Error::LimitExceeded { limit, max, .. }) => Some((limit, max)),
+        _ => None,
+    };
+    assert_eq!(
+        exceeded(Limits::new().max_blocks(3)),
+        Some((LimitKind::Blocks, 3))
+    );
+    assert_eq!(
+        exceeded(Limits::new().max_inline_items(2)),
+        Some((LimitKind::InlineItems, 2))
+    );
+    assert_eq!(
+        exceeded(Limits::new().max_table_cells(2)),
+        Some((LimitKind::TableCells, 2))
+    );
+    assert_eq!(
+        exceeded(
+            Limits::new()
+                .max_input_bytes(input.len())
+                .max_blocks(1000)
+                .max_inline_items(1000)
+                .max_table_cells(1000)
+        ),
+        None
+    );
+}
+
+#[test]
+fn test_convert_options() {
+    let input = r#"
+    <blockgroup>
+      <blockcontainer backgroundColor="default" id="heading" textColor="red">
+        <heading level="1" textAlignment="left" isToggleable="false">Title</heading>
+        <blockgroup>
+          <blockcontainer backgroundColor="default" id="image" textColor="default">
+            <image url="https://atuin.sh/logo.png" caption="" showPreview="true"></image>
+          </blockcontainer>
+        </blockgroup>
+      </blockcontainer>
+      <blockcontainer backgroundColor="default" id="table" textColor="default">
+        <table textColor="default">
+          <tableRow>
+            <tableCell colspan="2" rowspan="1" backgroundColor="blue"><tableParagraph>Wide</tableParagraph></tableCell>
+          </tableRow>
+        </table>
+      </blockcontainer>
+    </blockgroup>
+    "#;
+
+    let options = ConvertOptions::new()
+        .omit_empty_content(true)
+        .omit_empty_children(true);
+    let result = convert_to_value_with(input.to_string(), &options).unwrap();
+    assert_eq!(result[0]["content"][0]["text"], json!("Title"));
+    assert_eq!(result[0]["props"]["textAlignment"], json!("left"));
+    assert_eq!(result[0]["children"][0].get("content"), None);
+    assert_eq!(result[0]["children"][0].get("children"), None);
+    assert_eq!(result[1].get("children"), None);
+
+    let options = ConvertOptions::new().drop_default_props(true);
+    let result = convert_to_value_with(input.to_string(), &options).unwrap();
+    assert_eq!(result[0]["props"], json!({ "textColor": "red" }));
+    assert_eq!(
+        result[0]["children"][0]["props"],
+        json!({ "url": "https://atuin.sh/logo.png" })
+    );
+    assert_eq!(result[0]["children"][0]["content"], json!([]));
+
+    let options = ConvertOptions::new()
+        .partial_blocks(true)
+        .version(BlockNoteVersion::V0_23);
+    let result = convert_to_value_with(input.to_string(), &options).unwrap();
+    assert_eq!(
+        result,
+        json!([
+          {
+            "id": "heading",
+            "type": "heading",
+            "props": { "textColor": "red" },
+            "content": [{ "type": "text", "text": "Title" }],
+            "children": [
+              {
+                "id": "image",
+                "type": "image",
+                "props": { "url": "https://atuin.sh/logo.png" }
+              }
+            ]
+          },
+          {
+            "id": "table",
+            "type": "table",
+            "content": {
+              "type": "tableContent",
+              "columnWidths": [null],
+              "rows": [
+                {
+                  "cells": [
+                    {
+                      "type": "tableCell",
+                      "props": { "backgroundColor": "blue", "colspan": 2 },
+                      "content": [{ "type": "text", "text": "Wide" }]
+                    }
+                  ]
+                }
+              ]
+            }
+          }
+        ])
+    );
+}
+
+struct FailingWriter;
+
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("disk full"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_convert_to_writer() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+
+    let mut json = Vec::new();
+    convert_to_writer(input.clone(), &mut json).unwrap();
+    assert_eq!(
+        serde_json::from_slice::<Value>(&json).unwrap(),
+        convert_to_value(input.clone()).unwrap()
+    );
+
+    let options = ConvertOptions::new()
+        .partial_blocks(true)
+        .version(BlockNoteVersion::V0_23);
+    let json = convert_to_json_with(input.clone(), &options).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&json).unwrap(),
+        convert_to_value_with(input.clone(), &options).unwrap()
+    );
+
+    assert!(matches!(
+        convert_to_writer(input, FailingWriter),
+        Err(Error::Io(_))
+    ));
+}
+
+#[test]
+fn test_borrowed_input() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let expected = convert_to_value(input.clone()).unwrap();
+
+    assert_eq!(convert_str_to_value(&input).unwrap(), expected);
+    assert_eq!(convert_bytes_to_value(input.as_bytes()).unwrap(), expected);
+    let json = convert_bytes_to_json(input.as_bytes()).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), expected);
+
+    assert!(matches!(
+        convert_bytes_to_value(b"<blockgroup>\xff</blockgroup>"),
+        Err(Error::InvalidUtf8(_))
+    ));
+}
+
+#[test]
+fn test_reusable_converter() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let expected = convert_to_value(input.clone()).unwrap();
+    let blocks = expected.as_array().unwrap().len();
+
+    // the block limit has to apply to each document separately
+    let mut converter =
+        ReusableConverter::new(ConvertOptions::new().limits(Limits::new().max_blocks(blocks * 2)));
+    for _ in 0..3 {
+        assert_eq!(converter.to_value(&input).unwrap(), expected);
+        let json = converter.to_json(&input).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(json).unwrap(), expected);
+    }
+
+    assert!(matches!(
+        converter.to_json("<blockgroup>"),
+        Err(Error::ParseError(_))
+    ));
+    let mut json = Vec::new();
+    converter.to_writer(&input, &mut json).unwrap();
+    assert_eq!(serde_json::from_slice::<Value>(&json).unwrap(), expected);
+}
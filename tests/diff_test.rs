@@ -0,0 +1,107 @@
+use atuin_ydoc_convert::{
+    convert_to_value, diff_blocks, BlockChange, BlockPosition, Error, PropChange, TextChange,
+};
+use serde_json::{json, Value};
+use std::fs;
+
+fn paragraph(id: &str, text: &str, alignment: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "paragraph",
+        "props": {"textColor": "default", "backgroundColor": "default", "textAlignment": alignment},
+        "content": [{"type": "text", "text": text, "styles": {}}],
+        "children": [],
+    })
+}
+
+fn position(parent: Option<&str>, index: usize) -> BlockPosition {
+    BlockPosition {
+        parent: parent.map(str::to_string),
+        index,
+    }
+}
+
+#[test]
+fn test_diff_blocks() {
+    let old = json!([
+        paragraph("a", "Check the pods", "left"),
+        paragraph("b", "Setup", "left"),
+        paragraph("c", "Remove me", "left"),
+        {
+            "id": "d",
+            "type": "heading",
+            "props": {"level": 2},
+            "content": [{"type": "text", "text": "Deploy", "styles": {}}],
+            "children": [paragraph("e", "Nested", "left")],
+        },
+    ]);
+    let new = json!([
+        paragraph("b", "Setup", "left"),
+        {
+            "id": "d",
+            "type": "heading",
+            "props": {"level": 2},
+            "content": [{"type": "text", "text": "Deploy", "styles": {"bold": true}}],
+            "children": [],
+        },
+        paragraph("f", "New", "left"),
+        paragraph("e", "Nested", "left"),
+        paragraph("a", "Check all the pods now", "center"),
+    ]);
+
+    assert_eq!(
+        diff_blocks(&old, &new).unwrap(),
+        vec![
+            BlockChange::Modified {
+                id: "d".to_string(),
+                type_change: None,
+                props: vec![],
+                content_changed: true,
+                text: vec![],
+            },
+            BlockChange::Added {
+                id: "f".to_string(),
+                position: position(None, 2),
+            },
+            BlockChange::Moved {
+                id: "e".to_string(),
+                from: position(Some("d"), 0),
+                to: position(None, 3),
+            },
+            BlockChange::Moved {
+                id: "a".to_string(),
+                from: position(None, 0),
+                to: position(None, 4),
+            },
+            BlockChange::Modified {
+                id: "a".to_string(),
+                type_change: None,
+                props: vec![PropChange {
+                    name: "textAlignment".to_string(),
+                    old: Some(json!("left")),
+                    new: Some(json!("center")),
+                }],
+                content_changed: true,
+                text: vec![
+                    TextChange::Equal("Check ".to_string()),
+                    TextChange::Insert("all ".to_string()),
+                    TextChange::Equal("the pods".to_string()),
+                    TextChange::Insert(" now".to_string()),
+                ],
+            },
+            BlockChange::Removed {
+                id: "c".to_string(),
+                position: position(None, 2),
+            },
+        ]
+    );
+
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let document = convert_to_value(input).unwrap();
+    assert_eq!(diff_blocks(&document, &document).unwrap(), vec![]);
+
+    assert!(matches!(
+        diff_blocks(&json!([{"id": "a"}]), &new),
+        Err(Error::InvalidBlocks(_))
+    ));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    convert_document, convert_document_to_json, convert_to_value, convert_to_value_with,
+    ConvertOptions, DocumentOptions, Error,
+};
+use common::doc_from_xml;
+use serde_json::{json, Value};
+use std::fs;
+use yrs::updates::decoder::Decode;
+use yrs::{Array, Doc, Map, MapPrelim, ReadTxn, StateVector, Text, Transact, Update};
+
+#[test]
+fn test_convert_document() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let doc = doc_from_xml(&input, "blocknote");
+    {
+        let settings = doc.get_or_insert_map("settings");
+        let title = doc.get_or_insert_text("title");
+        let tags = doc.get_or_insert_array("tags");
+        let mut txn = doc.transact_mut();
+        settings.insert(&mut txn, "autoRun", false);
+        settings.insert(
+            &mut txn,
+            "env",
+            MapPrelim::from([("REGION".to_string(), "eu-west-1".to_string())]),
+        );
+        title.insert(&mut txn, 0, "Deploy");
+        tags.insert_range(&mut txn, 0, ["ops", "deploy"]);
+    }
+
+    let options = DocumentOptions::new()
+        .fragment_name("blocknote")
+        .meta("title")
+        .meta("settings")
+        .meta("tags")
+        .meta("missing");
+    let expected = json!({
+        "meta": {
+            "title": "Deploy",
+            "settings": {"autoRun": false, "env": {"REGION": "eu-west-1"}},
+            "tags": ["ops", "deploy"],
+        },
+        "blocks": convert_to_value(input.clone()).unwrap(),
+    });
+    assert_eq!(convert_document(&doc, &options).unwrap(), expected);
+
+    // the same from a document that was received as an update
+    let update = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let remote = Doc::new();
+    remote
+        .transact_mut()
+        .apply_update(Update::decode_v1(&update).unwrap())
+        .unwrap();
+    let json = convert_document_to_json(&remote, &options).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), expected);
+
+    let options = options.convert(ConvertOptions::new().partial_blocks(true));
+    assert_eq!(
+        convert_document(&doc, &options).unwrap()["blocks"],
+        convert_to_value_with(input, &ConvertOptions::new().partial_blocks(true)).unwrap()
+    );
+
+    // the blocks have to be there, unlike the metadata
+    assert!(matches!(
+        convert_document(&doc, &DocumentOptions::new()),
+        Err(Error::FragmentNotFound { .. })
+    ));
+    assert_eq!(
+        convert_document(&doc, &DocumentOptions::new().fragment_name("blocknote")).unwrap()["meta"],
+        json!({})
+    );
+}
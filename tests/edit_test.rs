@@ -0,0 +1,190 @@
+mod common;
+
+use atuin_ydoc_convert::{edit_blocks, find_block, set_fragment_blocks, BlockEdits, Error};
+use common::{current, element};
+use serde_json::json;
+use yrs::{Doc, Transact, Xml, XmlFragment};
+
+fn doc() -> Doc {
+    let doc = Doc::new();
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([
+            {
+                "id": "check",
+                "type": "checkListItem",
+                "props": {"checked": false, "textColor": "default"},
+                "content": [{"type": "text", "text": "Deploy", "styles": {}}],
+                "children": [{
+                    "id": "nested",
+                    "type": "paragraph",
+                    "props": {},
+                    "content": [{"type": "text", "text": "Nested", "styles": {"bold": true}}],
+                    "children": [],
+                }],
+            },
+            {
+                "id": "run",
+                "type": "run",
+                "props": {"code": "echo hi", "name": ""},
+                "children": [],
+            },
+        ]),
+    )
+    .unwrap();
+
+    doc
+}
+
+#[test]
+fn test_edit_blocks() {
+    let doc = doc();
+    let edits = BlockEdits::new()
+        .update_props("check", json!({"checked": true, "textColor": "red"}))
+        .update_props("run", json!({"code": "echo bye", "name": null}))
+        .replace_content(
+            "check",
+            json!([{"type": "text", "text": "Deployed", "styles": {"italic": true}}]),
+        )
+        .insert_after(
+            "run",
+            json!([{
+                "id": "new",
+                "type": "paragraph",
+                "props": {},
+                "content": [{"type": "text", "text": "New", "styles": {}}],
+                "children": [],
+            }]),
+        )
+        .move_before("nested", "check")
+        .delete("run");
+    edit_blocks(&doc, "document-store", &edits).unwrap();
+
+    assert_eq!(
+        current(&doc),
+        json!([
+            {
+                "id": "nested",
+                "type": "paragraph",
+                "props": {},
+                "content": [{"type": "text", "text": "Nested", "styles": {"bold": true}}],
+                "children": [],
+            },
+            {
+                "id": "check",
+                "type": "checkListItem",
+                "props": {"checked": true, "textColor": "red"},
+                "content": [{"type": "text", "text": "Deployed", "styles": {"italic": true}}],
+                "children": [],
+            },
+            {
+                "id": "new",
+                "type": "paragraph",
+                "props": {},
+                "content": [{"type": "text", "text": "New", "styles": {}}],
+                "children": [],
+            },
+        ])
+    );
+
+    let block = find_block(&doc, "document-store", "new").unwrap();
+    assert_eq!(
+        block.get_attribute(&doc.transact(), "id").as_deref(),
+        Some("new")
+    );
+}
+
+#[test]
+fn test_move_keeps_props_and_marks() {
+    let doc = Doc::new();
+    let blocks = json!([
+        {
+            "id": "heading",
+            "type": "heading",
+            "props": {"level": 2, "textColor": "red", "backgroundColor": "blue"},
+            "content": [
+                {"type": "text", "text": "Bold ", "styles": {"bold": true}},
+                {
+                    "type": "link",
+                    "href": "https://atuin.sh",
+                    "content": [{"type": "text", "text": "link", "styles": {"italic": true}}],
+                },
+            ],
+            "children": [],
+        },
+        {
+            "id": "last",
+            "type": "paragraph",
+            "props": {},
+            "content": [],
+            "children": [],
+        },
+    ]);
+    set_fragment_blocks(&doc, "document-store", &blocks).unwrap();
+    let before = current(&doc);
+
+    let edits = BlockEdits::new().move_after("heading", "last");
+    edit_blocks(&doc, "document-store", &edits).unwrap();
+
+    let moved = current(&doc);
+    assert_eq!(moved[0]["id"], "last");
+    assert_eq!(moved[1], before[0]);
+}
+
+#[test]
+fn test_delete_every_block() {
+    let doc = doc();
+
+    let edits = BlockEdits::new().delete("check").delete("run");
+    edit_blocks(&doc, "document-store", &edits).unwrap();
+    assert_eq!(current(&doc), json!([]));
+
+    // the emptied top-level blockGroup is kept
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    let txn = doc.transact();
+    assert_eq!(fragment.len(&txn), 1);
+    assert_eq!(element(fragment.get(&txn, 0)).len(&txn), 0);
+}
+
+#[test]
+fn test_update_props_out_of_range() {
+    let doc = doc();
+    let before = current(&doc);
+
+    let edits = BlockEdits::new()
+        .update_props("run", json!({"code": "echo bye"}))
+        .update_props("check", json!({"big": u64::MAX}));
+    let result = edit_blocks(&doc, "document-store", &edits);
+    assert!(matches!(result, Err(Error::InvalidBlocks(_))));
+    assert_eq!(current(&doc), before);
+}
+
+#[test]
+fn test_edit_blocks_is_all_or_nothing() {
+    let doc = doc();
+    let before = current(&doc);
+
+    // the nested block is gone by the time it's moved
+    let edits = BlockEdits::new()
+        .update_props("run", json!({"code": "echo bye"}))
+        .delete("check")
+        .move_after("nested", "run");
+    let result = edit_blocks(&doc, "document-store", &edits);
+    assert!(matches!(result, Err(Error::BlockNotFound { id }) if id == "nested"));
+
+    let edits = BlockEdits::new().move_after("check", "nested");
+    let result = edit_blocks(&doc, "document-store", &edits);
+    assert!(matches!(result, Err(Error::InvalidEdit(_))));
+
+    let edits =
+        BlockEdits::new().insert_before("run", json!([{"id": "check", "type": "paragraph"}]));
+    let result = edit_blocks(&doc, "document-store", &edits);
+    assert!(matches!(result, Err(Error::InvalidBlocks(_))));
+
+    assert_eq!(current(&doc), before);
+    assert!(matches!(
+        find_block(&doc, "document-store", "missing"),
+        Err(Error::BlockNotFound { .. })
+    ));
+}
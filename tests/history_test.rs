@@ -0,0 +1,181 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    convert_at, convert_changes, convert_changes_to_json, convert_to_json_at, convert_to_value,
+    get_fragment_xml_at, get_fragment_xml_at_with, get_fragment_xml_ref, set_fragment_blocks,
+    ConvertOptions, Error, LiveDocument, XmlOptions,
+};
+use common::element;
+use serde_json::{json, Value};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use yrs::{
+    Doc, Options, ReadTxn, StateVector, Text, Transact, Xml, XmlElementPrelim, XmlFragment, XmlOut,
+    XmlTextPrelim,
+};
+
+#[test]
+fn test_convert_at() {
+    let doc = Doc::with_options(Options {
+        skip_gc: true,
+        ..Default::default()
+    });
+    let v1 = json!([{
+        "id": "1",
+        "type": "paragraph",
+        "props": {"textColor": "default", "backgroundColor": "default", "textAlignment": "left"},
+        "content": [{"type": "text", "text": "kubectl get pods", "styles": {}}],
+        "children": [],
+    }]);
+    set_fragment_blocks(&doc, "document-store", &v1).unwrap();
+    let snapshot1 = doc.transact().snapshot();
+    let canonical = XmlOptions::new().canonical(true);
+    let xml1 = get_fragment_xml_ref(&doc, "document-store", &canonical);
+
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let v2 = convert_to_value(input).unwrap();
+    set_fragment_blocks(&doc, "document-store", &v2).unwrap();
+    let snapshot2 = doc.transact().snapshot();
+
+    let options = ConvertOptions::new();
+    assert_eq!(
+        convert_at(&doc, &snapshot1, "document-store", &options).unwrap(),
+        v1
+    );
+    assert_eq!(
+        convert_at(&doc, &snapshot2, "document-store", &options).unwrap(),
+        v2
+    );
+    let json = convert_to_json_at(&doc, &snapshot1, "document-store", &options).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), v1);
+    assert_eq!(
+        get_fragment_xml_at_with(&doc, &snapshot1, "document-store", &canonical).unwrap(),
+        xml1
+    );
+    assert!(matches!(
+        get_fragment_xml_at(&doc, &snapshot1, "missing"),
+        Err(Error::FragmentNotFound { .. })
+    ));
+}
+
+#[test]
+fn test_convert_at_with_gc() {
+    let doc = Doc::new();
+    set_fragment_blocks(&doc, "document-store", &json!([])).unwrap();
+    let snapshot = doc.transact().snapshot();
+
+    assert!(matches!(
+        convert_at(&doc, &snapshot, "document-store", &ConvertOptions::new()),
+        Err(Error::SnapshotUnavailable)
+    ));
+}
+
+#[test]
+fn test_convert_changes() {
+    let doc = Doc::with_options(Options {
+        skip_gc: true,
+        ..Default::default()
+    });
+    let paragraph = |id: &str, text: &str| {
+        json!({
+            "id": id,
+            "type": "paragraph",
+            "props": {"textColor": "default", "backgroundColor": "default", "textAlignment": "left"},
+            "content": [{"type": "text", "text": text, "styles": {}}],
+            "children": [],
+        })
+    };
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([
+            paragraph("1", "Hello world"),
+            paragraph("2", "Remove me"),
+            paragraph("3", "Unchanged"),
+        ]),
+    )
+    .unwrap();
+    let before = doc.transact().snapshot();
+
+    {
+        let fragment = doc.get_or_insert_xml_fragment("document-store");
+        let mut txn = doc.transact_mut();
+        let group = element(fragment.get(&txn, 0));
+        let text = match element(element(group.get(&txn, 0)).get(&txn, 0)).get(&txn, 0) {
+            Some(XmlOut::Text(text)) => text,
+            _ => panic!("expected a text node"),
+        };
+        text.insert(&mut txn, 5, " there");
+        text.remove_range(&mut txn, 11, 6);
+        group.remove_range(&mut txn, 1, 1);
+        let container = group.push_back(&mut txn, XmlElementPrelim::empty("blockContainer"));
+        container.insert_attribute(&mut txn, "id", "4");
+        let elem = container.push_back(&mut txn, XmlElementPrelim::empty("heading"));
+        elem.push_back(&mut txn, XmlTextPrelim::new("New"));
+    }
+    let after = doc.transact().snapshot();
+
+    let expected = json!([
+        {
+            "id": "1",
+            "type": "paragraph",
+            "props": {"textColor": "default", "backgroundColor": "default", "textAlignment": "left"},
+            "content": [
+                {"type": "text", "text": "Hello", "styles": {}},
+                {"type": "text", "text": " there", "styles": {"insertion": true}},
+                {"type": "text", "text": " world", "styles": {"deletion": true}},
+            ],
+            "children": [],
+        },
+        {
+            "id": "2",
+            "type": "paragraph",
+            "props": {"textColor": "default", "backgroundColor": "default", "textAlignment": "left"},
+            "content": [{"type": "text", "text": "Remove me", "styles": {"deletion": true}}],
+            "children": [],
+            "change": "removed",
+        },
+        paragraph("3", "Unchanged"),
+        {
+            "id": "4",
+            "type": "heading",
+            "props": {},
+            "content": [{"type": "text", "text": "New", "styles": {"insertion": true}}],
+            "children": [],
+            "change": "added",
+        },
+    ]);
+    let options = ConvertOptions::new();
+    // the document isn't changed, so observers of it aren't called
+    let events = Arc::new(AtomicUsize::new(0));
+    let counted = events.clone();
+    let _live = LiveDocument::new(&doc, "document-store", &options, move |changes| {
+        counted.fetch_add(changes.len(), Ordering::Relaxed);
+    })
+    .unwrap();
+    let update = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+
+    let changes = {
+        // only reads the document, so it doesn't conflict with other readers
+        let _txn = doc.transact();
+        convert_changes(&doc, &before, &after, "document-store", &options).unwrap()
+    };
+    assert_eq!(changes, expected);
+    assert_eq!(events.load(Ordering::Relaxed), 0);
+    assert_eq!(
+        doc.transact()
+            .encode_state_as_update_v1(&StateVector::default()),
+        update
+    );
+    let json = convert_changes_to_json(&doc, &before, &after, "document-store", &options).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), expected);
+
+    // with nothing changed, nothing is marked
+    assert_eq!(
+        convert_changes(&doc, &after, &after, "document-store", &options).unwrap(),
+        convert_at(&doc, &after, "document-store", &options).unwrap()
+    );
+}
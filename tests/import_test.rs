@@ -0,0 +1,70 @@
+use atuin_ydoc_convert::{
+    convert_to_value, convert_to_value_with, get_fragment_xml_ref, set_fragment_blocks,
+    BlockNoteVersion, ConvertOptions, Error, XmlOptions,
+};
+use serde_json::{json, Value};
+use std::fs;
+use yrs::Doc;
+
+fn roundtrip(blocks: &Value, options: &ConvertOptions) -> Value {
+    let doc = Doc::new();
+    set_fragment_blocks(&doc, "document-store", blocks).unwrap();
+    let xml = get_fragment_xml_ref(&doc, "document-store", &XmlOptions::default());
+    convert_to_value_with(xml, options).unwrap()
+}
+
+#[test]
+fn test_set_fragment_blocks() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let expected = convert_to_value(input.clone()).unwrap();
+    assert_eq!(roundtrip(&expected, &ConvertOptions::default()), expected);
+
+    // tableCell objects, and partial blocks with their defaults left out
+    for options in [
+        ConvertOptions::new().version(BlockNoteVersion::V0_23),
+        ConvertOptions::new().partial_blocks(true),
+    ] {
+        let expected = convert_to_value_with(input.clone(), &options).unwrap();
+        assert_eq!(roundtrip(&expected, &options), expected);
+    }
+}
+
+#[test]
+fn test_set_fragment_blocks_invalid() {
+    let doc = Doc::new();
+    let blocks = json!([{"id": "1", "type": "paragraph", "content": "Hello"}]);
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([{"id": "1", "type": "paragraph"}]),
+    )
+    .unwrap();
+    let xml = get_fragment_xml_ref(&doc, "document-store", &XmlOptions::default());
+
+    for invalid in [
+        json!({"type": "paragraph"}),
+        json!([{"id": "1"}]),
+        json!([{"id": "1", "type": "paragraph", "content": [{"type": "text", "text": "Hi", "styles": {"sparkly": true}}]}]),
+        json!([{"id": "1", "type": "table", "content": {"type": "tableContent"}}]),
+        // yrs can't store integers beyond i64
+        json!([{"id": "1", "type": "paragraph", "props": {"x": u64::MAX}, "content": []}]),
+        json!([{"id": "1", "type": "paragraph", "content": [{"type": "mention", "user": [u64::MAX]}]}]),
+    ] {
+        assert!(matches!(
+            set_fragment_blocks(&doc, "document-store", &invalid),
+            Err(Error::InvalidBlocks(_))
+        ));
+    }
+    // a string isn't inline content either
+    assert!(set_fragment_blocks(&doc, "document-store", &blocks).is_err());
+
+    // nothing is written if the blocks are invalid
+    assert_eq!(
+        get_fragment_xml_ref(&doc, "document-store", &XmlOptions::default()),
+        xml
+    );
+    assert_eq!(
+        convert_to_value(xml).unwrap(),
+        json!([{"id": "1", "type": "paragraph", "props": {}, "content": [], "children": []}])
+    );
+}
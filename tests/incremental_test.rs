@@ -0,0 +1,92 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    get_fragment_xml_ref, ConvertOptions, Error, IncrementalConverter, Limits, XmlOptions,
+};
+use common::{current, doc_from_xml, element};
+use std::fs;
+use yrs::{Text, Transact, Xml, XmlFragment, XmlOut};
+
+#[test]
+fn test_incremental_converter() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let doc = doc_from_xml(&input, "document-store");
+    let mut converter =
+        IncrementalConverter::new(&doc, "document-store", &ConvertOptions::new()).unwrap();
+
+    assert_eq!(converter.convert().unwrap(), current(&doc));
+    let blocks = converter.converted();
+    assert!(blocks > 1);
+
+    // nothing changed, so every block comes from the cache
+    assert_eq!(converter.convert().unwrap(), current(&doc));
+    assert_eq!(converter.converted(), 0);
+
+    // editing the text of one block only converts that block
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    let id = {
+        let mut txn = doc.transact_mut();
+        let container = element(element(fragment.get(&txn, 0)).get(&txn, 0));
+        let text = match element(container.get(&txn, 0)).get(&txn, 0) {
+            Some(XmlOut::Text(text)) => text,
+            _ => panic!("expected a text node"),
+        };
+        text.insert(&mut txn, 0, "Edited ");
+        container.get_attribute(&txn, "id").unwrap()
+    };
+    assert_eq!(converter.convert().unwrap(), current(&doc));
+    assert_eq!(converter.converted(), 1);
+
+    // removing a block converts nothing
+    {
+        let mut txn = doc.transact_mut();
+        element(fragment.get(&txn, 0)).remove_range(&mut txn, 1, 1);
+    }
+    assert_eq!(converter.convert().unwrap(), current(&doc));
+    assert_eq!(converter.converted(), 0);
+
+    converter.invalidate(&id);
+    assert_eq!(converter.convert().unwrap(), current(&doc));
+    assert_eq!(converter.converted(), 1);
+
+    converter.invalidate_all();
+    assert_eq!(converter.convert().unwrap(), current(&doc));
+    assert_eq!(converter.converted(), blocks - 1);
+}
+
+#[test]
+fn test_incremental_input_limit() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let doc = doc_from_xml(&input, "document-store");
+    let len = get_fragment_xml_ref(&doc, "document-store", &XmlOptions::default()).len();
+    let options =
+        |max_bytes| ConvertOptions::new().limits(Limits::new().max_input_bytes(max_bytes));
+
+    // the limit applies to the whole fragment's XML, not to each block's
+    let mut converter = IncrementalConverter::new(&doc, "document-store", &options(len)).unwrap();
+    assert_eq!(converter.convert().unwrap(), current(&doc));
+    let mut converter =
+        IncrementalConverter::new(&doc, "document-store", &options(len - 1)).unwrap();
+    assert!(matches!(
+        converter.convert(),
+        Err(Error::InputTooLarge { len: too_long, .. }) if too_long == len
+    ));
+
+    // including the blocks taken from the cache
+    let mut converter = IncrementalConverter::new(&doc, "document-store", &options(len)).unwrap();
+    converter.convert().unwrap();
+    {
+        let fragment = doc.get_or_insert_xml_fragment("document-store");
+        let mut txn = doc.transact_mut();
+        let container = element(element(fragment.get(&txn, 0)).get(&txn, 0));
+        let text = match element(container.get(&txn, 0)).get(&txn, 0) {
+            Some(XmlOut::Text(text)) => text,
+            _ => panic!("expected a text node"),
+        };
+        text.insert(&mut txn, 0, "Edited ");
+    }
+    assert!(matches!(
+        converter.convert(),
+        Err(Error::InputTooLarge { .. })
+    ));
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    find_fragment, get_fragment_xml_ref, list_shared_types, try_get_fragment_xml, Error,
+    SharedTypeInfo, SharedTypeKind, XmlOptions,
+};
+use common::doc_from_xml;
+use std::fs;
+use yrs::updates::decoder::Decode;
+use yrs::{Array, Doc, Map, ReadTxn, StateVector, Text, Transact, Update};
+
+fn info(name: &str, kind: SharedTypeKind, size: usize) -> SharedTypeInfo {
+    SharedTypeInfo {
+        name: name.to_string(),
+        kind,
+        size,
+    }
+}
+
+#[test]
+fn test_list_shared_types() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let doc = doc_from_xml(&input, "document-store");
+    {
+        let meta = doc.get_or_insert_map("meta");
+        let title = doc.get_or_insert_text("title");
+        let tags = doc.get_or_insert_array("tags");
+        let mut txn = doc.transact_mut();
+        meta.insert(&mut txn, "author", "ellie");
+        meta.insert(&mut txn, "version", 2);
+        title.insert(&mut txn, 0, "Runbook 🔢");
+        tags.insert_range(&mut txn, 0, ["ops", "deploy", "prod"]);
+    }
+
+    let expected = vec![
+        info("document-store", SharedTypeKind::XmlFragment, 1),
+        info("meta", SharedTypeKind::Map, 2),
+        info("tags", SharedTypeKind::Array, 3),
+        info("title", SharedTypeKind::Text, 9),
+    ];
+    assert_eq!(list_shared_types(&doc), expected);
+
+    // roots received in an update are untyped until they're accessed, so their kind is inferred
+    let update = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let remote = Doc::new();
+    remote
+        .transact_mut()
+        .apply_update(Update::decode_v1(&update).unwrap())
+        .unwrap();
+    assert_eq!(list_shared_types(&remote), expected);
+
+    let canonical = XmlOptions::new().canonical(true);
+    assert_eq!(
+        try_get_fragment_xml(&remote, "document-store", &canonical).unwrap(),
+        get_fragment_xml_ref(&doc, "document-store", &canonical)
+    );
+}
+
+#[test]
+fn test_find_fragment() {
+    let doc = doc_from_xml("<blockGroup></blockGroup>", "document-store");
+    doc.get_or_insert_map("meta");
+
+    assert!(find_fragment(&doc, "document-store").is_ok());
+    assert!(matches!(
+        find_fragment(&doc, "blocknote"),
+        Err(Error::FragmentNotFound { name }) if name == "blocknote"
+    ));
+    assert!(matches!(
+        try_get_fragment_xml(&doc, "meta", &XmlOptions::default()),
+        Err(Error::NotAFragment {
+            kind: SharedTypeKind::Map,
+            ..
+        })
+    ));
+
+    // looking up a missing fragment doesn't create it
+    assert_eq!(
+        list_shared_types(&doc)
+            .into_iter()
+            .map(|info| info.name)
+            .collect::<Vec<_>>(),
+        ["document-store", "meta"]
+    );
+}
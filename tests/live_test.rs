@@ -0,0 +1,164 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    edit_blocks, set_fragment_blocks, BlockEdits, BlockEvent, ConvertOptions, Error, LiveDocument,
+};
+use common::{current, element};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use yrs::{Doc, Text, Transact, Xml, XmlElementPrelim, XmlFragment, XmlOut, XmlTextPrelim};
+
+#[test]
+fn test_live_document() {
+    let doc = Doc::new();
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([{
+            "id": "1",
+            "type": "paragraph",
+            "content": [{"type": "text", "text": "Hello", "styles": {}}],
+            "children": [{
+                "id": "2",
+                "type": "paragraph",
+                "content": [{"type": "text", "text": "Nested", "styles": {}}],
+                "children": [],
+            }],
+        }]),
+    )
+    .unwrap();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let received = events.clone();
+    let live = LiveDocument::new(
+        &doc,
+        "document-store",
+        &ConvertOptions::new(),
+        move |changes: &[BlockEvent]| received.lock().unwrap().extend_from_slice(changes),
+    )
+    .unwrap();
+    assert_eq!(live.blocks(), current(&doc));
+
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    // typing in a nested block only updates that block
+    {
+        let mut txn = doc.transact_mut();
+        let group = element(fragment.get(&txn, 0));
+        let outer = element(group.get(&txn, 0));
+        let nested = element(element(outer.get(&txn, 1)).get(&txn, 0));
+        let text = match element(nested.get(&txn, 0)).get(&txn, 0) {
+            Some(XmlOut::Text(text)) => text,
+            _ => panic!("expected a text node"),
+        };
+        text.insert(&mut txn, 6, "!");
+    }
+    let blocks = current(&doc);
+    assert_eq!(live.blocks(), blocks);
+    assert_eq!(
+        events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![BlockEvent::Updated {
+            id: "2".to_string(),
+            block: blocks[0]["children"][0].clone(),
+        }]
+    );
+
+    // adding and removing top-level blocks
+    {
+        let mut txn = doc.transact_mut();
+        let group = element(fragment.get(&txn, 0));
+        let container = group.insert(&mut txn, 0, XmlElementPrelim::empty("blockContainer"));
+        container.insert_attribute(&mut txn, "id", "3");
+        let elem = container.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+        elem.push_back(&mut txn, XmlTextPrelim::new("First"));
+    }
+    let blocks = current(&doc);
+    assert_eq!(live.blocks(), blocks);
+    assert_eq!(
+        events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![BlockEvent::Inserted {
+            id: "3".to_string(),
+            index: 0,
+            block: blocks[0].clone(),
+        }]
+    );
+
+    {
+        let mut txn = doc.transact_mut();
+        let group = element(fragment.get(&txn, 0));
+        group.remove_range(&mut txn, 1, 1);
+    }
+    assert_eq!(live.blocks(), current(&doc));
+    assert_eq!(
+        events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![BlockEvent::Removed {
+            id: "1".to_string()
+        }]
+    );
+}
+
+#[test]
+fn test_live_document_top_level_changes() {
+    let paragraph = |id: &str| {
+        json!({
+            "id": id,
+            "type": "paragraph",
+            "content": [{"type": "text", "text": id, "styles": {}}],
+            "children": [],
+        })
+    };
+    let doc = Doc::new();
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([paragraph("a"), paragraph("b"), paragraph("c")]),
+    )
+    .unwrap();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let received = events.clone();
+    let live = LiveDocument::new(
+        &doc,
+        "document-store",
+        &ConvertOptions::new(),
+        move |changes: &[BlockEvent]| received.lock().unwrap().extend_from_slice(changes),
+    )
+    .unwrap();
+
+    let edits = BlockEdits::new()
+        .move_before("c", "a")
+        .insert_after("a", json!([paragraph("d")]))
+        .delete("b")
+        .update_props("a", json!({"textColor": "red"}));
+    edit_blocks(&doc, "document-store", &edits).unwrap();
+
+    let blocks = current(&doc);
+    assert_eq!(live.blocks(), blocks);
+    assert_eq!(
+        events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![
+            BlockEvent::Removed {
+                id: "b".to_string()
+            },
+            BlockEvent::Moved {
+                id: "c".to_string(),
+                index: 0
+            },
+            BlockEvent::Inserted {
+                id: "d".to_string(),
+                index: 2,
+                block: blocks[2].clone(),
+            },
+            BlockEvent::Updated {
+                id: "a".to_string(),
+                block: blocks[1].clone(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_live_document_missing_fragment() {
+    let doc = Doc::new();
+    let result = LiveDocument::new(&doc, "document-store", &ConvertOptions::new(), |_| {});
+    assert!(matches!(result, Err(Error::FragmentNotFound { .. })));
+}
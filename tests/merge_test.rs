@@ -0,0 +1,215 @@
+use atuin_ydoc_convert::{
+    block_offset_to_sticky, convert_to_value, get_fragment_xml_ref, merge_blocks,
+    merge_into_fragment, set_fragment_blocks, sticky_to_block_offset, BlockOffset, Conflict,
+    ConflictPolicy, Error, MergeOptions, OffsetUnit, Side, XmlOptions,
+};
+use serde_json::{json, Value};
+use yrs::{Assoc, Doc};
+
+fn paragraph(id: &str, text: &str) -> Value {
+    styled_paragraph(id, text, "default", "left")
+}
+
+fn styled_paragraph(id: &str, text: &str, color: &str, alignment: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "paragraph",
+        "props": {"textColor": color, "backgroundColor": "default", "textAlignment": alignment},
+        "content": [{"type": "text", "text": text, "styles": {}}],
+        "children": [],
+    })
+}
+
+#[test]
+fn test_merge_blocks() {
+    let base = json!([
+        paragraph("a", "Intro"),
+        paragraph("b", "Step 1"),
+        paragraph("c", "Step 2"),
+        paragraph("d", "Step 3"),
+    ]);
+    let ours = json!([
+        paragraph("a", "Intro text"),
+        styled_paragraph("b", "Step 1", "default", "center"),
+        paragraph("e", "New"),
+        paragraph("c", "Step 2"),
+    ]);
+    let theirs = json!([
+        styled_paragraph("a", "Intro", "red", "left"),
+        paragraph("c", "Step 2"),
+        paragraph("b", "Step one"),
+        paragraph("d", "Step 3"),
+        paragraph("f", "Tail"),
+    ]);
+
+    let merged = merge_blocks(&base, &ours, &theirs, &MergeOptions::new()).unwrap();
+    assert_eq!(
+        merged.blocks,
+        json!([
+            styled_paragraph("a", "Intro text", "red", "left"),
+            paragraph("c", "Step 2"),
+            styled_paragraph("b", "Step one", "default", "center"),
+            paragraph("e", "New"),
+            paragraph("f", "Tail"),
+        ])
+    );
+    assert_eq!(merged.conflicts, vec![]);
+
+    // merging with no changes on one side gives the other side
+    let merged = merge_blocks(&base, &base, &theirs, &MergeOptions::new()).unwrap();
+    assert_eq!(merged.blocks, theirs);
+}
+
+#[test]
+fn test_merge_blocks_conflicts() {
+    let base = json!([paragraph("a", "x"), paragraph("b", "Old")]);
+    let ours = json!([paragraph("a", "y")]);
+    let theirs = json!([paragraph("a", "z"), paragraph("b", "Changed")]);
+    let conflicts = vec![
+        Conflict::Content {
+            id: "a".to_string(),
+        },
+        Conflict::Deleted {
+            id: "b".to_string(),
+            deleted_by: Side::Ours,
+        },
+    ];
+
+    let merged = merge_blocks(&base, &ours, &theirs, &MergeOptions::new()).unwrap();
+    assert_eq!(merged.blocks, ours);
+    assert_eq!(merged.conflicts, conflicts);
+
+    let options = MergeOptions::new().policy(ConflictPolicy::Theirs);
+    let merged = merge_blocks(&base, &ours, &theirs, &options).unwrap();
+    assert_eq!(merged.blocks, theirs);
+    assert_eq!(merged.conflicts, conflicts);
+
+    let options = MergeOptions::new().policy(ConflictPolicy::Fail);
+    assert!(matches!(
+        merge_blocks(&base, &ours, &theirs, &options),
+        Err(Error::MergeConflict(found)) if found == conflicts
+    ));
+
+    // we nest b in a while they nest a in b, so one of them has to stay at the top level
+    let base = json!([paragraph("a", "x"), paragraph("b", "y")]);
+    let mut nested = paragraph("a", "x");
+    nested["children"] = json!([paragraph("b", "y")]);
+    let ours = json!([nested]);
+    let mut nested = paragraph("b", "y");
+    nested["children"] = json!([paragraph("a", "x")]);
+    let theirs = json!([nested]);
+    let merged = merge_blocks(&base, &ours, &theirs, &MergeOptions::new()).unwrap();
+    assert_eq!(merged.blocks.as_array().unwrap().len(), 1);
+    assert!(merged
+        .conflicts
+        .iter()
+        .all(|conflict| matches!(conflict, Conflict::Moved { .. })));
+    assert!(!merged.conflicts.is_empty());
+
+    assert!(matches!(
+        merge_blocks(&base, &json!([{"type": "paragraph"}]), &theirs, &options),
+        Err(Error::InvalidBlocks(_))
+    ));
+}
+
+#[test]
+fn test_merge_into_fragment() {
+    let base = json!([paragraph("a", "Intro"), paragraph("b", "Step 1")]);
+    let ours = json!([paragraph("a", "Intro text"), paragraph("b", "Step 1")]);
+    let theirs = json!([
+        paragraph("a", "Intro"),
+        paragraph("c", "Step 0"),
+        paragraph("b", "Step 1")
+    ]);
+
+    let doc = Doc::new();
+    set_fragment_blocks(&doc, "document-store", &ours).unwrap();
+    let conflicts =
+        merge_into_fragment(&doc, "document-store", &base, &theirs, &MergeOptions::new()).unwrap();
+    assert_eq!(conflicts, vec![]);
+
+    let xml = get_fragment_xml_ref(&doc, "document-store", &XmlOptions::new());
+    assert_eq!(
+        convert_to_value(xml).unwrap(),
+        json!([
+            paragraph("a", "Intro text"),
+            paragraph("c", "Step 0"),
+            paragraph("b", "Step 1"),
+        ])
+    );
+}
+
+#[test]
+fn test_merge_into_fragment_restructured() {
+    let with_children = |mut block: Value, children: Vec<Value>| {
+        block["children"] = Value::Array(children);
+        block
+    };
+    let base = json!([
+        paragraph("a", "Intro"),
+        with_children(
+            paragraph("b", "Steps"),
+            vec![paragraph("c", "Step 1"), paragraph("d", "Step 2")]
+        ),
+        paragraph("e", "Outro"),
+    ]);
+    let ours = json!([
+        paragraph("a", "Intro"),
+        with_children(
+            paragraph("b", "Steps"),
+            vec![paragraph("c", "Step 1"), paragraph("d", "Step two")]
+        ),
+        paragraph("e", "Outro text"),
+    ]);
+    // b is deleted with c moved out of it, d is nested in e, e becomes a heading and a block
+    // is added to a
+    let theirs = json!([
+        with_children(paragraph("a", "Intro"), vec![paragraph("f", "Note")]),
+        paragraph("c", "Step 1"),
+        with_children(
+            json!({
+                "id": "e",
+                "type": "heading",
+                "props": {"textColor": "default", "backgroundColor": "default", "textAlignment": "left", "level": 2, "isToggleable": false},
+                "content": [{"type": "text", "text": "Outro", "styles": {}}],
+                "children": [],
+            }),
+            vec![paragraph("d", "Step 2")]
+        ),
+    ]);
+
+    let doc = Doc::new();
+    set_fragment_blocks(&doc, "document-store", &ours).unwrap();
+    // a position in a block that isn't changed
+    let position = BlockOffset {
+        block_id: "a".to_string(),
+        offset: 2,
+    };
+    let sticky = block_offset_to_sticky(
+        &doc,
+        "document-store",
+        &position,
+        Assoc::After,
+        OffsetUnit::Utf16,
+    )
+    .unwrap();
+
+    let options = MergeOptions::new();
+    let conflicts = merge_into_fragment(&doc, "document-store", &base, &theirs, &options).unwrap();
+    assert_eq!(conflicts, vec![]);
+
+    let xml = get_fragment_xml_ref(&doc, "document-store", &XmlOptions::new());
+    let merged = merge_blocks(&base, &ours, &theirs, &options).unwrap();
+    assert_eq!(convert_to_value(xml).unwrap(), merged.blocks);
+    assert_eq!(merged.blocks[2]["content"][0]["text"], "Outro text");
+    assert_eq!(
+        merged.blocks[2]["children"][0]["content"][0]["text"],
+        "Step two"
+    );
+
+    // the block was left as it was rather than written again
+    assert_eq!(
+        sticky_to_block_offset(&doc, "document-store", &sticky, OffsetUnit::Utf16).unwrap(),
+        position
+    );
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use atuin_ydoc_convert::{
+    block_offset_to_sticky, set_fragment_blocks, sticky_to_block_offset, BlockOffset, Error,
+    OffsetUnit,
+};
+use common::element;
+use serde_json::json;
+use yrs::{
+    Assoc, Doc, IndexedSequence, OffsetKind, Options, Text, Transact, XmlFragment, XmlOut,
+    XmlTextRef,
+};
+
+fn doc(offset_kind: OffsetKind) -> Doc {
+    let doc = Doc::with_options(Options {
+        offset_kind,
+        ..Options::default()
+    });
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([{
+            "id": "1",
+            "type": "paragraph",
+            "content": [
+                {"type": "text", "text": "a😀 ", "styles": {}},
+                {"type": "text", "text": "bé", "styles": {"bold": true}},
+            ],
+            "children": [{
+                "id": "2",
+                "type": "paragraph",
+                "content": [{"type": "text", "text": "nested", "styles": {}}],
+                "children": [],
+            }],
+        }]),
+    )
+    .unwrap();
+
+    doc
+}
+
+#[test]
+fn test_sticky_to_block_offset() {
+    for (offset_kind, index) in [(OffsetKind::Bytes, 7), (OffsetKind::Utf16, 5)] {
+        let doc = doc(offset_kind);
+        // just before the "é"
+        let text = first_text(&doc);
+        let sticky = text
+            .sticky_index(&mut doc.transact_mut(), index, Assoc::After)
+            .unwrap();
+
+        for (unit, offset) in [
+            (OffsetUnit::Utf16, 5),
+            (OffsetUnit::Utf8, 7),
+            (OffsetUnit::Chars, 4),
+        ] {
+            let position = sticky_to_block_offset(&doc, "document-store", &sticky, unit).unwrap();
+            assert_eq!(
+                position,
+                BlockOffset {
+                    block_id: "1".to_string(),
+                    offset
+                }
+            );
+            let back =
+                block_offset_to_sticky(&doc, "document-store", &position, Assoc::After, unit)
+                    .unwrap();
+            assert_eq!(
+                sticky_to_block_offset(&doc, "document-store", &back, unit).unwrap(),
+                position
+            );
+        }
+
+        // the position sticks to its character as text is typed before it
+        text.insert(&mut doc.transact_mut(), 0, "xy");
+        let position =
+            sticky_to_block_offset(&doc, "document-store", &sticky, OffsetUnit::Utf16).unwrap();
+        assert_eq!(position.offset, 7);
+    }
+}
+
+#[test]
+fn test_block_offset_to_sticky() {
+    let doc = doc(OffsetKind::Bytes);
+    let position = BlockOffset {
+        block_id: "2".to_string(),
+        offset: 6,
+    };
+    let sticky = block_offset_to_sticky(
+        &doc,
+        "document-store",
+        &position,
+        Assoc::After,
+        OffsetUnit::Utf16,
+    )
+    .unwrap();
+    assert_eq!(
+        sticky_to_block_offset(&doc, "document-store", &sticky, OffsetUnit::Utf16).unwrap(),
+        position
+    );
+
+    // inside the surrogate pair of the emoji
+    let result = block_offset_to_sticky(
+        &doc,
+        "document-store",
+        &BlockOffset {
+            block_id: "1".to_string(),
+            offset: 2,
+        },
+        Assoc::After,
+        OffsetUnit::Utf16,
+    );
+    assert!(matches!(result, Err(Error::InvalidPosition(_))));
+
+    let result = block_offset_to_sticky(
+        &doc,
+        "document-store",
+        &BlockOffset {
+            block_id: "3".to_string(),
+            offset: 0,
+        },
+        Assoc::After,
+        OffsetUnit::Utf16,
+    );
+    assert!(matches!(result, Err(Error::BlockNotFound { id }) if id == "3"));
+}
+
+#[test]
+fn test_sticky_to_block_offset_between_nested_blocks() {
+    let paragraph = |id: &str| {
+        json!({
+            "id": id,
+            "type": "paragraph",
+            "content": [{"type": "text", "text": id, "styles": {}}],
+            "children": [],
+        })
+    };
+    let doc = Doc::new();
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([{
+            "id": "p",
+            "type": "paragraph",
+            "content": [{"type": "text", "text": "hello", "styles": {}}],
+            "children": [paragraph("c1"), paragraph("c2")],
+        }]),
+    )
+    .unwrap();
+
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    let group = {
+        let txn = doc.transact();
+        let container = element(element(fragment.get(&txn, 0)).get(&txn, 0));
+        element(container.get(&txn, 1))
+    };
+    for (index, id) in [(0, "c1"), (1, "c2"), (2, "c2")] {
+        let assoc = if index == 2 {
+            Assoc::Before
+        } else {
+            Assoc::After
+        };
+        let sticky = group
+            .sticky_index(&mut doc.transact_mut(), index, assoc)
+            .unwrap();
+        assert_eq!(
+            sticky_to_block_offset(&doc, "document-store", &sticky, OffsetUnit::Utf16).unwrap(),
+            BlockOffset {
+                block_id: id.to_string(),
+                offset: 0
+            }
+        );
+    }
+}
+
+#[test]
+fn test_round_trip_every_offset() {
+    let text = "h😀é🙂zend";
+    for offset_kind in [OffsetKind::Bytes, OffsetKind::Utf16] {
+        let doc = Doc::with_options(Options {
+            offset_kind,
+            ..Options::default()
+        });
+        set_fragment_blocks(
+            &doc,
+            "document-store",
+            &json!([{
+                "id": "1",
+                "type": "paragraph",
+                "content": [
+                    {"type": "text", "text": "h😀é", "styles": {"bold": true}},
+                    {
+                        "type": "link",
+                        "href": "https://atuin.sh",
+                        "content": [{"type": "text", "text": "🙂z", "styles": {}}],
+                    },
+                    {"type": "text", "text": "end", "styles": {}},
+                ],
+                "children": [],
+            }]),
+        )
+        .unwrap();
+
+        for unit in [OffsetUnit::Utf16, OffsetUnit::Utf8, OffsetUnit::Chars] {
+            let len = |s: &str| match unit {
+                OffsetUnit::Utf16 => s.encode_utf16().count(),
+                OffsetUnit::Utf8 => s.len(),
+                _ => s.chars().count(),
+            };
+            let boundaries = text
+                .char_indices()
+                .map(|(i, _)| len(&text[..i]))
+                .chain([len(text)])
+                .collect::<Vec<_>>();
+
+            for offset in 0..=len(text) {
+                let position = BlockOffset {
+                    block_id: "1".to_string(),
+                    offset,
+                };
+                let sticky =
+                    block_offset_to_sticky(&doc, "document-store", &position, Assoc::After, unit);
+                if !boundaries.contains(&offset) {
+                    assert!(matches!(sticky, Err(Error::InvalidPosition(_))));
+                    continue;
+                }
+                assert_eq!(
+                    sticky_to_block_offset(&doc, "document-store", &sticky.unwrap(), unit).unwrap(),
+                    position,
+                    "{:?} offset {} in a {:?} document",
+                    unit,
+                    offset,
+                    offset_kind
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sticky_to_block_offset_in_fragment() {
+    let doc = Doc::new();
+    let block = |id: &str| {
+        json!({
+            "id": id,
+            "type": "paragraph",
+            "content": [{"type": "text", "text": id, "styles": {}}],
+            "children": [],
+        })
+    };
+    set_fragment_blocks(
+        &doc,
+        "document-store",
+        &json!([block("a"), block("b"), block("c")]),
+    )
+    .unwrap();
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+
+    // before and after the top-level blockGroup
+    for (index, assoc, block_id) in [(0, Assoc::After, "a"), (1, Assoc::Before, "c")] {
+        let sticky = fragment
+            .sticky_index(&mut doc.transact_mut(), index, assoc)
+            .unwrap();
+        assert_eq!(
+            sticky_to_block_offset(&doc, "document-store", &sticky, OffsetUnit::Utf16).unwrap(),
+            BlockOffset {
+                block_id: block_id.to_string(),
+                offset: 0
+            }
+        );
+    }
+}
+
+// The text of the first block
+fn first_text(doc: &Doc) -> XmlTextRef {
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    let txn = doc.transact();
+    let container = element(element(fragment.get(&txn, 0)).get(&txn, 0));
+    match element(container.get(&txn, 0)).get(&txn, 0) {
+        Some(XmlOut::Text(text)) => text,
+        _ => panic!("expected a text node"),
+    }
+}
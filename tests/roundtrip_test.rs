@@ -0,0 +1,186 @@
+use std::collections::{BTreeSet, HashMap};
+
+use atuin_ydoc_convert::{convert_to_value, get_fragment_xml};
+use proptest::prelude::*;
+use serde_json::Value;
+use yrs::branch::{Branch, BranchPtr};
+use yrs::{
+    Any, Doc, Map, MapRef, Text, Transact, TransactionMut, Xml, XmlElementPrelim, XmlElementRef,
+    XmlFragment, XmlTextPrelim,
+};
+
+const MARKS: &[&str] = &["bold", "italic", "code"];
+
+// Block types and attribute names the converter gives special treatment to, which would
+// make the expected output differ from the input
+const RESERVED_TYPES: &[&str] = &[
+    "table",
+    "heading",
+    "image",
+    "video",
+    "audio",
+    "checkListItem",
+    "bulletlistitem",
+    "checklistitem",
+    "numberedlistitem",
+    "numberedListItem",
+];
+
+#[derive(Debug, Clone)]
+struct TestBlock {
+    id: String,
+    type_name: String,
+    props: HashMap<String, Any>,
+    text: Vec<(String, BTreeSet<&'static str>)>,
+    children: Vec<TestBlock>,
+}
+
+fn any_value() -> impl Strategy<Value = Any> {
+    prop_oneof![
+        any::<String>().prop_map(|s| Any::String(s.into())),
+        any::<bool>().prop_map(Any::Bool),
+        any::<i32>().prop_map(|n| Any::Number(n as f64)),
+        any::<f64>()
+            .prop_filter("JSON has no NaN or infinity", |n| n.is_finite())
+            .prop_map(Any::Number),
+        Just(Any::Null),
+        prop::collection::vec(any::<String>(), 0..3)
+            .prop_map(|v| Any::Array(v.into_iter().map(|s| Any::String(s.into())).collect())),
+    ]
+}
+
+fn block_leaf() -> impl Strategy<Value = TestBlock> {
+    (
+        any::<String>(),
+        any::<String>().prop_filter("reserved type", |t| !RESERVED_TYPES.contains(&t.as_str())),
+        prop::collection::hash_map(
+            any::<String>().prop_filter("reserved attribute", |name| name != "id"),
+            any_value(),
+            0..4,
+        ),
+        prop::collection::vec(
+            (
+                ".+",
+                prop::collection::btree_set(prop::sample::select(MARKS), 0..3),
+            ),
+            0..4,
+        ),
+    )
+        .prop_map(|(id, type_name, props, text)| TestBlock {
+            id,
+            type_name,
+            props,
+            text,
+            children: Vec::new(),
+        })
+}
+
+fn block() -> impl Strategy<Value = TestBlock> {
+    block_leaf().prop_recursive(3, 16, 3, |inner| {
+        (block_leaf(), prop::collection::vec(inner, 0..3)).prop_map(|(mut block, children)| {
+            block.children = children;
+            block
+        })
+    })
+}
+
+fn insert_block(txn: &mut TransactionMut, group: &XmlElementRef, block: &TestBlock) {
+    let container = group.push_back(txn, XmlElementPrelim::empty("blockContainer"));
+    container.insert_attribute(txn, "id", block.id.clone());
+
+    let elem = container.push_back(txn, XmlElementPrelim::empty(block.type_name.clone()));
+    let attributes = MapRef::from(BranchPtr::from(AsRef::<Branch>::as_ref(&elem)));
+    for (name, value) in block.props.iter() {
+        attributes.insert(txn, name.clone(), value.clone());
+    }
+
+    if !block.text.is_empty() {
+        let text = elem.push_back(txn, XmlTextPrelim::new(""));
+        for (chunk, marks) in block.text.iter() {
+            let attrs = marks
+                .iter()
+                .map(|mark| ((*mark).into(), Any::Bool(true)))
+                .collect::<HashMap<_, _>>();
+            let index = text.len(txn);
+            text.insert_with_attributes(txn, index, chunk, attrs);
+        }
+    }
+
+    if !block.children.is_empty() {
+        let group = container.push_back(txn, XmlElementPrelim::empty("blockGroup"));
+        for child in block.children.iter() {
+            insert_block(txn, &group, child);
+        }
+    }
+}
+
+// Flattens inline content to characters and their styles, since yrs merges and splits
+// runs of text with the same formatting
+fn flatten_content(content: &Value, out: &mut Vec<(char, BTreeSet<String>)>) {
+    for item in content.as_array().unwrap() {
+        let styles = item["styles"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        for c in item["text"].as_str().unwrap().chars() {
+            out.push((c, styles.clone()));
+        }
+    }
+}
+
+fn assert_block_eq(block: &TestBlock, actual: &Value) {
+    assert_eq!(actual["id"], Value::from(block.id.clone()));
+    assert_eq!(actual["type"], Value::from(block.type_name.clone()));
+
+    let expected_props = block
+        .props
+        .iter()
+        .map(|(name, value)| (name.clone(), serde_json::to_value(value).unwrap()))
+        .collect::<serde_json::Map<_, _>>();
+    assert_eq!(actual["props"], Value::Object(expected_props));
+
+    let expected_text = block
+        .text
+        .iter()
+        .flat_map(|(chunk, marks)| {
+            let marks = marks.iter().map(|m| m.to_string()).collect::<BTreeSet<_>>();
+            chunk.chars().map(move |c| (c, marks.clone()))
+        })
+        .collect::<Vec<_>>();
+    let mut actual_text = Vec::new();
+    flatten_content(&actual["content"], &mut actual_text);
+    assert_eq!(actual_text, expected_text);
+
+    let children = actual["children"].as_array().unwrap();
+    assert_eq!(children.len(), block.children.len());
+    for (child, actual) in block.children.iter().zip(children) {
+        assert_block_eq(child, actual);
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_every_document_round_trips(blocks in prop::collection::vec(block(), 0..4)) {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment("document-store");
+        {
+            let mut txn = doc.transact_mut();
+            let group = fragment.push_back(&mut txn, XmlElementPrelim::empty("blockGroup"));
+            for block in blocks.iter() {
+                insert_block(&mut txn, &group, block);
+            }
+        }
+
+        let xml = get_fragment_xml(doc, "document-store".to_string());
+        let result = convert_to_value(xml.clone())
+            .unwrap_or_else(|e| panic!("failed to convert {:?}: {}", xml, e));
+
+        let result = result.as_array().unwrap();
+        prop_assert_eq!(result.len(), blocks.len());
+        for (block, actual) in blocks.iter().zip(result) {
+            assert_block_eq(block, actual);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+
+use atuin_ydoc_convert::{
+    convert_to_value, get_fragment_xml, get_fragment_xml_ref, get_fragment_xml_with,
+    write_fragment_xml, write_fragment_xml_io, XmlOptions,
+};
+use serde_json::{json, Value};
+use yrs::branch::{Branch, BranchPtr};
+use yrs::{
+    Any, Doc, Map, MapRef, Text, Transact, Xml, XmlElementPrelim, XmlElementRef, XmlFragment,
+    XmlTextPrelim,
+};
+
+// y-prosemirror stores some attributes as non-strings, which `Xml::insert_attribute` can't do
+fn insert_typed_attribute(
+    txn: &mut yrs::TransactionMut,
+    elem: &XmlElementRef,
+    name: &str,
+    value: Any,
+) {
+    let attributes = MapRef::from(BranchPtr::from(AsRef::<Branch>::as_ref(elem)));
+    attributes.insert(txn, name, value);
+}
+
+#[test]
+fn test_typed_attributes_round_trip() {
+    let doc = Doc::new();
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    {
+        let mut txn = doc.transact_mut();
+        let group = fragment.push_back(&mut txn, XmlElementPrelim::empty("blockgroup"));
+        let container = group.push_back(&mut txn, XmlElementPrelim::empty("blockcontainer"));
+        container.insert_attribute(&mut txn, "id", "heading-1");
+        let heading = container.push_back(&mut txn, XmlElementPrelim::empty("heading"));
+        insert_typed_attribute(&mut txn, &heading, "level", Any::Number(2.0));
+        insert_typed_attribute(&mut txn, &heading, "isToggleable", Any::Bool(false));
+        insert_typed_attribute(
+            &mut txn,
+            &heading,
+            "meta",
+            Any::Map(HashMap::from([("a".to_string(), Any::BigInt(1))]).into()),
+        );
+        insert_typed_attribute(&mut txn, &heading, "nothing", Any::Undefined);
+
+        let text = heading.push_back(&mut txn, XmlTextPrelim::new(""));
+        let mut link = HashMap::new();
+        link.insert("href".to_string(), Any::String("https://atuin.sh".into()));
+        link.insert("weight".to_string(), Any::Number(1.5));
+        let attrs = HashMap::from([("link".into(), Any::Map(link.into()))]);
+        text.insert_with_attributes(&mut txn, 0, "Atuin", attrs);
+    }
+
+    let xml = get_fragment_xml(doc, "document-store".to_string());
+    let result = convert_to_value(xml).unwrap();
+    let block = &result[0];
+
+    assert_eq!(block["props"]["level"], json!(2));
+    assert_eq!(block["props"]["isToggleable"], json!(false));
+    assert_eq!(block["props"]["meta"], json!({ "a": 1 }));
+    assert_eq!(block["props"].get("nothing"), None);
+
+    let link = &block["content"][0];
+    assert_eq!(link["type"], json!("link"));
+    assert_eq!(link["href"], json!("https://atuin.sh"));
+    assert_eq!(link["weight"], json!(1.5));
+    assert_eq!(link["content"][0]["text"], Value::from("Atuin"));
+}
+
+#[test]
+fn test_string_attributes_are_not_reparsed() {
+    let doc = Doc::new();
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    {
+        let mut txn = doc.transact_mut();
+        let group = fragment.push_back(&mut txn, XmlElementPrelim::empty("blockgroup"));
+        let container = group.push_back(&mut txn, XmlElementPrelim::empty("blockcontainer"));
+        container.insert_attribute(&mut txn, "id", "paragraph-1");
+        let paragraph = container.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+        paragraph.insert_attribute(&mut txn, "textAlignment", "left");
+        paragraph.insert_attribute(&mut txn, "count", "10");
+    }
+
+    let xml = get_fragment_xml(doc, "document-store".to_string());
+    assert!(!xml.contains("xmlns:"));
+
+    let result = convert_to_value(xml).unwrap();
+    assert_eq!(result[0]["props"]["count"], json!("10"));
+    assert_eq!(result[0]["props"]["textAlignment"], json!("left"));
+}
+
+fn build_formatted_doc() -> Doc {
+    let doc = Doc::new();
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    {
+        let mut txn = doc.transact_mut();
+        let group = fragment.push_back(&mut txn, XmlElementPrelim::empty("blockgroup"));
+        let container = group.push_back(&mut txn, XmlElementPrelim::empty("blockcontainer"));
+        for (name, value) in [
+            ("textColor", "default"),
+            ("id", "paragraph-1"),
+            ("backgroundColor", "default"),
+        ] {
+            container.insert_attribute(&mut txn, name, value);
+        }
+        let paragraph = container.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+        insert_typed_attribute(
+            &mut txn,
+            &paragraph,
+            "meta",
+            Any::Map(
+                HashMap::from([
+                    ("zeta".to_string(), Any::BigInt(1)),
+                    ("alpha".to_string(), Any::Bool(true)),
+                    ("mu".to_string(), Any::Null),
+                ])
+                .into(),
+            ),
+        );
+
+        let text = paragraph.push_back(&mut txn, XmlTextPrelim::new(""));
+        let attrs = HashMap::from([
+            ("underline".into(), Any::Bool(true)),
+            ("bold".into(), Any::Bool(true)),
+            ("strike".into(), Any::Bool(true)),
+            ("italic".into(), Any::Bool(true)),
+        ]);
+        text.insert_with_attributes(&mut txn, 0, "styled", attrs);
+    }
+
+    doc
+}
+
+#[test]
+fn test_canonical_xml_is_deterministic() {
+    let options = XmlOptions::new().canonical(true);
+
+    let first = get_fragment_xml_with(
+        build_formatted_doc(),
+        "document-store".to_string(),
+        &options,
+    );
+    for _ in 0..10 {
+        let xml = get_fragment_xml_with(
+            build_formatted_doc(),
+            "document-store".to_string(),
+            &options,
+        );
+        assert_eq!(first, xml);
+    }
+
+    assert!(first.contains(
+        r#"<blockcontainer backgroundColor="default" id="paragraph-1" textColor="default">"#
+    ));
+    assert!(first.contains(
+        r#"ydoc:meta="{&quot;alpha&quot;:true,&quot;mu&quot;:null,&quot;zeta&quot;:1}""#
+    ));
+    assert!(first
+        .contains("<bold><italic><strike><underline>styled</underline></strike></italic></bold>"));
+}
+
+#[test]
+fn test_indented_xml() {
+    let doc = common::doc_from_xml(
+        r#"<blockgroup><blockcontainer id="1"><paragraph>Some <bold>text</bold></paragraph><blockgroup><blockcontainer id="2"><paragraph></paragraph></blockcontainer></blockgroup></blockcontainer></blockgroup>"#,
+        "document-store",
+    );
+
+    let mut xml = String::new();
+    write_fragment_xml(
+        &doc,
+        "document-store",
+        &mut xml,
+        &XmlOptions::new().canonical(true).indent(2),
+    )
+    .unwrap();
+
+    let expected = r#"<blockgroup>
+  <blockcontainer id="1">
+    <paragraph>Some <bold>text</bold></paragraph>
+    <blockgroup>
+      <blockcontainer id="2">
+        <paragraph></paragraph>
+      </blockcontainer>
+    </blockgroup>
+  </blockcontainer>
+</blockgroup>
+"#;
+    assert_eq!(xml, expected);
+}
+
+#[test]
+fn test_indented_xml_converts_identically() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+    let doc = common::doc_from_xml(&input, "document-store");
+
+    let compact = get_fragment_xml_with(
+        doc.clone(),
+        "document-store".to_string(),
+        &XmlOptions::new().canonical(true),
+    );
+    assert_eq!(
+        get_fragment_xml_ref(&doc, "document-store", &XmlOptions::new().canonical(true)),
+        compact
+    );
+    let mut indented = Vec::new();
+    write_fragment_xml_io(
+        &doc,
+        "document-store",
+        &mut indented,
+        &XmlOptions::new().canonical(true).indent(4),
+    )
+    .unwrap();
+    let indented = String::from_utf8(indented).unwrap();
+
+    assert!(indented.lines().count() > 1);
+    assert_eq!(
+        convert_to_value(compact).unwrap(),
+        convert_to_value(indented).unwrap()
+    );
+}
+
+#[test]
+fn test_unrepresentable_text_and_names() {
+    let doc = Doc::new();
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    {
+        let mut txn = doc.transact_mut();
+        let group = fragment.push_back(&mut txn, XmlElementPrelim::empty("blockgroup"));
+        let container = group.push_back(&mut txn, XmlElementPrelim::empty("blockcontainer"));
+        container.insert_attribute(&mut txn, "id", "a\u{0}b");
+        let block = container.push_back(&mut txn, XmlElementPrelim::empty("my block"));
+        block.insert_attribute(&mut txn, "name", "\u{1b}[31m");
+        block.insert_attribute(&mut txn, "data:thing", "x");
+        block.insert_attribute(&mut txn, "xmlns", "y");
+
+        let text = block.push_back(&mut txn, XmlTextPrelim::new(""));
+        text.insert(&mut txn, 0, "bell\u{7}\u{FFFF}");
+        let bold = HashMap::from([("bold".into(), Any::Bool(true))]);
+        text.insert_with_attributes(&mut txn, 10, " ", bold);
+    }
+
+    let xml = get_fragment_xml(doc, "document-store".to_string());
+    let result = convert_to_value(xml).unwrap();
+    let block = &result[0];
+
+    assert_eq!(block["id"], json!("a\u{0}b"));
+    assert_eq!(block["type"], json!("my block"));
+    assert_eq!(block["props"]["name"], json!("\u{1b}[31m"));
+    assert_eq!(block["props"]["data:thing"], json!("x"));
+    assert_eq!(block["props"]["xmlns"], json!("y"));
+    assert_eq!(
+        block["content"],
+        json!([
+            { "type": "text", "text": "bell\u{7}\u{FFFF}", "styles": {} },
+            { "type": "text", "text": " ", "styles": { "bold": true } },
+        ])
+    );
+}
+
+#[test]
+fn test_serialize_deeply_nested_fragment() {
+    let doc = Doc::new();
+    let fragment = doc.get_or_insert_xml_fragment("document-store");
+    {
+        let mut txn = doc.transact_mut();
+        let mut elem = fragment.push_back(&mut txn, XmlElementPrelim::empty("blockgroup"));
+        for _ in 0..100_000 {
+            elem = elem.push_back(&mut txn, XmlElementPrelim::empty("blockgroup"));
+        }
+    }
+
+    let xml = get_fragment_xml(doc, "document-store".to_string());
+    assert_eq!(xml.matches("<blockgroup>").count(), 100_001);
+    assert!(xml.ends_with("</blockgroup></blockgroup>"));
+}
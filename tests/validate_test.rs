@@ -0,0 +1,139 @@
+use std::fs;
+
+use atuin_ydoc_convert::{convert_to_value_strict, validate, validate_with_limits, Error, Limits};
+
+#[test]
+fn test_validate_everything_bagel() {
+    let input = fs::read_to_string("tests/fixtures/everything_input.xml").unwrap();
+
+    assert_eq!(validate(input).unwrap(), vec![]);
+}
+
+#[test]
+fn test_validate_reports_all_violations() {
+    let input = r#"
+    <blockgroup>
+      <blockcontainer id="a" textColor="chartreuse">
+        <heading level="9">Title</heading>
+        <paragraph>Extra</paragraph>
+      </blockcontainer>
+      <paragraph>Not in a container</paragraph>
+      <blockcontainer id="a">
+        <paragraph>Some <bold>bold</bold><italic></italic> text</paragraph>
+        <blockcontainer id="b"><paragraph></paragraph></blockcontainer>
+      </blockcontainer>
+      <blockcontainer id="table">
+        <table>
+          <tableRow>
+            <tableCell colspan="2"><tableParagraph>Wide</tableParagraph></tableCell>
+          </tableRow>
+          <tableRow>
+            <tableCell><tableParagraph>One</tableParagraph></tableCell>
+          </tableRow>
+          <tableRow>
+            <tableCell rowspan="0"></tableCell>
+            <tableCell><tableParagraph>Two</tableParagraph></tableCell>
+          </tableRow>
+        </table>
+      </blockcontainer>
+      <blockcontainer id="image">
+        <image previewWidth="512" showPreview="true">Caption</image>
+      </blockcontainer>
+    </blockgroup>
+    "#;
+
+    let violations = validate(input.to_string()).unwrap();
+    let summary = violations
+        .iter()
+        .map(|v| (v.block_id.as_deref(), v.message.as_str()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        summary,
+        vec![
+            (
+                Some("a"),
+                r#"invalid textColor "chartreuse" on blockContainer"#
+            ),
+            (Some("a"), r#"invalid level "9" on heading"#),
+            (Some("a"), "expected blockGroup, found <paragraph>"),
+            (None, "expected blockContainer, found <paragraph>"),
+            (Some("a"), "duplicate block id a"),
+            (Some("a"), "italic must contain exactly one child, found 0"),
+            (Some("a"), "expected blockGroup, found <blockcontainer>"),
+            (Some("table"), "tableRow spans 1 columns, expected 2"),
+            (Some("table"), r#"invalid rowspan "0""#),
+            (
+                Some("table"),
+                "tableCell must contain exactly one tableParagraph"
+            ),
+            (
+                Some("image"),
+                "image blocks cannot have content, found text"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_convert_strict() {
+    let valid = r#"<blockGroup><blockContainer id="a"><paragraph>Text</paragraph></blockContainer></blockGroup>"#;
+    assert!(convert_to_value_strict(valid.to_string()).is_ok());
+
+    let invalid = r#"<blockGroup><paragraph>Text</paragraph></blockGroup>"#;
+    match convert_to_value_strict(invalid.to_string()) {
+        Err(Error::InvalidDocument(violations)) => assert_eq!(violations.len(), 1),
+        other => panic!("expected InvalidDocument, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_validate_deeply_nested() {
+    let xml = "<blockGroup>".repeat(100_000) + &"</blockGroup>".repeat(100_000);
+    assert!(matches!(
+        validate(xml),
+        Err(Error::DepthLimitExceeded { max_depth: 128, .. })
+    ));
+}
+
+#[test]
+fn test_validate_with_limits() {
+    let xml = "<blockGroup>".repeat(200) + &"</blockGroup>".repeat(200);
+    assert!(validate_with_limits(xml.clone(), &Limits::new().max_depth(200)).is_ok());
+    assert!(matches!(
+        validate_with_limits(xml.clone(), &Limits::new().max_depth(10)),
+        Err(Error::DepthLimitExceeded { max_depth: 10, .. })
+    ));
+    assert!(matches!(
+        validate_with_limits(xml, &Limits::new().max_depth(200).max_input_bytes(100)),
+        Err(Error::InputTooLarge { max_bytes: 100, .. })
+    ));
+}
+
+#[test]
+fn test_validate_huge_span() {
+    let xml = r#"
+    <blockGroup>
+      <blockContainer id="table">
+        <table>
+          <tableRow>
+            <tableCell colspan="4000000000" rowspan="1001"><tableParagraph>Wide</tableParagraph></tableCell>
+          </tableRow>
+        </table>
+      </blockContainer>
+    </blockGroup>
+    "#;
+
+    let violations = validate(xml.to_string()).unwrap();
+    let messages = violations
+        .iter()
+        .map(|v| v.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "colspan 4000000000 is more than 1000",
+            "rowspan 1001 is more than 1000"
+        ]
+    );
+}